@@ -0,0 +1,325 @@
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, Hasher},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use tokio::{net::UdpSocket, sync::oneshot, task::JoinHandle};
+
+use crate::pcap::{record, source_address, Capture};
+use crate::socket::{should_fail_over, Exchange, RetryPolicy};
+
+struct InFlight {
+    server: SocketAddr,
+    answer: oneshot::Sender<Vec<u8>>,
+}
+
+type Pending = Arc<Mutex<HashMap<u16, InFlight>>>;
+
+/// Random transaction IDs tried before searching for a free one in order.
+const RANDOM_PICKS: usize = 16;
+/// Bounds of the pause after a failed receive.
+const MIN_PAUSE: Duration = Duration::from_millis(1);
+const MAX_PAUSE: Duration = Duration::from_millis(500);
+
+/// Asynchronous UDP client which multiplexes any number of in-flight
+/// queries over one socket per address family.
+///
+/// Every query gets a transaction ID which is unique among the in-flight
+/// queries. A background task per socket reads all datagrams and hands
+/// each answer to the query with the matching ID and server address.
+pub struct AsyncUDPClient {
+    v4: Arc<UdpSocket>,
+    v6: Option<Arc<UdpSocket>>,
+    pending: Pending,
+    policy: RetryPolicy,
+    receivers: Vec<JoinHandle<()>>,
+    capture: Option<Capture>,
+}
+
+impl AsyncUDPClient {
+    pub async fn new(policy: RetryPolicy) -> Result<Self, String> {
+        let pending: Pending = Arc::new(Mutex::new(HashMap::new()));
+        let v4 = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+            .await
+            .map(Arc::new)
+            .map_err(|err| format!("Failed to initialize udp socket: {}", err))?;
+        // Hosts without IPv6 support can still query IPv4 servers.
+        let v6 = UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0))
+            .await
+            .map(Arc::new)
+            .ok();
+        let mut receivers = vec![tokio::spawn(receive(v4.clone(), pending.clone()))];
+        if let Some(v6) = &v6 {
+            receivers.push(tokio::spawn(receive(v6.clone(), pending.clone())));
+        }
+        Ok(Self {
+            v4,
+            v6,
+            pending,
+            policy,
+            receivers,
+            capture: None,
+        })
+    }
+
+    /// Records every query sent and answer received in `capture`.
+    pub fn with_capture(mut self, capture: Option<Capture>) -> Self {
+        self.capture = capture;
+        self
+    }
+
+    /// Sends `msg` to the first of `addrs` that answers, with the same
+    /// retry and failover rules as [`crate::socket::UDPClient`]. The answer
+    /// carries the transaction ID of `msg`.
+    pub async fn query(&self, msg: Vec<u8>, addrs: &[SocketAddr]) -> Result<Exchange, String> {
+        if msg.len() < 12 {
+            return Err(String::from("Query is too short to be a DNS message."));
+        }
+        if addrs.is_empty() {
+            return Err(String::from("No server addresses to send the query to."));
+        }
+        let mut last_err = String::new();
+        let mut last_answer: Option<Exchange> = None;
+        for attempt in 0..self.policy.attempts.max(1) {
+            let timeout = self.policy.timeout_for(attempt);
+            for addr in addrs {
+                match self.exchange(&msg, addr, timeout).await {
+                    Ok(ex) if !should_fail_over(&ex.answer) => return Ok(ex),
+                    Ok(ex) => last_answer = Some(ex),
+                    Err(err) => last_err = err,
+                }
+            }
+        }
+        match last_answer {
+            Some(ex) => Ok(ex),
+            None => Err(format!(
+                "No server answered after {} attempts: {}",
+                self.policy.attempts.max(1),
+                last_err
+            )),
+        }
+    }
+
+    async fn exchange(
+        &self,
+        msg: &[u8],
+        addr: &SocketAddr,
+        timeout: Duration,
+    ) -> Result<Exchange, String> {
+        let socket = match addr {
+            SocketAddr::V4(_) => &self.v4,
+            SocketAddr::V6(_) => match &self.v6 {
+                Some(socket) => socket,
+                None => return Err(String::from("IPv6 is not available.")),
+            },
+        };
+        let (tx, rx) = oneshot::channel();
+        let id = self.register(*addr, tx)?;
+        // Also released when the caller stops waiting for the answer.
+        let _registration = Registration {
+            pending: &self.pending,
+            id,
+        };
+        let mut wire = msg.to_vec();
+        wire[0..2].copy_from_slice(&id.to_be_bytes());
+
+        // The socket is not connected, so it can not tell the address it
+        // sends from.
+        let local = match (&self.capture, socket.local_addr()) {
+            (Some(_), Ok(local)) => source_address(local.port(), addr),
+            _ => SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0),
+        };
+        let sent = Instant::now();
+        match socket.send_to(&wire, addr).await {
+            Ok(_) => {
+                record(&self.capture, local, *addr, &wire);
+                match tokio::time::timeout(timeout, rx).await {
+                    Ok(Ok(mut answer)) => {
+                        record(&self.capture, *addr, local, &answer);
+                        answer[0..2].copy_from_slice(&msg[0..2]);
+                        Ok(Exchange {
+                            answer,
+                            server: *addr,
+                            rtt: sent.elapsed(),
+                        })
+                    }
+                    Ok(Err(_)) => Err(String::from("Receiving task stopped.")),
+                    Err(_) => Err(String::from("Timed out waiting for an answer.")),
+                }
+            }
+            Err(err) => Err(format!("Failed to send message over socket: {}", err)),
+        }
+    }
+
+    /// Reserves a random transaction ID which no other in-flight query uses.
+    /// After a few random picks the IDs are searched in order from a random
+    /// start, so a nearly exhausted ID space does not keep the lock for long.
+    fn register(
+        &self,
+        server: SocketAddr,
+        answer: oneshot::Sender<Vec<u8>>,
+    ) -> Result<u16, String> {
+        let mut pending = self.pending.lock().unwrap();
+        let start = random_id();
+        let id = (0..RANDOM_PICKS)
+            .map(|_| random_id())
+            .chain((0..=u16::MAX).map(|offset| start.wrapping_add(offset)))
+            .find(|id| !pending.contains_key(id))
+            .ok_or_else(|| String::from("All transaction IDs are in use."))?;
+        pending.insert(id, InFlight { server, answer });
+        Ok(id)
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.pending.lock().unwrap().len()
+    }
+}
+
+/// The transaction ID of a query in flight, freed when dropped.
+struct Registration<'a> {
+    pending: &'a Pending,
+    id: u16,
+}
+
+impl Drop for Registration<'_> {
+    fn drop(&mut self) {
+        self.pending
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&self.id);
+    }
+}
+
+impl Drop for AsyncUDPClient {
+    fn drop(&mut self) {
+        for receiver in self.receivers.iter() {
+            receiver.abort();
+        }
+    }
+}
+
+/// Hands the datagrams received on `socket` to the queries waiting for
+/// them. Receive errors, e.g. an ICMP port unreachable reported for an
+/// earlier query, are retried after a pause which grows while they last.
+async fn receive(socket: Arc<UdpSocket>, pending: Pending) {
+    let mut buf = vec![0; 4096];
+    let mut pause = Duration::ZERO;
+    loop {
+        let (length, src) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(_) => {
+                pause = (pause * 2).clamp(MIN_PAUSE, MAX_PAUSE);
+                tokio::time::sleep(pause).await;
+                continue;
+            }
+        };
+        pause = Duration::ZERO;
+        if length < 12 {
+            continue;
+        }
+        let id = u16::from_be_bytes([buf[0], buf[1]]);
+        let mut pending = pending.lock().unwrap();
+        // Answers from other addresses than the one asked are spoofed or late.
+        if pending.get(&id).map(|q| q.server == src) == Some(true) {
+            let query = pending.remove(&id).unwrap();
+            let _ = query.answer.send(buf[..length].to_vec());
+        }
+    }
+}
+
+fn random_id() -> u16 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u8(0);
+    hasher.finish() as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use dns::record::RecordType;
+    use parsing::{byte_stream_parser::ByteStreamParser, Query};
+
+    use super::*;
+    use crate::test_util::{response_to, spawn_server};
+
+    /// Answers every batch of `batch` queries in reverse order of arrival.
+    fn reversing_server(batch: usize) -> SocketAddr {
+        let mut received = vec![];
+        spawn_server(move |query, src| {
+            received.push((response_to(query), src));
+            if received.len() == batch {
+                received.drain(..).rev().collect()
+            } else {
+                vec![]
+            }
+        })
+    }
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy::new(1, Duration::from_secs(2), 2)
+    }
+
+    #[tokio::test]
+    async fn test_multiplexed_queries() {
+        let server = reversing_server(50);
+        let client = Arc::new(AsyncUDPClient::new(policy()).await.unwrap());
+        let mut tasks = vec![];
+        for n in 0..50 {
+            let client = client.clone();
+            tasks.push(tokio::spawn(async move {
+                let name = format!("host{}.example.com", n);
+                let msg = Query::new(name, RecordType::A, true).to_bytes();
+                let ex = client.query(msg.to_vec(), &[server]).await.unwrap();
+                assert_eq!(ex.server, server);
+                (msg, ex.answer)
+            }));
+        }
+        for task in tasks {
+            let (msg, answer) = task.await.unwrap();
+            assert!(ByteStreamParser::new(&answer).parse_response().is_ok());
+            assert_eq!(answer[0..2], msg[0..2]);
+            assert_eq!(answer[12..], msg[12..]);
+        }
+        assert_eq!(client.in_flight(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_query_timeout() {
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client = AsyncUDPClient::new(RetryPolicy::new(2, Duration::from_millis(50), 2))
+            .await
+            .unwrap();
+        let msg = Query::new("example.com".to_string(), RecordType::A, true).to_bytes();
+        let res = client.query(msg, &[silent.local_addr().unwrap()]).await;
+        assert!(res.is_err());
+        assert_eq!(client.in_flight(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_abandoned_query_released() {
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client = AsyncUDPClient::new(policy()).await.unwrap();
+        let msg = Query::new("example.com".to_string(), RecordType::A, true).to_bytes();
+        let addrs = [silent.local_addr().unwrap()];
+        let query = client.query(msg, &addrs);
+        assert!(tokio::time::timeout(Duration::from_millis(50), query)
+            .await
+            .is_err());
+        assert_eq!(client.in_flight(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_register_exhausted_ids() {
+        let client = AsyncUDPClient::new(policy()).await.unwrap();
+        let server: SocketAddr = "127.0.0.1:53".parse().unwrap();
+        for _ in 0..=u16::MAX {
+            client.register(server, oneshot::channel().0).unwrap();
+        }
+        assert_eq!(client.in_flight(), 1 << 16);
+        assert!(client.register(server, oneshot::channel().0).is_err());
+    }
+}
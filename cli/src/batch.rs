@@ -0,0 +1,512 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, Read},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use dns::{header::Header, record::RecordType};
+use parsing::{byte_stream_parser::ByteStreamParser, Edns, Query};
+use tokio::sync::{mpsc, Semaphore};
+
+use crate::{
+    async_client::AsyncUDPClient,
+    cache::{failure, lock, upstream, Cache, CacheKey},
+    socket::Exchange,
+};
+
+/// One lookup of a batch file in the form `domain [TYPE] [@server]`.
+#[derive(Clone)]
+pub struct BatchLine {
+    pub number: usize,
+    pub name: String,
+    pub r_type: Option<RecordType>,
+    pub server: Option<String>,
+}
+
+impl BatchLine {
+    /// Parses a line of a batch file. Empty lines and comments starting
+    /// with `#` yield `None`.
+    pub fn parse(number: usize, line: &str) -> Option<Result<Self, String>> {
+        let line = match line.find('#') {
+            Some(idx) => &line[..idx],
+            None => line,
+        };
+        let mut parts = line.split_whitespace();
+        let name = parts.next()?.to_string();
+        let mut r_type: Option<RecordType> = None;
+        let mut server: Option<String> = None;
+        for part in parts {
+            if let Some(srv) = part.strip_prefix('@') {
+                server = Some(srv.to_string());
+                continue;
+            }
+            match RecordType::try_from_string(part) {
+                Ok(t) => r_type = Some(t),
+                Err(err) => return Some(Err(format!("line {}: {}", number, err))),
+            }
+        }
+        Some(Ok(Self {
+            number,
+            name,
+            r_type,
+            server,
+        }))
+    }
+}
+
+/// A line of a batch file which can't be looked up, the message starts with
+/// the line number.
+pub struct BatchError {
+    pub number: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for BatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// Reads all lookups from `path`, `-` reads from stdin. Lines which can't be
+/// parsed are kept as errors, so the rest of the batch can still run.
+pub fn read_batch(path: &str) -> Result<Vec<Result<BatchLine, BatchError>>, String> {
+    let mut content = String::new();
+    let read = if path == "-" {
+        io::stdin().read_to_string(&mut content).map(|_| ())
+    } else {
+        fs::read_to_string(path).map(|c| content = c)
+    };
+    if let Err(err) = read {
+        return Err(format!("Failed to read batch {}: {}", path, err));
+    }
+    let lines = content
+        .lines()
+        .enumerate()
+        .filter_map(|(idx, line)| {
+            BatchLine::parse(idx + 1, line).map(|parsed| {
+                parsed.map_err(|message| BatchError {
+                    number: idx + 1,
+                    message,
+                })
+            })
+        })
+        .collect();
+    Ok(lines)
+}
+
+pub enum OutputOrder {
+    Input,
+    Completion,
+}
+
+impl OutputOrder {
+    pub fn from_string(order: &str) -> Result<Self, String> {
+        match order {
+            "input" => Ok(OutputOrder::Input),
+            "completion" => Ok(OutputOrder::Completion),
+            _ => Err(format!("Unknown output order {}", order)),
+        }
+    }
+}
+
+/// A fully resolved batch lookup: the names to try in order, the record
+/// type, the server addresses to ask and the EDNS options of the server.
+pub struct Lookup {
+    pub line: BatchLine,
+    pub names: Vec<String>,
+    pub r_type: RecordType,
+    pub addrs: Vec<SocketAddr>,
+    pub rd: bool,
+    pub edns: Option<Edns>,
+}
+
+/// The answer of a batch lookup with the server which sent it and the round
+/// trip time, both `None` when it came from the cache.
+pub struct BatchAnswer {
+    pub answer: Vec<u8>,
+    pub server: Option<SocketAddr>,
+    pub rtt: Option<Duration>,
+}
+
+impl BatchAnswer {
+    fn cached(answer: Vec<u8>) -> Self {
+        Self {
+            answer,
+            server: None,
+            rtt: None,
+        }
+    }
+}
+
+impl From<Exchange> for BatchAnswer {
+    fn from(ex: Exchange) -> Self {
+        Self {
+            answer: ex.answer,
+            server: Some(ex.server),
+            rtt: Some(ex.rtt),
+        }
+    }
+}
+
+/// Runs all lookups with at most `concurrency` of them in flight and calls
+/// `emit` with the answer of every lookup in the requested order. Answers
+/// found in `cache` are not queried again, popular ones about to expire
+/// are refreshed in the background.
+pub async fn run_batch<F>(
+    lookups: Vec<Lookup>,
+    client: Arc<AsyncUDPClient>,
+    cache: Arc<Mutex<Cache>>,
+    concurrency: usize,
+    order: OutputOrder,
+    mut emit: F,
+) where
+    F: FnMut(&Lookup, Result<BatchAnswer, String>),
+{
+    let lookups = Arc::new(lookups);
+    let permits = Arc::new(Semaphore::new(concurrency.max(1)));
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    for idx in 0..lookups.len() {
+        let (lookups, client, cache, permits, tx) = (
+            lookups.clone(),
+            client.clone(),
+            cache.clone(),
+            permits.clone(),
+            tx.clone(),
+        );
+        tokio::spawn(async move {
+            let _permit = permits.acquire().await.unwrap();
+            // A lookup which panics still gets an answer, otherwise the
+            // lines after it would wait for it forever in input order.
+            let task = {
+                let (lookups, client, cache) = (lookups.clone(), client.clone(), cache.clone());
+                tokio::spawn(async move { resolve(&lookups[idx], &client, &cache).await })
+            };
+            let (answer, prefetches) = task
+                .await
+                .unwrap_or_else(|err| (Err(format!("Lookup failed: {}", err)), vec![]));
+            let _ = tx.send((idx, answer));
+            // Popular answers are refreshed once they were printed. The batch
+            // ends when every sender is gone, so it waits for the refreshes
+            // instead of dropping them with the runtime.
+            for prefetch in prefetches {
+                refresh(&client, &cache, prefetch, &lookups[idx].addrs).await;
+            }
+        });
+    }
+    drop(tx);
+
+    let mut buffered: BTreeMap<usize, Result<BatchAnswer, String>> = BTreeMap::new();
+    let mut next: usize = 0;
+    while let Some((idx, answer)) = rx.recv().await {
+        match order {
+            OutputOrder::Completion => emit(&lookups[idx], answer),
+            OutputOrder::Input => {
+                buffered.insert(idx, answer);
+                while let Some(answer) = buffered.remove(&next) {
+                    emit(&lookups[next], answer);
+                    next += 1;
+                }
+            }
+        }
+    }
+}
+
+/// A popular cached answer which is about to expire: the query to refresh
+/// it with and the entry which answered.
+struct Prefetch {
+    msg: Vec<u8>,
+    key: CacheKey,
+}
+
+/// Tries the names of a lookup until one exists and has records. Also
+/// returns the cached answers served on the way which should be refreshed.
+async fn resolve(
+    lookup: &Lookup,
+    client: &AsyncUDPClient,
+    cache: &Mutex<Cache>,
+) -> (Result<BatchAnswer, String>, Vec<Prefetch>) {
+    let server = upstream(&lookup.addrs);
+    let mut prefetches: Vec<Prefetch> = vec![];
+    let mut last: Result<BatchAnswer, String> = Err(String::from("No names to look up."));
+    for name in lookup.names.iter() {
+        let qry = Query::new(name.clone(), lookup.r_type.clone(), lookup.rd).with_edns(lookup.edns);
+        let hit = lock(cache).lookup(&server, name, &lookup.r_type, 1);
+        last = match hit {
+            Some(hit) => {
+                if hit.prefetch {
+                    prefetches.push(Prefetch {
+                        msg: qry.to_bytes(),
+                        key: hit.key.clone(),
+                    });
+                }
+                hit.to_response(&qry)
+                    .map(|resp| BatchAnswer::cached(resp.get_bytes()))
+            }
+            None => {
+                let answer = query(client, cache, qry.to_bytes(), &lookup.addrs).await;
+                let parsed = answer
+                    .as_ref()
+                    .map_err(|err| err.clone())
+                    .and_then(|ex| ByteStreamParser::new(&ex.answer).parse_response());
+                let stale = match failure(&parsed) {
+                    Some(_) => lock(cache).lookup_stale(&server, name, &lookup.r_type, 1),
+                    None => None,
+                };
+                match stale {
+                    Some(stale) => stale
+                        .to_response(&qry)
+                        .map(|resp| BatchAnswer::cached(resp.get_bytes())),
+                    None => answer.map(BatchAnswer::from),
+                }
+            }
+        };
+        if let Ok(found) = &last {
+            let header = Header::from_bytes(found.answer.to_vec());
+            if !header.rcode().is_err() && header.an_count() > 0 {
+                break;
+            }
+        }
+    }
+    (last, prefetches)
+}
+
+/// Refreshes a prefetched answer. When no usable response arrives, the
+/// next hit asks for a refresh again.
+async fn refresh(
+    client: &AsyncUDPClient,
+    cache: &Mutex<Cache>,
+    prefetch: Prefetch,
+    addrs: &[SocketAddr],
+) {
+    let parsed = query(client, cache, prefetch.msg, addrs)
+        .await
+        .and_then(|ex| ByteStreamParser::new(&ex.answer).parse_response());
+    if failure(&parsed).is_some() {
+        lock(cache).prefetch_failed(&prefetch.key);
+    }
+}
+
+/// Sends `msg` and adds the response to `cache` for the servers at `addrs`.
+async fn query(
+    client: &AsyncUDPClient,
+    cache: &Mutex<Cache>,
+    msg: Vec<u8>,
+    addrs: &[SocketAddr],
+) -> Result<Exchange, String> {
+    let answer = client.query(msg, addrs).await;
+    if let Ok(Ok(resp)) = answer
+        .as_ref()
+        .map(|ex| ByteStreamParser::new(&ex.answer).parse_response())
+    {
+        lock(cache).insert_response(&upstream(addrs), &resp);
+    }
+    answer
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use parsing::Response;
+
+    use crate::socket::RetryPolicy;
+    use crate::test_util::{record, response_to, spawn_server};
+
+    use super::*;
+
+    #[test]
+    fn test_parse_line() {
+        let line = BatchLine::parse(3, "example.com mx @1.1.1.1 # mail")
+            .unwrap()
+            .unwrap();
+        assert_eq!(line.number, 3);
+        assert_eq!(line.name, "example.com");
+        assert!(matches!(line.r_type, Some(RecordType::MX)));
+        assert_eq!(line.server, Some("1.1.1.1".to_string()));
+
+        let line = BatchLine::parse(4, "example.com").unwrap().unwrap();
+        assert!(line.r_type.is_none());
+        assert!(line.server.is_none());
+
+        assert!(BatchLine::parse(5, "  # comment").is_none());
+        assert!(BatchLine::parse(6, "example.com SRV").unwrap().is_err());
+    }
+
+    #[test]
+    fn test_read_batch_keeps_bad_lines() {
+        let path = std::env::temp_dir().join("askrs_test_batch.txt");
+        fs::write(&path, "a.example\n\nb.example SRV\nc.example MX\n").unwrap();
+        let lines = read_batch(path.to_str().unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].as_ref().ok().unwrap().name, "a.example");
+        let err = lines[1].as_ref().err().unwrap();
+        assert_eq!(err.number, 3);
+        assert!(err.to_string().starts_with("line 3:"));
+        assert_eq!(lines[2].as_ref().ok().unwrap().number, 4);
+    }
+
+    /// Answers the first `delay_first` queries only after all others.
+    fn delaying_server(delay_first: usize, total: usize) -> SocketAddr {
+        let mut held = vec![];
+        let mut seen = 0;
+        spawn_server(move |query, src| {
+            seen += 1;
+            let mut sent = vec![];
+            if seen <= delay_first {
+                held.push((response_to(query), src));
+            } else {
+                sent.push((response_to(query), src));
+            }
+            if seen == total {
+                sent.append(&mut held);
+            }
+            sent
+        })
+    }
+
+    fn lookups(addr: SocketAddr, count: usize) -> Vec<Lookup> {
+        (0..count)
+            .map(|n| Lookup {
+                line: BatchLine::parse(n + 1, &format!("host{}.example.com", n))
+                    .unwrap()
+                    .unwrap(),
+                names: vec![format!("host{}.example.com", n)],
+                r_type: RecordType::A,
+                addrs: vec![addr],
+                rd: true,
+                edns: None,
+            })
+            .collect()
+    }
+
+    async fn run(order: OutputOrder) -> Vec<usize> {
+        let addr = delaying_server(1, 5);
+        let policy = RetryPolicy::new(1, Duration::from_secs(2), 2);
+        let client = Arc::new(AsyncUDPClient::new(policy).await.unwrap());
+        let mut emitted = vec![];
+        let cache = Arc::new(Mutex::new(Cache::new(0)));
+        run_batch(
+            lookups(addr, 5),
+            client,
+            cache,
+            5,
+            order,
+            |lookup, answer| {
+                assert!(answer.is_ok());
+                emitted.push(lookup.line.number);
+            },
+        )
+        .await;
+        emitted
+    }
+
+    /// Answers every query with one A record and counts the queries.
+    fn answering_server(queries: Arc<AtomicUsize>) -> SocketAddr {
+        spawn_server(move |query, src| {
+            queries.fetch_add(1, Ordering::SeqCst);
+            let mut answer = response_to(query);
+            answer[7] = 1;
+            answer.extend([0xC0, 12, 0, 1, 0, 1, 0, 0, 1, 44, 0, 4, 192, 0, 2, 1]);
+            vec![(answer, src)]
+        })
+    }
+
+    #[tokio::test]
+    async fn test_cached_lookups() {
+        let queries = Arc::new(AtomicUsize::new(0));
+        let addr = answering_server(queries.clone());
+        let policy = RetryPolicy::new(1, Duration::from_secs(2), 2);
+        let client = Arc::new(AsyncUDPClient::new(policy).await.unwrap());
+        let cache = Arc::new(Mutex::new(Cache::new(10)));
+        let repeated: Vec<Lookup> = (0..4)
+            .map(|n| {
+                let mut lookup = lookups(addr, 1).remove(0);
+                lookup.line.number = n + 1;
+                lookup
+            })
+            .collect();
+        let mut ttls = vec![];
+        let mut servers = vec![];
+        run_batch(
+            repeated,
+            client,
+            cache,
+            1,
+            OutputOrder::Input,
+            |_, answer| {
+                let answer = answer.unwrap();
+                let resp = ByteStreamParser::new(&answer.answer)
+                    .parse_response()
+                    .unwrap();
+                ttls.push(resp.answers()[0].get_ttl());
+                servers.push(answer.server);
+            },
+        )
+        .await;
+        assert_eq!(queries.load(Ordering::SeqCst), 1);
+        assert_eq!(ttls.len(), 4);
+        assert!(ttls.iter().all(|ttl| *ttl <= 300 && *ttl >= 299));
+        assert_eq!(servers, vec![Some(addr), None, None, None]);
+    }
+
+    #[tokio::test]
+    async fn test_prefetch_awaited() {
+        let queries = Arc::new(AtomicUsize::new(0));
+        let addr = answering_server(queries.clone());
+        let policy = RetryPolicy::new(1, Duration::from_secs(2), 2);
+        let client = Arc::new(AsyncUDPClient::new(policy).await.unwrap());
+        let cache = Arc::new(Mutex::new(Cache::new(10)));
+        let qry = Query::new("host0.example.com".to_string(), RecordType::A, true);
+        let answers = vec![record(
+            "host0.example.com",
+            RecordType::A,
+            100,
+            vec![192, 0, 2, 1],
+        )];
+        let resp = Response::synthesize(&qry, answers, false).unwrap();
+        lock(&cache).insert_response_aged(&upstream(&[addr]), &resp, Duration::from_secs(95));
+        let repeated: Vec<Lookup> = (0..4)
+            .map(|n| {
+                let mut lookup = lookups(addr, 1).remove(0);
+                lookup.line.number = n + 1;
+                lookup
+            })
+            .collect();
+        let mut servers = vec![];
+        run_batch(
+            repeated,
+            client,
+            cache.clone(),
+            1,
+            OutputOrder::Input,
+            |_, answer| servers.push(answer.unwrap().server),
+        )
+        .await;
+        // The third hit is refreshed, which is done once the batch is.
+        assert_eq!(servers, vec![None, None, None, None]);
+        assert_eq!(queries.load(Ordering::SeqCst), 1);
+        let refreshed = lock(&cache)
+            .get(&upstream(&[addr]), "host0.example.com", &RecordType::A, 1)
+            .unwrap();
+        assert!(refreshed[0].get_ttl() > 100);
+    }
+
+    #[tokio::test]
+    async fn test_input_order() {
+        assert_eq!(run(OutputOrder::Input).await, vec![1, 2, 3, 4, 5]);
+    }
+
+    #[tokio::test]
+    async fn test_completion_order() {
+        let emitted = run(OutputOrder::Completion).await;
+        assert_eq!(emitted.len(), 5);
+        assert_eq!(emitted[4], 1);
+    }
+}
@@ -0,0 +1,906 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::SocketAddr,
+    sync::{Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
+use dns::{
+    answer::Answer,
+    header_flags::RCODE,
+    name::Name,
+    record::{RecordType, SOARecord},
+};
+use parsing::{Query, Response};
+
+use crate::{chain::DEFAULT_MAX_CHAIN, iterative::normalize};
+
+/// Entries kept by default before the least recently used one is evicted.
+pub const DEFAULT_CACHE_SIZE: usize = 1000;
+
+/// Type code of the keys of NXDOMAIN entries, which deny every type of a
+/// name. No record type uses it.
+const NXDOMAIN_TYPE: u16 = 0;
+
+const RCODE_NXDOMAIN: u8 = 3;
+
+/// Seconds expired entries may be served for when no server answers.
+pub const DEFAULT_MAX_STALE: u64 = 86400;
+
+/// TTL of records served after they expired, as recommended by RFC 8767.
+pub const STALE_TTL: u32 = 30;
+
+/// Hits after which an entry counts as popular and is refreshed before it
+/// expires.
+const PREFETCH_HITS: u32 = 3;
+
+/// The upstream of answers found by iterative resolution, which come from
+/// the authoritative servers.
+pub const ITERATIVE: &str = "iterative";
+
+/// The upstream the answers of the servers at `addrs` are cached for.
+/// Servers may answer differently for the same name (split-horizon DNS),
+/// so an answer is only served again for the servers which sent it.
+pub fn upstream(addrs: &[SocketAddr]) -> String {
+    addrs
+        .iter()
+        .map(|addr| addr.to_string())
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    pub server: String,
+    pub name: String,
+    pub r_type: u16,
+    pub class: u16,
+}
+
+impl CacheKey {
+    pub fn new(server: &str, name: &str, r_type: &RecordType, class: u16) -> Self {
+        Self {
+            server: server.to_string(),
+            name: normalize(name),
+            r_type: r_type.code(),
+            class,
+        }
+    }
+
+    fn nxdomain(server: &str, name: &str, class: u16) -> Self {
+        Self {
+            server: server.to_string(),
+            name: normalize(name),
+            r_type: NXDOMAIN_TYPE,
+            class,
+        }
+    }
+}
+
+/// An RRset, or for negative entries no records and the SOA record of
+/// the zone which denied them.
+struct CacheEntry {
+    answers: Vec<Answer>,
+    soa: Vec<Answer>,
+    stored: Instant,
+    expires: Instant,
+    used: u64,
+    hits: u32,
+    prefetching: bool,
+}
+
+impl CacheEntry {
+    fn new(answers: Vec<Answer>, soa: Vec<Answer>, now: Instant, ttl: u32) -> Self {
+        Self {
+            answers,
+            soa,
+            stored: now,
+            expires: now + Duration::from_secs(ttl as u64),
+            used: 0,
+            hits: 0,
+            prefetching: false,
+        }
+    }
+
+    /// Popular entries in the last tenth of their TTL are refreshed early,
+    /// but only once.
+    fn wants_prefetch(&mut self, now: Instant) -> bool {
+        let ttl = self.expires.duration_since(self.stored);
+        if self.prefetching || self.hits < PREFETCH_HITS || self.expires - now > ttl / 10 {
+            return false;
+        }
+        self.prefetching = true;
+        true
+    }
+}
+
+/// Records served from the cache. Negative answers carry the SOA record
+/// which is returned in the authority section, `nxdomain` tells NXDOMAIN
+/// from NODATA. `prefetch` asks the caller to refresh the answer as it
+/// is popular and about to expire, `key` is the entry which answered.
+pub struct CachedAnswer {
+    pub answers: Vec<Answer>,
+    pub soa: Vec<Answer>,
+    pub nxdomain: bool,
+    pub prefetch: bool,
+    pub key: CacheKey,
+}
+
+impl CachedAnswer {
+    pub fn to_response(&self, query: &Query) -> Result<Response, String> {
+        let rcode = if self.nxdomain { RCODE_NXDOMAIN } else { 0 };
+        Response::synthesize_with(
+            query,
+            rcode,
+            self.answers.to_vec(),
+            self.soa.to_vec(),
+            false,
+        )
+    }
+}
+
+/// In-memory cache of RRsets keyed by the upstream which sent them, see
+/// [`upstream`], owner name, type and class.
+///
+/// An RRset expires with the lowest TTL of its records. Retrieved records
+/// carry the TTL which is left. Once `max_entries` RRsets are stored, the
+/// least recently used one makes room for the next.
+///
+/// NXDOMAIN and NODATA answers are cached as well (RFC 2308) for the
+/// lower of the TTL and the MINIMUM field of the SOA record they carry.
+///
+/// Expired entries are kept for `max_stale` to answer with when no server
+/// can be reached (RFC 8767), see [`Cache::lookup_stale`].
+pub struct Cache {
+    entries: HashMap<CacheKey, CacheEntry>,
+    recency: BTreeMap<u64, CacheKey>,
+    max_entries: usize,
+    max_stale: Duration,
+    tick: u64,
+}
+
+impl Cache {
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            max_entries,
+            max_stale: Duration::ZERO,
+            tick: 0,
+        }
+    }
+
+    /// Keeps expired entries for `max_stale` to serve them stale.
+    pub fn with_max_stale(mut self, max_stale: Duration) -> Self {
+        self.max_stale = max_stale;
+        self
+    }
+
+    pub fn max_stale(&self) -> Duration {
+        self.max_stale
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Stores the RRsets of the answer section of `resp` and, for negative
+    /// answers, the denial of the name the answers lead to. Records whose
+    /// names can not be read are skipped.
+    pub fn insert_response(&mut self, server: &str, resp: &Response) {
+        self.insert_response_at(server, resp, Instant::now())
+    }
+
+    /// Like [`Cache::insert_response`] for a response received `age` ago.
+    /// Its records expire as if they had been cached since and those which
+    /// did are only served stale for what is left of `max_stale`.
+    pub fn insert_response_aged(&mut self, server: &str, resp: &Response, age: Duration) {
+        // Some platforms can not go back in time further than the boot.
+        if let Some(stored) = Instant::now().checked_sub(age) {
+            self.insert_response_at(server, resp, stored)
+        }
+    }
+
+    fn insert_response_at(&mut self, server: &str, resp: &Response, now: Instant) {
+        let src = resp.get_bytes();
+        let answers: Vec<Answer> = resp
+            .answers()
+            .iter()
+            .filter_map(|an| an.expand(src.to_vec()).ok())
+            .collect();
+        self.insert_at(server, &answers, now);
+
+        let question = match resp.question() {
+            Some(question) => question,
+            None => return,
+        };
+        let r_type = question.get_type();
+        // Questions are always of class IN.
+        let class = 1;
+        let name = match question.get_name().get_string() {
+            Ok(name) => final_name(&answers, &name),
+            Err(_) => return,
+        };
+        let nxdomain = matches!(resp.header().rcode(), RCODE::NAMEERR);
+        let nodata = !resp.header().rcode().is_err()
+            && !answers
+                .iter()
+                .any(|an| an.get_type().code() == r_type.code() && owner(an) == name);
+        if !nxdomain && !nodata {
+            return;
+        }
+        let soa = resp
+            .authority()
+            .iter()
+            .find(|ns| matches!(ns.get_type(), RecordType::SOA))
+            .and_then(|soa| soa.expand(src.to_vec()).ok());
+        let (soa, minimum) = match soa {
+            Some(soa) => match SOARecord::from_bytes(soa.get_data().to_vec(), vec![], 0) {
+                Ok(soa_data) => (soa, soa_data.get_minimum()),
+                Err(_) => return,
+            },
+            None => return,
+        };
+        let ttl = soa.get_ttl().min(minimum);
+        if ttl == 0 || self.max_entries == 0 {
+            return;
+        }
+        let key = if nxdomain {
+            CacheKey::nxdomain(server, &name, class)
+        } else {
+            CacheKey::new(server, &name, r_type, class)
+        };
+        self.put(key, CacheEntry::new(vec![], vec![soa], now, ttl));
+    }
+
+    /// Stores `answers` grouped into RRsets. The records must not contain
+    /// compression pointers, see [`Answer::expand`].
+    pub fn insert(&mut self, server: &str, answers: &[Answer]) {
+        self.insert_at(server, answers, Instant::now())
+    }
+
+    fn insert_at(&mut self, server: &str, answers: &[Answer], now: Instant) {
+        let mut rrsets: Vec<(CacheKey, Vec<Answer>)> = vec![];
+        for an in answers.iter() {
+            let key = CacheKey::new(server, &owner(an), an.get_type(), an.get_class());
+            match rrsets.iter_mut().find(|(k, _)| k == &key) {
+                Some((_, rrset)) => rrset.push(an.clone()),
+                None => rrsets.push((key, vec![an.clone()])),
+            }
+        }
+        for (key, rrset) in rrsets {
+            let ttl = rrset.iter().map(|an| an.get_ttl()).min().unwrap_or(0);
+            if ttl == 0 || self.max_entries == 0 {
+                continue;
+            }
+            self.put(key, CacheEntry::new(rrset, vec![], now, ttl));
+        }
+    }
+
+    fn put(&mut self, key: CacheKey, mut entry: CacheEntry) {
+        if let Some(old) = self.entries.remove(&key) {
+            self.recency.remove(&old.used);
+        }
+        while self.entries.len() >= self.max_entries {
+            match self.recency.pop_first() {
+                Some((_, oldest)) => self.entries.remove(&oldest),
+                None => break,
+            };
+        }
+        self.tick += 1;
+        entry.used = self.tick;
+        self.recency.insert(self.tick, key.clone());
+        self.entries.insert(key, entry);
+    }
+
+    /// The RRset of `name`, `r_type` and `class` sent by `server` with the
+    /// TTLs reduced by the time it spent in the cache, `None` if missing,
+    /// expired or negative.
+    pub fn get(
+        &mut self,
+        server: &str,
+        name: &str,
+        r_type: &RecordType,
+        class: u16,
+    ) -> Option<Vec<Answer>> {
+        let key = CacheKey::new(server, name, r_type, class);
+        let (answers, _) = self.get_at(&key, Instant::now())?;
+        if answers.is_empty() {
+            None
+        } else {
+            Some(answers)
+        }
+    }
+
+    /// The records and SOA of an entry with the TTLs which are left.
+    fn get_at(&mut self, key: &CacheKey, now: Instant) -> Option<(Vec<Answer>, Vec<Answer>)> {
+        let (answers, soa, _) = self.entry_at(key, now, false)?;
+        Some((answers, soa))
+    }
+
+    /// Like [`Cache::get_at`], with `stale` an entry which expired less
+    /// than `max_stale` ago is returned with a TTL of [`STALE_TTL`]. The
+    /// last value tells whether the entry should be prefetched.
+    fn entry_at(
+        &mut self,
+        key: &CacheKey,
+        now: Instant,
+        stale: bool,
+    ) -> Option<(Vec<Answer>, Vec<Answer>, bool)> {
+        let max_stale = self.max_stale;
+        let entry = self.entries.get_mut(key)?;
+        let expired = entry.expires <= now;
+        if expired && entry.expires + max_stale <= now {
+            let used = entry.used;
+            self.entries.remove(key);
+            self.recency.remove(&used);
+            return None;
+        }
+        if expired && !stale {
+            return None;
+        }
+        self.recency.remove(&entry.used);
+        self.tick += 1;
+        entry.used = self.tick;
+        self.recency.insert(self.tick, key.clone());
+        entry.hits = entry.hits.saturating_add(1);
+
+        let elapsed = now.duration_since(entry.stored).as_secs() as u32;
+        let age = |records: &[Answer]| -> Vec<Answer> {
+            let mut records = records.to_vec();
+            for an in records.iter_mut() {
+                let ttl = if expired {
+                    STALE_TTL
+                } else {
+                    an.get_ttl().saturating_sub(elapsed)
+                };
+                an.set_ttl(ttl);
+            }
+            records
+        };
+        let prefetch = !expired && entry.wants_prefetch(now);
+        Some((age(&entry.answers), age(&entry.soa), prefetch))
+    }
+
+    /// Lets the entry of `key` be prefetched again after its refresh failed.
+    pub fn prefetch_failed(&mut self, key: &CacheKey) {
+        if let Some(entry) = self.entries.get_mut(key) {
+            entry.prefetching = false;
+        }
+    }
+
+    /// What `server` would answer with: the RRset asked for or the cached
+    /// denial, preceded by the CNAMEs leading to it. `None` unless the
+    /// whole chain is cached.
+    pub fn lookup(
+        &mut self,
+        server: &str,
+        name: &str,
+        r_type: &RecordType,
+        class: u16,
+    ) -> Option<CachedAnswer> {
+        self.lookup_at(server, name, r_type, class, Instant::now())
+    }
+
+    /// Like [`Cache::lookup`], but records which expired less than
+    /// `max_stale` ago are served with a TTL of [`STALE_TTL`]. Meant for
+    /// when every server failed to answer (RFC 8767).
+    pub fn lookup_stale(
+        &mut self,
+        server: &str,
+        name: &str,
+        r_type: &RecordType,
+        class: u16,
+    ) -> Option<CachedAnswer> {
+        self.lookup_with(server, name, r_type, class, Instant::now(), true)
+    }
+
+    fn lookup_at(
+        &mut self,
+        server: &str,
+        name: &str,
+        r_type: &RecordType,
+        class: u16,
+        now: Instant,
+    ) -> Option<CachedAnswer> {
+        self.lookup_with(server, name, r_type, class, now, false)
+    }
+
+    fn lookup_with(
+        &mut self,
+        server: &str,
+        name: &str,
+        r_type: &RecordType,
+        class: u16,
+        now: Instant,
+        stale: bool,
+    ) -> Option<CachedAnswer> {
+        let mut answers: Vec<Answer> = vec![];
+        let mut current = normalize(name);
+        for _ in 0..=DEFAULT_MAX_CHAIN {
+            let key = CacheKey::nxdomain(server, &current, class);
+            if let Some((_, soa, prefetch)) = self.entry_at(&key, now, stale) {
+                return Some(CachedAnswer {
+                    answers,
+                    soa,
+                    nxdomain: true,
+                    prefetch,
+                    key,
+                });
+            }
+            let key = CacheKey::new(server, &current, r_type, class);
+            if let Some((rrset, soa, prefetch)) = self.entry_at(&key, now, stale) {
+                answers.extend(rrset);
+                return Some(CachedAnswer {
+                    answers,
+                    soa,
+                    nxdomain: false,
+                    prefetch,
+                    key,
+                });
+            }
+            if matches!(r_type, RecordType::CNAME) {
+                return None;
+            }
+            let key = CacheKey::new(server, &current, &RecordType::CNAME, class);
+            let (cname, _, _) = self.entry_at(&key, now, stale)?;
+            let target = Name::from_bytes(cname.first()?.get_data().to_vec(), 0);
+            current = normalize(&target.get_string().ok()?);
+            answers.extend(cname);
+        }
+        None
+    }
+}
+
+/// The name the CNAMEs among `answers` lead to from `name`.
+fn final_name(answers: &[Answer], name: &str) -> String {
+    let mut current = normalize(name);
+    for _ in 0..=DEFAULT_MAX_CHAIN {
+        let cname = answers
+            .iter()
+            .find(|an| matches!(an.get_type(), RecordType::CNAME) && owner(an) == current);
+        match cname.map(|an| Name::from_bytes(an.get_data().to_vec(), 0).get_string()) {
+            Some(Ok(target)) => current = normalize(&target),
+            _ => break,
+        }
+    }
+    current
+}
+
+fn owner(an: &Answer) -> String {
+    normalize(&an.get_name().get_string().unwrap_or_default())
+}
+
+/// Locks `cache`. A panic while it was locked leaves it usable, the entry
+/// being changed is at worst missing.
+pub fn lock(cache: &Mutex<Cache>) -> MutexGuard<'_, Cache> {
+    cache.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Answers the query for `name` to `server` from `cache` if possible,
+/// otherwise with `lookup` whose response is added to the cache. When
+/// `lookup` fails or only gets SERVFAIL, stale records are served if the
+/// cache has them.
+///
+/// Popular answers about to expire are refreshed with `lookup` right away,
+/// the cached answer is only served when the refresh fails.
+pub fn cached_lookup<F>(
+    cache: &Mutex<Cache>,
+    server: &str,
+    name: &str,
+    r_type: &RecordType,
+    rd: bool,
+    lookup: F,
+) -> Result<Response, String>
+where
+    F: FnOnce() -> Result<Response, String>,
+{
+    let hit = lock(cache).lookup(server, name, r_type, 1);
+    let hit = match hit {
+        Some(hit) if !hit.prefetch => {
+            return hit.to_response(&Query::new(name.to_string(), r_type.clone(), rd))
+        }
+        hit => hit,
+    };
+    let res = lookup();
+    if let Some(reason) = failure(&res) {
+        if let Some(hit) = hit {
+            lock(cache).prefetch_failed(&hit.key);
+            return hit.to_response(&Query::new(name.to_string(), r_type.clone(), rd));
+        }
+        let stale = lock(cache).lookup_stale(server, name, r_type, 1);
+        if let Some(stale) = stale {
+            eprintln!("Serving stale records for {}: {}", name, reason);
+            return stale.to_response(&Query::new(name.to_string(), r_type.clone(), rd));
+        }
+    }
+    let resp = res?;
+    lock(cache).insert_response(server, &resp);
+    Ok(resp)
+}
+
+/// Why the result of a lookup calls for stale records, if it does.
+pub fn failure(res: &Result<Response, String>) -> Option<String> {
+    match res {
+        Ok(resp) if matches!(resp.header().rcode(), RCODE::SRVFAIL) => {
+            Some(String::from("the server failed with SERVFAIL"))
+        }
+        Ok(_) => None,
+        Err(err) => Some(err.clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::record;
+
+    fn a(owner: &str, ttl: u32, last: u8) -> Answer {
+        record(owner, RecordType::A, ttl, vec![192, 0, 2, last])
+    }
+
+    const SERVER: &str = "192.0.2.53:53";
+
+    fn key(name: &str) -> CacheKey {
+        CacheKey::new(SERVER, name, &RecordType::A, 1)
+    }
+
+    #[test]
+    fn test_ttl_decrement_and_expiry() {
+        let now = Instant::now();
+        let mut cache = Cache::new(10);
+        cache.insert_at(
+            SERVER,
+            &[a("example.com", 300, 1), a("Example.com.", 60, 2)],
+            now,
+        );
+        assert_eq!(cache.len(), 1);
+
+        let (answers, _) = cache
+            .get_at(&key("example.com"), now + Duration::from_secs(20))
+            .unwrap();
+        assert_eq!(answers.len(), 2);
+        assert_eq!(answers[0].get_ttl(), 280);
+        assert_eq!(answers[1].get_ttl(), 40);
+
+        // The RRset expires with its lowest TTL.
+        assert!(cache
+            .get_at(&key("example.com"), now + Duration::from_secs(60))
+            .is_none());
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_unreadable_records_skipped() {
+        let qry = Query::new("example.com".to_string(), RecordType::NS, true);
+        let base = Response::synthesize(&qry, vec![], false).unwrap();
+        let ns = Name::from_string("ns1.example.com".to_string()).unwrap();
+        // The target of the second NS record points past the end of the message.
+        let answers = vec![
+            record("example.com", RecordType::NS, 300, ns.get_bytes()),
+            record("bad.example.com", RecordType::NS, 300, vec![0xC0, 0xFF]),
+        ];
+        let resp = Response::new(
+            base.get_bytes(),
+            base.header().clone(),
+            vec![],
+            answers,
+            vec![],
+            vec![],
+        );
+        let cache = Mutex::new(Cache::new(10));
+        lock(&cache).insert_response(SERVER, &resp);
+        assert!(lock(&cache)
+            .get(SERVER, "example.com", &RecordType::NS, 1)
+            .is_some());
+        assert!(lock(&cache)
+            .get(SERVER, "bad.example.com", &RecordType::NS, 1)
+            .is_none());
+
+        // A panic while the cache is locked does not make it unusable.
+        let _ = std::panic::catch_unwind(|| {
+            let _guard = cache.lock().unwrap();
+            panic!("lookup failed");
+        });
+        assert!(cache.is_poisoned());
+        lock(&cache).insert(SERVER, &[a("example.org", 300, 1)]);
+        assert_eq!(lock(&cache).len(), 2);
+    }
+
+    #[test]
+    fn test_zero_ttl_not_cached() {
+        let mut cache = Cache::new(10);
+        cache.insert(SERVER, &[a("example.com", 0, 1)]);
+        assert!(cache.is_empty());
+        assert!(cache
+            .get(SERVER, "example.com", &RecordType::A, 1)
+            .is_none());
+    }
+
+    #[test]
+    fn test_lru_eviction() {
+        let now = Instant::now();
+        let mut cache = Cache::new(2);
+        cache.insert_at(SERVER, &[a("one.example", 300, 1)], now);
+        cache.insert_at(SERVER, &[a("two.example", 300, 2)], now);
+        assert!(cache.get_at(&key("one.example"), now).is_some());
+        cache.insert_at(SERVER, &[a("three.example", 300, 3)], now);
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get_at(&key("two.example"), now).is_none());
+        assert!(cache.get_at(&key("one.example"), now).is_some());
+        assert!(cache.get_at(&key("three.example"), now).is_some());
+    }
+
+    #[test]
+    fn test_lookup_follows_cname() {
+        let now = Instant::now();
+        let target = Name::from_string("cdn.example.net".to_string())
+            .unwrap()
+            .get_bytes();
+        let mut cache = Cache::new(10);
+        cache.insert_at(
+            SERVER,
+            &[record("www.example.com", RecordType::CNAME, 300, target)],
+            now,
+        );
+        assert!(cache
+            .lookup_at(SERVER, "www.example.com", &RecordType::A, 1, now)
+            .is_none());
+
+        cache.insert_at(SERVER, &[a("cdn.example.net", 300, 1)], now);
+        let answers = cache
+            .lookup_at(SERVER, "www.example.com", &RecordType::A, 1, now)
+            .unwrap()
+            .answers;
+        assert_eq!(answers.len(), 2);
+        assert!(matches!(answers[0].get_type(), RecordType::CNAME));
+        assert!(matches!(answers[1].get_type(), RecordType::A));
+    }
+
+    #[test]
+    fn test_cached_lookup() {
+        let cache = Mutex::new(Cache::new(10));
+        let mut calls = 0;
+        for _ in 0..3 {
+            let resp = cached_lookup(&cache, SERVER, "example.com", &RecordType::A, true, || {
+                calls += 1;
+                let qry = Query::new("example.com".to_string(), RecordType::A, true);
+                Response::synthesize(&qry, vec![a("example.com", 300, 1)], false)
+            })
+            .unwrap();
+            assert_eq!(resp.answers().len(), 1);
+            assert!(!resp.header().flags().is_aa());
+        }
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn test_servers_kept_apart() {
+        let addrs: Vec<SocketAddr> =
+            vec!["10.0.0.53:53".parse().unwrap(), "[::1]:53".parse().unwrap()];
+        let internal = upstream(&addrs);
+        assert_eq!(internal, "10.0.0.53:53,[::1]:53");
+        let mut cache = Cache::new(10);
+        cache.insert(&internal, &[a("intranet.example.com", 300, 1)]);
+        assert!(cache
+            .lookup(&internal, "intranet.example.com", &RecordType::A, 1)
+            .is_some());
+        // Split-horizon: another server may not know the name at all.
+        assert!(cache
+            .lookup(SERVER, "intranet.example.com", &RecordType::A, 1)
+            .is_none());
+        assert!(cache
+            .lookup_stale(SERVER, "intranet.example.com", &RecordType::A, 1)
+            .is_none());
+    }
+
+    #[test]
+    fn test_serve_stale() {
+        let now = Instant::now();
+        let mut cache = Cache::new(10).with_max_stale(Duration::from_secs(3600));
+        cache.insert_at(SERVER, &[a("example.com", 300, 1)], now);
+
+        let expired = now + Duration::from_secs(600);
+        assert!(cache
+            .lookup_at(SERVER, "example.com", &RecordType::A, 1, expired)
+            .is_none());
+        let hit = cache
+            .lookup_with(SERVER, "example.com", &RecordType::A, 1, expired, true)
+            .unwrap();
+        assert_eq!(hit.answers[0].get_ttl(), STALE_TTL);
+
+        // Fresh records keep their TTL when served for a failed lookup.
+        let fresh = cache
+            .lookup_with(SERVER, "example.com", &RecordType::A, 1, now, true)
+            .unwrap();
+        assert_eq!(fresh.answers[0].get_ttl(), 300);
+
+        let gone = now + Duration::from_secs(300 + 3600);
+        assert!(cache
+            .lookup_with(SERVER, "example.com", &RecordType::A, 1, gone, true)
+            .is_none());
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_stale_after_failed_lookup() {
+        let cache = Mutex::new(Cache::new(10).with_max_stale(Duration::from_secs(3600)));
+        let resp = cached_lookup(&cache, SERVER, "example.com", &RecordType::A, true, || {
+            Err(String::from("No server answered."))
+        });
+        assert!(resp.is_err());
+
+        cache.lock().unwrap().insert_at(
+            SERVER,
+            &[a("example.com", 300, 1)],
+            Instant::now() - Duration::from_secs(600),
+        );
+        let resp = cached_lookup(&cache, SERVER, "example.com", &RecordType::A, true, || {
+            let qry = Query::new("example.com".to_string(), RecordType::A, true);
+            Response::synthesize_with(&qry, 2, vec![], vec![], false)
+        })
+        .unwrap();
+        assert_eq!(resp.answers().len(), 1);
+        assert_eq!(resp.answers()[0].get_ttl(), STALE_TTL);
+    }
+
+    #[test]
+    fn test_cached_lookup_prefetch() {
+        let cache = Mutex::new(Cache::new(10));
+        let stored = Instant::now() - Duration::from_secs(95);
+        lock(&cache).insert_at(SERVER, &[a("example.com", 100, 1)], stored);
+        let mut calls = 0;
+        let mut ttls = vec![];
+        for _ in 0..5 {
+            let resp = cached_lookup(&cache, SERVER, "example.com", &RecordType::A, true, || {
+                calls += 1;
+                let qry = Query::new("example.com".to_string(), RecordType::A, true);
+                match calls {
+                    1 => Err(String::from("No server answered.")),
+                    _ => Response::synthesize(&qry, vec![a("example.com", 300, 1)], false),
+                }
+            })
+            .unwrap();
+            ttls.push(resp.answers()[0].get_ttl());
+        }
+        // The third hit asks for a refresh, which fails and is tried again
+        // on the next one.
+        assert_eq!(calls, 2);
+        assert!(ttls[..3].iter().all(|ttl| *ttl <= 5));
+        assert_eq!(ttls[3], 300);
+        assert!(ttls[4] > 5);
+    }
+
+    #[test]
+    fn test_prefetch_popular() {
+        let now = Instant::now();
+        let mut cache = Cache::new(10);
+        cache.insert_at(
+            SERVER,
+            &[a("example.com", 100, 1), a("rare.example.com", 100, 1)],
+            now,
+        );
+        let lookup = |cache: &mut Cache, name: &str, secs: u64| {
+            cache
+                .lookup_at(
+                    SERVER,
+                    name,
+                    &RecordType::A,
+                    1,
+                    now + Duration::from_secs(secs),
+                )
+                .unwrap()
+                .prefetch
+        };
+        for _ in 0..3 {
+            assert!(!lookup(&mut cache, "example.com", 10));
+        }
+        assert!(!lookup(&mut cache, "example.com", 50));
+        assert!(lookup(&mut cache, "example.com", 95));
+        // Only the first hit near expiry asks for a refresh.
+        assert!(!lookup(&mut cache, "example.com", 96));
+        assert!(!lookup(&mut cache, "rare.example.com", 95));
+    }
+
+    fn soa(zone: &str, ttl: u32, minimum: u32) -> Answer {
+        let mut data = Name::from_string(format!("ns.{}", zone))
+            .unwrap()
+            .get_bytes();
+        data.extend(
+            Name::from_string(format!("admin.{}", zone))
+                .unwrap()
+                .get_bytes(),
+        );
+        for n in [1, 7200, 3600, 1209600, minimum] {
+            data.extend(n.to_be_bytes());
+        }
+        record(zone, RecordType::SOA, ttl, data)
+    }
+
+    fn negative(name: &str, r_type: RecordType, rcode: u8, answers: Vec<Answer>) -> Response {
+        let qry = Query::new(name.to_string(), r_type, true);
+        let authority = vec![soa("example.com", 3600, 60)];
+        Response::synthesize_with(&qry, rcode, answers, authority, false).unwrap()
+    }
+
+    #[test]
+    fn test_nxdomain_cached() {
+        let now = Instant::now();
+        let mut cache = Cache::new(10);
+        cache.insert_response_at(
+            SERVER,
+            &negative("gone.example.com", RecordType::A, 3, vec![]),
+            now,
+        );
+        assert_eq!(cache.len(), 1);
+
+        // NXDOMAIN denies every type of the name.
+        let later = now + Duration::from_secs(10);
+        let hit = cache
+            .lookup_at(SERVER, "gone.example.com", &RecordType::MX, 1, later)
+            .unwrap();
+        assert!(hit.nxdomain);
+        assert!(hit.answers.is_empty());
+        assert_eq!(hit.soa[0].get_ttl(), 3590);
+        let qry = Query::new("gone.example.com".to_string(), RecordType::MX, true);
+        let resp = hit.to_response(&qry).unwrap();
+        assert!(matches!(resp.header().rcode(), RCODE::NAMEERR));
+        assert!(matches!(resp.authority()[0].get_type(), RecordType::SOA));
+
+        // The entry lives for the SOA MINIMUM, which is below its TTL.
+        let expired = now + Duration::from_secs(60);
+        assert!(cache
+            .lookup_at(SERVER, "gone.example.com", &RecordType::A, 1, expired)
+            .is_none());
+    }
+
+    #[test]
+    fn test_nodata_cached() {
+        let now = Instant::now();
+        let mut cache = Cache::new(10);
+        cache.insert_response_at(
+            SERVER,
+            &negative("www.example.com", RecordType::AAAA, 0, vec![]),
+            now,
+        );
+        let hit = cache
+            .lookup_at(SERVER, "www.example.com", &RecordType::AAAA, 1, now)
+            .unwrap();
+        assert!(!hit.nxdomain);
+        assert!(hit.answers.is_empty());
+        assert_eq!(hit.soa.len(), 1);
+        assert!(cache
+            .lookup_at(SERVER, "www.example.com", &RecordType::A, 1, now)
+            .is_none());
+        assert!(cache
+            .get(SERVER, "www.example.com", &RecordType::AAAA, 1)
+            .is_none());
+    }
+
+    #[test]
+    fn test_nxdomain_after_cname() {
+        let now = Instant::now();
+        let target = Name::from_string("gone.example.com".to_string())
+            .unwrap()
+            .get_bytes();
+        let answers = vec![record("www.example.com", RecordType::CNAME, 300, target)];
+        let mut cache = Cache::new(10);
+        cache.insert_response_at(
+            SERVER,
+            &negative("www.example.com", RecordType::A, 3, answers),
+            now,
+        );
+        let hit = cache
+            .lookup_at(SERVER, "www.example.com", &RecordType::A, 1, now)
+            .unwrap();
+        assert!(hit.nxdomain);
+        assert_eq!(hit.answers.len(), 1);
+        assert!(cache
+            .lookup_at(SERVER, "gone.example.com", &RecordType::UNKNOWN(16), 1, now)
+            .is_some());
+    }
+}
@@ -0,0 +1,326 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use dns::record::{RecordType, SOARecord};
+use parsing::{byte_stream_parser::ByteStreamParser, Response};
+use rusqlite::{params, Connection};
+
+use crate::{cache::Cache, iterative::normalize};
+
+// Rows of the first version were shared by all servers and are dropped.
+const SCHEMA: &str = "DROP TABLE IF EXISTS responses;
+CREATE TABLE IF NOT EXISTS server_responses (
+    name TEXT NOT NULL,
+    r_type INTEGER NOT NULL,
+    class INTEGER NOT NULL,
+    server TEXT NOT NULL,
+    stored_at INTEGER NOT NULL,
+    ttl INTEGER NOT NULL,
+    expires_at INTEGER NOT NULL,
+    bytes BLOB NOT NULL,
+    PRIMARY KEY (server, name, r_type, class)
+)";
+
+/// Responses persisted in SQLite, so lookups repeated across invocations
+/// are answered without asking a server as long as their TTL allows.
+///
+/// Every row keeps the raw response with the question, the upstream it was
+/// asked, see [`crate::cache::upstream`], when it was stored and the TTL it
+/// is valid for. Responses are only served again for the same upstream,
+/// with the TTL which is left.
+pub struct CacheDb {
+    conn: Connection,
+}
+
+impl CacheDb {
+    pub fn open(path: &str) -> Result<Self, String> {
+        let conn = Connection::open(path)
+            .map_err(|err| format!("Failed to open cache database {}: {}", path, err))?;
+        conn.execute_batch(SCHEMA)
+            .map_err(|err| format!("Failed to create cache table in {}: {}", path, err))?;
+        Ok(Self { conn })
+    }
+
+    /// Stores `resp` unless none of its records may be cached.
+    pub fn store(&self, resp: &Response, server: &str) -> Result<(), String> {
+        self.store_at(resp, server, unix_now())
+    }
+
+    fn store_at(&self, resp: &Response, server: &str, now: u64) -> Result<(), String> {
+        let ttl = match cache_ttl(resp) {
+            Some(ttl) if ttl > 0 => ttl,
+            _ => return Ok(()),
+        };
+        let question = match resp.question() {
+            Some(question) => question,
+            None => return Ok(()),
+        };
+        let name = question
+            .get_name()
+            .get_string()
+            .map_err(|err| err.to_string())?;
+        self.conn
+            .execute(
+                "INSERT OR REPLACE INTO server_responses
+                (name, r_type, class, server, stored_at, ttl, expires_at, bytes)
+                VALUES (?1, ?2, 1, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    normalize(&name),
+                    question.get_type().code(),
+                    server,
+                    now,
+                    ttl,
+                    now + ttl as u64,
+                    resp.get_bytes(),
+                ],
+            )
+            .map_err(|err| format!("Failed to store response for {}: {}", name, err))?;
+        Ok(())
+    }
+
+    /// Adds every response of `server` which is still valid, or expired
+    /// less than the `max_stale` of `cache` ago, to `cache`. Returns the
+    /// number of responses loaded.
+    pub fn load_into(&self, cache: &mut Cache, server: &str) -> Result<usize, String> {
+        self.load_into_at(cache, server, unix_now())
+    }
+
+    fn load_into_at(&self, cache: &mut Cache, server: &str, now: u64) -> Result<usize, String> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT stored_at, bytes FROM server_responses
+                WHERE server = ?3 AND expires_at + ?2 > ?1",
+            )
+            .map_err(|err| format!("Failed to read cache database: {}", err))?;
+        let rows = stmt
+            .query_map(params![now, cache.max_stale().as_secs(), server], |row| {
+                Ok((row.get::<_, u64>(0)?, row.get::<_, Vec<u8>>(1)?))
+            })
+            .map_err(|err| format!("Failed to read cache database: {}", err))?;
+        let mut loaded = 0;
+        for row in rows {
+            let (stored_at, bytes) =
+                row.map_err(|err| format!("Failed to read cache database: {}", err))?;
+            // Rows which no longer parse are skipped and evicted eventually.
+            if let Ok(resp) = ByteStreamParser::new(&bytes).parse_response() {
+                let age = Duration::from_secs(now.saturating_sub(stored_at));
+                cache.insert_response_aged(server, &resp, age);
+                loaded += 1;
+            }
+        }
+        Ok(loaded)
+    }
+
+    /// Deletes responses which expired more than `max_stale` seconds ago,
+    /// the others may still be served stale, and compacts the database
+    /// file. Returns the number of responses deleted.
+    pub fn evict(&self, max_stale: u64) -> Result<usize, String> {
+        self.evict_at(unix_now(), max_stale)
+    }
+
+    fn evict_at(&self, now: u64, max_stale: u64) -> Result<usize, String> {
+        let deleted = self
+            .conn
+            .execute(
+                "DELETE FROM server_responses WHERE expires_at + ?2 <= ?1",
+                params![now, max_stale],
+            )
+            .map_err(|err| format!("Failed to evict expired responses: {}", err))?;
+        self.conn
+            .execute("VACUUM", [])
+            .map_err(|err| format!("Failed to vacuum cache database: {}", err))?;
+        Ok(deleted)
+    }
+
+    pub fn len(&self) -> Result<usize, String> {
+        self.conn
+            .query_row("SELECT COUNT(*) FROM server_responses", [], |row| {
+                row.get(0)
+            })
+            .map_err(|err| format!("Failed to read cache database: {}", err))
+    }
+
+    pub fn is_empty(&self) -> Result<bool, String> {
+        self.len().map(|len| len == 0)
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Seconds `resp` may be cached: the lowest TTL of its answers or, for
+/// negative answers, the lower of TTL and MINIMUM of the SOA record.
+fn cache_ttl(resp: &Response) -> Option<u32> {
+    if !resp.answers().is_empty() {
+        return resp.answers().iter().map(|an| an.get_ttl()).min();
+    }
+    let soa = resp
+        .authority()
+        .iter()
+        .find(|ns| matches!(ns.get_type(), RecordType::SOA))?;
+    let soa_data = SOARecord::from_bytes(soa.get_data().to_vec(), resp.get_bytes(), 0).ok()?;
+    Some(soa.get_ttl().min(soa_data.get_minimum()))
+}
+
+#[cfg(test)]
+mod tests {
+    use dns::name::Name;
+    use parsing::Query;
+
+    use super::*;
+    use crate::test_util::record;
+
+    fn response(name: &str, ttl: u32) -> Response {
+        let qry = Query::new(name.to_string(), RecordType::A, true);
+        let answers = vec![record(name, RecordType::A, ttl, vec![192, 0, 2, 1])];
+        Response::synthesize(&qry, answers, false).unwrap()
+    }
+
+    fn nxdomain(name: &str) -> Response {
+        let mut soa = Name::from_string("ns.example.com".to_string())
+            .unwrap()
+            .get_bytes();
+        soa.extend(
+            Name::from_string("admin.example.com".to_string())
+                .unwrap()
+                .get_bytes(),
+        );
+        for n in [1u32, 7200, 3600, 1209600, 60] {
+            soa.extend(n.to_be_bytes());
+        }
+        let qry = Query::new(name.to_string(), RecordType::A, true);
+        let authority = vec![record("example.com", RecordType::SOA, 3600, soa)];
+        Response::synthesize_with(&qry, 3, vec![], authority, false).unwrap()
+    }
+
+    fn db() -> CacheDb {
+        CacheDb::open(":memory:").unwrap()
+    }
+
+    const SERVER: &str = "192.0.2.53:53";
+
+    #[test]
+    fn test_store_and_age() {
+        let db = db();
+        let now = unix_now();
+        db.store_at(&response("example.com", 300), SERVER, now - 100)
+            .unwrap();
+        let mut cache = Cache::new(10);
+        assert_eq!(db.load_into_at(&mut cache, SERVER, now).unwrap(), 1);
+        let answers = cache
+            .get(SERVER, "Example.com.", &RecordType::A, 1)
+            .unwrap();
+        assert_eq!(answers.len(), 1);
+        assert!(answers[0].get_ttl() <= 200);
+        assert!(cache
+            .get(SERVER, "example.com", &RecordType::AAAA, 1)
+            .is_none());
+
+        let mut cache = Cache::new(10);
+        assert_eq!(db.load_into_at(&mut cache, SERVER, now + 300).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_store_per_server() {
+        let db = db();
+        db.store(&response("intranet.example.com", 300), SERVER)
+            .unwrap();
+        db.store(&response("intranet.example.com", 60), "8.8.8.8:53")
+            .unwrap();
+        assert_eq!(db.len().unwrap(), 2);
+        let mut cache = Cache::new(10);
+        assert_eq!(db.load_into(&mut cache, "10.0.0.53:53").unwrap(), 0);
+        assert_eq!(db.load_into(&mut cache, SERVER).unwrap(), 1);
+        assert!(cache
+            .get("8.8.8.8:53", "intranet.example.com", &RecordType::A, 1)
+            .is_none());
+        let answers = cache
+            .get(SERVER, "intranet.example.com", &RecordType::A, 1)
+            .unwrap();
+        assert!(answers[0].get_ttl() > 60);
+    }
+
+    #[test]
+    fn test_store_negative() {
+        let db = db();
+        let now = unix_now();
+        db.store_at(&nxdomain("gone.example.com"), SERVER, now - 10)
+            .unwrap();
+        let mut cache = Cache::new(10);
+        assert_eq!(db.load_into_at(&mut cache, SERVER, now).unwrap(), 1);
+        let hit = cache
+            .lookup(SERVER, "gone.example.com", &RecordType::A, 1)
+            .unwrap();
+        assert!(hit.nxdomain);
+        assert!(hit.soa[0].get_ttl() <= 3590);
+        // Negative answers live for the SOA MINIMUM.
+        let mut cache = Cache::new(10);
+        assert_eq!(db.load_into_at(&mut cache, SERVER, now + 50).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_zero_ttl_not_stored() {
+        let db = db();
+        db.store(&response("example.com", 0), SERVER).unwrap();
+        assert!(db.is_empty().unwrap());
+    }
+
+    #[test]
+    fn test_evict() {
+        let db = db();
+        db.store_at(&response("short.example.com", 10), SERVER, 1000)
+            .unwrap();
+        db.store_at(&response("long.example.com", 1000), SERVER, 1000)
+            .unwrap();
+        assert_eq!(db.evict_at(1100, 0).unwrap(), 1);
+        assert_eq!(db.len().unwrap(), 1);
+        // Expired responses are kept to be served stale.
+        assert_eq!(db.evict_at(2100, 3600).unwrap(), 0);
+        assert_eq!(db.evict_at(5700, 3600).unwrap(), 1);
+        assert!(db.is_empty().unwrap());
+    }
+
+    #[test]
+    fn test_load_into() {
+        let db = db();
+        db.store(&response("example.com", 300), SERVER).unwrap();
+        db.store(&nxdomain("gone.example.com"), SERVER).unwrap();
+        let mut cache = Cache::new(10);
+        assert_eq!(db.load_into(&mut cache, SERVER).unwrap(), 2);
+        assert!(cache
+            .get(SERVER, "example.com", &RecordType::A, 1)
+            .is_some());
+        assert!(
+            cache
+                .lookup(SERVER, "gone.example.com", &RecordType::MX, 1)
+                .unwrap()
+                .nxdomain
+        );
+    }
+
+    #[test]
+    fn test_load_stale() {
+        let db = db();
+        let now = unix_now();
+        db.store_at(&response("stale.example.com", 300), SERVER, now - 1000)
+            .unwrap();
+        db.store_at(&response("gone.example.com", 300), SERVER, now - 5000)
+            .unwrap();
+        let mut cache = Cache::new(10).with_max_stale(Duration::from_secs(3600));
+        assert_eq!(db.load_into_at(&mut cache, SERVER, now).unwrap(), 1);
+        assert!(cache
+            .get(SERVER, "stale.example.com", &RecordType::A, 1)
+            .is_none());
+        let stale = cache
+            .lookup_stale(SERVER, "stale.example.com", &RecordType::A, 1)
+            .unwrap();
+        assert_eq!(stale.answers.len(), 1);
+        assert!(cache
+            .lookup_stale(SERVER, "gone.example.com", &RecordType::A, 1)
+            .is_none());
+    }
+}
@@ -0,0 +1,349 @@
+use std::collections::HashSet;
+
+use dns::{
+    answer::Answer,
+    record::{CNAMERecord, DNAMERecord, RecordType},
+};
+use parsing::Response;
+
+use crate::iterative::{is_subdomain, normalize};
+
+/// CNAME and DNAME links followed by default before giving up.
+pub const DEFAULT_MAX_CHAIN: usize = 8;
+
+/// One step of an alias chain. A DNAME is followed by the CNAME which
+/// was synthesized from it for the name being resolved.
+pub struct ChainLink {
+    pub owner: String,
+    pub r_type: RecordType,
+    pub target: String,
+    pub synthesized: bool,
+}
+
+/// The aliases leading from the queried name to the final name and the
+/// records of the requested type found there.
+pub struct Chain {
+    pub links: Vec<ChainLink>,
+    pub name: String,
+    pub r_type: RecordType,
+    pub answers: Vec<Answer>,
+    pub response: Response,
+}
+
+impl Chain {
+    pub fn print(&self) {
+        println!("Chain:");
+        let mut indent = String::new();
+        for link in self.links.iter() {
+            let note = if link.synthesized {
+                " (synthesized from DNAME)"
+            } else {
+                ""
+            };
+            println!(
+                "{}{} {} {}{}",
+                indent,
+                link.owner,
+                link.r_type.to_string(),
+                link.target,
+                note
+            );
+            if !matches!(link.r_type, RecordType::DNAME) {
+                indent.push_str("  ");
+            }
+        }
+        println!(
+            "{}{} {}: {} records",
+            indent,
+            self.name,
+            self.r_type.to_string(),
+            self.answers.len()
+        );
+    }
+}
+
+/// Looks `name` up with `lookup` and follows CNAME and DNAME records until
+/// a name with records of `r_type` is reached. Links contained in one
+/// response are followed without asking again, the target of the last
+/// link is looked up when its records are missing. `lookup` has to drop
+/// records a server is not responsible for, like the iterative resolver
+/// does, or they are taken as the records of the target.
+///
+/// More than `max_depth` aliases or a name seen twice are errors. With a
+/// `max_depth` of zero the first response is returned as is.
+pub fn follow_chain<F>(
+    name: &str,
+    r_type: &RecordType,
+    max_depth: usize,
+    mut lookup: F,
+) -> Result<Chain, String>
+where
+    F: FnMut(&str) -> Result<Response, String>,
+{
+    let mut current = normalize(name);
+    let mut links: Vec<ChainLink> = vec![];
+    let mut seen: HashSet<String> = HashSet::from([current.clone()]);
+    // Asking for the aliases themselves must not follow them.
+    let follow = max_depth > 0 && !matches!(r_type, RecordType::CNAME | RecordType::DNAME);
+    loop {
+        let response = lookup(&current)?;
+        let src = response.get_bytes();
+        let mut moved = false;
+        loop {
+            let answers = records_of(response.answers(), &current, r_type);
+            if !answers.is_empty() || !follow {
+                return Ok(Chain {
+                    links,
+                    name: current,
+                    r_type: r_type.clone(),
+                    answers,
+                    response,
+                });
+            }
+            let (dname, target) = match next_alias(response.answers(), &src, &current) {
+                Some(alias) => alias,
+                None => break,
+            };
+            let aliases = links
+                .iter()
+                .filter(|l| matches!(l.r_type, RecordType::CNAME));
+            if aliases.count() >= max_depth {
+                return Err(format!(
+                    "Gave up following the alias chain of {} after {} links.",
+                    normalize(name),
+                    max_depth
+                ));
+            }
+            if !seen.insert(target.clone()) {
+                return Err(format!(
+                    "Alias loop: {} points back to {}.",
+                    current, target
+                ));
+            }
+            let synthesized = dname.is_some();
+            links.extend(dname);
+            links.push(ChainLink {
+                owner: current,
+                r_type: RecordType::CNAME,
+                target: target.clone(),
+                synthesized,
+            });
+            current = target;
+            moved = true;
+        }
+        // Errors like NXDOMAIN for the end of the chain are final.
+        if !moved || response.header().rcode().is_err() {
+            return Ok(Chain {
+                links,
+                name: current,
+                r_type: r_type.clone(),
+                answers: vec![],
+                response,
+            });
+        }
+    }
+}
+
+/// The target `name` is aliased to. A DNAME of one of its ancestors is
+/// preferred over a CNAME as servers add the CNAME synthesized from it
+/// (RFC 6672), the DNAME is returned as a link of its own.
+fn next_alias(records: &[Answer], src: &[u8], name: &str) -> Option<(Option<ChainLink>, String)> {
+    for rr in records.iter() {
+        let dname_owner = owner(rr);
+        if !matches!(rr.get_type(), RecordType::DNAME)
+            || dname_owner.is_empty()
+            || dname_owner == name
+            || !is_subdomain(name, &dname_owner)
+        {
+            continue;
+        }
+        let dname = DNAMERecord::from_bytes(rr.get_data().to_vec(), src.to_vec(), 0).ok()?;
+        let target = normalize(&dname.get_target().get_string().ok()?);
+        let prefix = &name[..name.len() - dname_owner.len() - 1];
+        let synthesized = if target.is_empty() {
+            prefix.to_string()
+        } else {
+            format!("{}.{}", prefix, target)
+        };
+        let link = ChainLink {
+            owner: dname_owner,
+            r_type: RecordType::DNAME,
+            target,
+            synthesized: false,
+        };
+        return Some((Some(link), synthesized));
+    }
+    for rr in records.iter() {
+        if matches!(rr.get_type(), RecordType::CNAME) && owner(rr) == name {
+            let cname = CNAMERecord::from_bytes(rr.get_data().to_vec(), src.to_vec(), 0).ok()?;
+            return Some((None, normalize(&cname.get_name().get_string().ok()?)));
+        }
+    }
+    None
+}
+
+fn records_of(records: &[Answer], name: &str, r_type: &RecordType) -> Vec<Answer> {
+    records
+        .iter()
+        .filter(|rr| rr.get_type().code() == r_type.code() && owner(rr) == name)
+        .cloned()
+        .collect()
+}
+
+fn owner(rr: &Answer) -> String {
+    normalize(&rr.get_name().get_string().unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use parsing::Query;
+
+    use super::*;
+    use crate::test_util::{name, record};
+
+    fn cname(owner: &str, target: &str) -> Answer {
+        record(owner, RecordType::CNAME, 300, name(target))
+    }
+
+    fn a(owner: &str) -> Answer {
+        record(owner, RecordType::A, 300, vec![192, 0, 2, 1])
+    }
+
+    fn response(qname: &str, answers: Vec<Answer>) -> Result<Response, String> {
+        Response::synthesize(
+            &Query::new(qname.to_string(), RecordType::A, true),
+            answers,
+            false,
+        )
+    }
+
+    /// Answers every lookup from `zone`, records are matched by owner.
+    fn lookup<'a>(
+        zone: &'a [Answer],
+        asked: &'a mut Vec<String>,
+    ) -> impl FnMut(&str) -> Result<Response, String> + 'a {
+        move |qname| {
+            asked.push(qname.to_string());
+            let answers = zone
+                .iter()
+                .filter(|rr| rr.get_name().get_string().unwrap() == qname)
+                .cloned()
+                .collect();
+            response(qname, answers)
+        }
+    }
+
+    #[test]
+    fn test_chain_in_one_response() {
+        let mut asked = vec![];
+        let chain = follow_chain("WWW.example.com.", &RecordType::A, 8, |qname| {
+            asked.push(qname.to_string());
+            response(
+                qname,
+                vec![
+                    cname("www.example.com", "cdn.example.net"),
+                    a("cdn.example.net"),
+                ],
+            )
+        })
+        .unwrap();
+        assert_eq!(asked, vec!["www.example.com"]);
+        assert_eq!(chain.links.len(), 1);
+        assert_eq!(chain.name, "cdn.example.net");
+        assert_eq!(chain.answers.len(), 1);
+    }
+
+    #[test]
+    fn test_chain_across_lookups() {
+        let zone = [
+            cname("www.example.com", "alias.example.net"),
+            cname("alias.example.net", "cdn.example.org"),
+            a("cdn.example.org"),
+        ];
+        let mut asked = vec![];
+        let chain = follow_chain(
+            "www.example.com",
+            &RecordType::A,
+            8,
+            lookup(&zone, &mut asked),
+        )
+        .unwrap();
+        assert_eq!(chain.links.len(), 2);
+        assert_eq!(chain.answers.len(), 1);
+        assert_eq!(
+            asked,
+            vec!["www.example.com", "alias.example.net", "cdn.example.org"]
+        );
+    }
+
+    #[test]
+    fn test_chain_loop_and_depth() {
+        let zone = [
+            cname("a.example.com", "b.example.com"),
+            cname("b.example.com", "a.example.com"),
+        ];
+        let mut asked = vec![];
+        let err = follow_chain(
+            "a.example.com",
+            &RecordType::A,
+            8,
+            lookup(&zone, &mut asked),
+        )
+        .err()
+        .unwrap();
+        assert!(err.starts_with("Alias loop"));
+
+        let zone = [
+            cname("a.example.com", "b.example.com"),
+            cname("b.example.com", "c.example.com"),
+            a("c.example.com"),
+        ];
+        let mut asked = vec![];
+        assert!(follow_chain(
+            "a.example.com",
+            &RecordType::A,
+            1,
+            lookup(&zone, &mut asked)
+        )
+        .is_err());
+
+        let mut asked = vec![];
+        let chain = follow_chain(
+            "a.example.com",
+            &RecordType::A,
+            0,
+            lookup(&zone, &mut asked),
+        )
+        .unwrap();
+        assert!(chain.links.is_empty());
+        assert!(chain.answers.is_empty());
+        assert_eq!(chain.response.answers().len(), 1);
+    }
+
+    #[test]
+    fn test_dname_synthesis() {
+        let zone = [
+            record("old.example", RecordType::DNAME, 300, name("new.example")),
+            a("www.new.example"),
+        ];
+        let mut asked = vec![];
+        let chain = follow_chain("www.old.example", &RecordType::A, 8, |qname| {
+            asked.push(qname.to_string());
+            // The DNAME is returned for every name below old.example.
+            let answers = zone
+                .iter()
+                .filter(|rr| is_subdomain(qname, &rr.get_name().get_string().unwrap()))
+                .cloned()
+                .collect();
+            response(qname, answers)
+        })
+        .unwrap();
+        assert_eq!(asked, vec!["www.old.example", "www.new.example"]);
+        assert_eq!(chain.links.len(), 2);
+        assert!(matches!(chain.links[0].r_type, RecordType::DNAME));
+        assert_eq!(chain.links[1].owner, "www.old.example");
+        assert_eq!(chain.links[1].target, "www.new.example");
+        assert!(chain.links[1].synthesized);
+        assert_eq!(chain.answers.len(), 1);
+    }
+}
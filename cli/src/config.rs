@@ -0,0 +1,301 @@
+use std::{
+    collections::BTreeMap,
+    env, fmt, fs,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+};
+
+use parsing::Edns;
+use serde::Deserialize;
+
+use crate::socket::DNSSocket;
+
+/*
+    The configuration file defines named servers, usable like the presets
+    with --server, and defaults for the command line options:
+
+        [defaults]
+        server = "corp-internal"
+        record = "A,AAAA"
+        recursion-desired = true
+
+        [servers.corp-internal]
+        address = ["10.0.0.53", "10.0.1.53:5353"]
+        edns = { udp-size = 1232, nsid = true }
+
+        [servers.corp-doh]
+        transport = "https"
+        doh-url = "https://dns.corp.example/dns-query"
+
+    Options given on the command line win over the defaults.
+*/
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub defaults: Defaults,
+    pub servers: BTreeMap<String, ServerProfile>,
+}
+
+/// Defaults for the command line options of the same name.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Defaults {
+    pub server: Option<String>,
+    pub record: Option<String>,
+    pub recursion_desired: Option<bool>,
+    pub hosts: Option<bool>,
+    pub format: Option<String>,
+    pub short: Option<bool>,
+    pub timeout: Option<u64>,
+    pub attempts: Option<u32>,
+    pub max_chain: Option<usize>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ServerProfile {
+    /// IP addresses with an optional port, tried in order.
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub address: Vec<String>,
+    #[serde(default)]
+    pub transport: Protocol,
+    /// The name to verify the certificate of a DNS over TLS server against.
+    pub tls_name: Option<String>,
+    pub doh_url: Option<String>,
+    pub edns: Option<EdnsOptions>,
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    #[default]
+    Udp,
+    Tcp,
+    Tls,
+    Https,
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Protocol::Udp => write!(f, "UDP"),
+            Protocol::Tcp => write!(f, "TCP"),
+            Protocol::Tls => write!(f, "DNS over TLS"),
+            Protocol::Https => write!(f, "DNS over HTTPS"),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct EdnsOptions {
+    pub udp_size: u16,
+    pub dnssec_ok: bool,
+    pub nsid: bool,
+}
+
+impl Default for EdnsOptions {
+    fn default() -> Self {
+        let edns = Edns::default();
+        Self {
+            udp_size: edns.udp_size,
+            dnssec_ok: edns.dnssec_ok,
+            nsid: edns.nsid,
+        }
+    }
+}
+
+fn one_or_many<'de, D: serde::Deserializer<'de>>(de: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(de)? {
+        OneOrMany::One(addr) => vec![addr],
+        OneOrMany::Many(addrs) => addrs,
+    })
+}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/askrs/config.toml`, or `~/.config/askrs/config.toml`
+    /// without it.
+    pub fn default_path() -> Option<PathBuf> {
+        let base = match env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        };
+        Some(base.join("askrs").join("config.toml"))
+    }
+
+    /// Reads the file given with --config, or the default file if there
+    /// is one.
+    pub fn load(path: Option<&str>) -> Result<Self, String> {
+        match (path, Self::default_path()) {
+            (Some(path), _) => Self::from_file(path),
+            (None, Some(path)) if path.is_file() => Self::from_file(&path.to_string_lossy()),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        let content =
+            fs::read_to_string(path).map_err(|err| format!("Failed to read {}: {}", path, err))?;
+        Self::parse(&content).map_err(|err| format!("Invalid configuration {}: {}", path, err))
+    }
+
+    pub fn parse(content: &str) -> Result<Self, String> {
+        toml::from_str(content).map_err(|err| err.to_string())
+    }
+
+    /// The server profile called `name`, if the file defines one.
+    pub fn server(&self, name: &str) -> Option<Result<DNSSocket, String>> {
+        self.servers.get(name).map(|profile| profile.socket(name))
+    }
+}
+
+impl ServerProfile {
+    /// Queries are only sent over UDP so far, profiles for the other
+    /// transports can be defined but not used yet.
+    pub fn socket(&self, name: &str) -> Result<DNSSocket, String> {
+        if self.transport != Protocol::Udp {
+            return Err(format!(
+                "The server {} uses {}, which is not supported: queries can only be sent over UDP so far.",
+                name, self.transport
+            ));
+        }
+        if self.address.is_empty() {
+            return Err(format!("The server {} has no address.", name));
+        }
+        let addrs = self
+            .address
+            .iter()
+            .map(|addr| parse_address(addr).ok_or_else(|| format!("Invalid address {} of the server {}, expected an IP address with an optional port.", addr, name)))
+            .collect::<Result<Vec<SocketAddr>, String>>()?;
+        let edns = self.edns.as_ref().map(|opts| Edns {
+            udp_size: opts.udp_size,
+            dnssec_ok: opts.dnssec_ok,
+            nsid: opts.nsid,
+        });
+        Ok(DNSSocket::PROFILE(addrs, edns))
+    }
+}
+
+fn parse_address(addr: &str) -> Option<SocketAddr> {
+    addr.parse::<SocketAddr>().ok().or_else(|| {
+        addr.parse::<IpAddr>()
+            .ok()
+            .map(|ip| SocketAddr::new(ip, 53))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::{CommandFactory, FromArgMatches};
+
+    use super::*;
+    use crate::Flags;
+
+    const CONFIG: &str = r#"
+        [defaults]
+        server = "corp-internal"
+        record = "A,AAAA"
+        recursion-desired = true
+
+        [servers.corp-internal]
+        address = ["10.0.0.53", "[2001:db8::53]:5353"]
+        edns = { udp-size = 4096, dnssec-ok = true }
+
+        [servers.lab]
+        address = "192.0.2.1"
+
+        [servers.corp-doh]
+        transport = "https"
+        doh-url = "https://dns.corp.example/dns-query"
+        tls-name = "dns.corp.example"
+    "#;
+
+    #[test]
+    fn test_parse_config() {
+        let config = Config::parse(CONFIG).unwrap();
+        assert_eq!(config.defaults.server.as_deref(), Some("corp-internal"));
+        assert_eq!(config.defaults.recursion_desired, Some(true));
+        assert!(config.defaults.timeout.is_none());
+
+        let corp = config.server("corp-internal").unwrap().unwrap();
+        assert_eq!(
+            corp.get_addresses(),
+            vec![
+                "10.0.0.53:53".parse().unwrap(),
+                "[2001:db8::53]:5353".parse().unwrap()
+            ]
+        );
+        let edns = corp.edns().unwrap();
+        assert_eq!(
+            (edns.udp_size, edns.dnssec_ok, edns.nsid),
+            (4096, true, false)
+        );
+
+        let lab = config.server("lab").unwrap().unwrap();
+        assert_eq!(lab.get_addresses(), vec!["192.0.2.1:53".parse().unwrap()]);
+        assert!(lab.edns().is_none());
+
+        let doh = &config.servers["corp-doh"];
+        assert_eq!(doh.transport, Protocol::Https);
+        assert_eq!(doh.tls_name.as_deref(), Some("dns.corp.example"));
+        let err = config.server("corp-doh").unwrap().err().unwrap();
+        assert!(err.contains("DNS over HTTPS, which is not supported"));
+        assert!(config.server("google").is_none());
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        assert!(Config::parse("[defaults]\nsrever = \"lab\"").is_err());
+        assert!(Config::parse("[servers.x]\ntransport = \"quic\"").is_err());
+        let config = Config::parse("[servers.x]\naddress = \"dns.example\"").unwrap();
+        assert!(config.server("x").unwrap().is_err());
+        let config = Config::parse("[servers.x]").unwrap();
+        assert!(config.server("x").unwrap().is_err());
+    }
+
+    #[test]
+    fn test_apply_defaults() {
+        let config = Config::parse(
+            "[defaults]\nserver = \"lab\"\nhosts = true\nmax-chain = 2\ntimeout = 3\nformat = \"json\"",
+        )
+        .unwrap();
+        let args = [
+            "askrs",
+            "-u",
+            "example.com",
+            "--max-chain",
+            "5",
+            "--format",
+            "dig",
+        ];
+        let matches = Flags::command().try_get_matches_from(args).unwrap();
+        let mut flags = Flags::from_arg_matches(&matches).unwrap();
+        flags.apply_defaults(&config.defaults, &matches).unwrap();
+        assert_eq!(flags.server, "lab");
+        assert!(flags.use_hosts);
+        assert_eq!(flags.timeout, Some(3));
+        assert_eq!(flags.max_chain, 5);
+        assert_eq!(flags.format, "dig");
+
+        let config = Config::parse("[defaults]\nshort = true").unwrap();
+        let matches = Flags::command()
+            .try_get_matches_from(["askrs", "-u", "example.com", "--format", "text"])
+            .unwrap();
+        let mut flags = Flags::from_arg_matches(&matches).unwrap();
+        flags.apply_defaults(&config.defaults, &matches).unwrap();
+        assert!(!flags.short);
+
+        let config = Config::parse("[defaults]\nformat = \"xml\"").unwrap();
+        let matches = Flags::command()
+            .try_get_matches_from(["askrs", "-u", "example.com"])
+            .unwrap();
+        assert!(flags.apply_defaults(&config.defaults, &matches).is_err());
+    }
+}
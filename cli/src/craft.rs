@@ -0,0 +1,341 @@
+use std::{fs, path::Path};
+
+use dns::{
+    answer::{data_from_string, Answer},
+    header::Header,
+    name::Name,
+    question::{QClass, Question},
+    record::RecordType,
+};
+use serde::Deserialize;
+
+/*
+    A message spec describes every field of a message, so servers can be
+    tested with messages a resolver would never send. In TOML:
+
+        id = 4660
+        opcode = "QUERY"
+        flags = ["rd", "z"]
+
+        [[question]]
+        name = "example.com"
+        type = "MX"
+        class = "CH"
+
+        [[additional]]
+        name = "example.com"
+        ttl = 300
+        type = "A"
+        data = "192.0.2.1"
+
+    JSON uses the same names. Types, classes, opcodes and RCODEs are given
+    by their mnemonic or number, record data in zone file format or in the
+    generic `\# length hex` form.
+*/
+
+/// A number or its mnemonic, e.g. 15 or "MX".
+#[derive(Deserialize, Clone)]
+#[serde(untagged)]
+pub enum Code {
+    Number(u16),
+    Name(String),
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct MessageSpec {
+    pub id: Option<u16>,
+    pub opcode: Option<Code>,
+    pub rcode: Option<Code>,
+    /// The header bits to set: qr, aa, tc, rd, ra, z, ad and cd.
+    pub flags: Vec<String>,
+    #[serde(alias = "questions")]
+    pub question: Vec<QuestionSpec>,
+    #[serde(alias = "answers")]
+    pub answer: Vec<RecordSpec>,
+    pub authority: Vec<RecordSpec>,
+    pub additional: Vec<RecordSpec>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct QuestionSpec {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub r_type: Option<Code>,
+    pub class: Option<Code>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RecordSpec {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub r_type: Code,
+    pub class: Option<Code>,
+    #[serde(default)]
+    pub ttl: u32,
+    pub data: String,
+}
+
+impl MessageSpec {
+    /// Reads a JSON or TOML spec, told apart by the extension of `path`.
+    /// Files with another extension may hold either.
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        let text =
+            fs::read_to_string(path).map_err(|err| format!("Failed to read {}: {}", path, err))?;
+        let json =
+            || serde_json::from_str(&text).map_err(|err| format!("Invalid spec {}: {}", path, err));
+        let toml =
+            || toml::from_str(&text).map_err(|err| format!("Invalid spec {}: {}", path, err));
+        match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some("json") => json(),
+            Some("toml") => toml(),
+            _ => json().or_else(|_| toml()),
+        }
+    }
+
+    /// The message in wire format. The counts follow the sections, names
+    /// are written without compression.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        let mut header = Header::new_query(None);
+        if let Some(id) = self.id {
+            header.set_id(id);
+        }
+        let flags = header.flags_mut();
+        for flag in self.flags.iter() {
+            match flag.to_ascii_lowercase().as_str() {
+                "qr" => flags.set_response(),
+                "aa" => flags.set_aa(),
+                "tc" => flags.set_truncated(),
+                "rd" => flags.set_recursive(),
+                "ra" => flags.set_recursion_available(),
+                "z" => flags.set_z(),
+                "ad" => flags.set_authentic_data(),
+                "cd" => flags.set_checking_disabled(),
+                other => return Err(format!("Unknown header flag {}", other)),
+            }
+        }
+        if let Some(opcode) = &self.opcode {
+            flags.set_opcode(opcode_from(opcode)?);
+        }
+        if let Some(rcode) = &self.rcode {
+            flags.set_rcode(rcode_from(rcode)?);
+        }
+        let count = |len: usize| {
+            u16::try_from(len).map_err(|_| String::from("Too many entries in a section."))
+        };
+        header.set_q_count(count(self.question.len())?);
+        header.set_an_count(count(self.answer.len())?);
+        header.set_ns_count(count(self.authority.len())?);
+        header.set_ar_count(count(self.additional.len())?);
+
+        let mut bytes = header.to_bytes();
+        for q in self.question.iter() {
+            let r_type = match &q.r_type {
+                Some(r_type) => type_from(r_type)?,
+                None => RecordType::A,
+            };
+            let class = match &q.class {
+                Some(class) => class_from(class)?,
+                None => QClass::INET,
+            };
+            bytes.extend(Question::init(name_from(&q.name)?, r_type, class).to_bytes());
+        }
+        for rr in self
+            .answer
+            .iter()
+            .chain(self.authority.iter())
+            .chain(self.additional.iter())
+        {
+            bytes.extend(rr.to_answer()?.to_bytes());
+        }
+        Ok(bytes)
+    }
+}
+
+impl QuestionSpec {
+    /// A question written as `name [type] [class]`.
+    pub fn from_string(text: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = text.split_whitespace().collect();
+        match fields.as_slice() {
+            [name, rest @ ..] if rest.len() <= 2 => Ok(Self {
+                name: name.to_string(),
+                r_type: rest.first().map(|t| Code::Name(t.to_string())),
+                class: rest.get(1).map(|c| Code::Name(c.to_string())),
+            }),
+            _ => Err(format!(
+                "A question has to be \"name [type] [class]\", not {}",
+                text
+            )),
+        }
+    }
+}
+
+impl RecordSpec {
+    /// A record written like a line of a zone file, `name [ttl] [class]
+    /// type data`. Missing TTLs are zero, missing classes IN.
+    pub fn from_string(text: &str) -> Result<Self, String> {
+        let mut fields = text.split_whitespace().peekable();
+        let name = fields
+            .next()
+            .ok_or_else(|| String::from("A record needs a name."))?;
+        let ttl = match fields.peek().and_then(|ttl| ttl.parse::<u32>().ok()) {
+            Some(ttl) => {
+                fields.next();
+                ttl
+            }
+            None => 0,
+        };
+        let mut class = None;
+        if let Some(field) = fields.peek() {
+            if QClass::try_from_string(field).is_ok() && RecordType::try_from_string(field).is_err()
+            {
+                class = fields.next().map(|c| Code::Name(c.to_string()));
+            }
+        }
+        let r_type = fields
+            .next()
+            .ok_or_else(|| format!("The record {} has no type.", text))?;
+        Ok(Self {
+            name: name.to_string(),
+            r_type: Code::Name(r_type.to_string()),
+            class,
+            ttl,
+            data: fields.collect::<Vec<&str>>().join(" "),
+        })
+    }
+
+    fn to_answer(&self) -> Result<Answer, String> {
+        let r_type = type_from(&self.r_type)?;
+        let class = match &self.class {
+            Some(class) => class_from(class)?,
+            None => QClass::INET,
+        };
+        let data = data_from_string(&r_type, &self.data)?;
+        let length = u16::try_from(data.len())
+            .map_err(|_| format!("The data of {} is too long.", self.name))?;
+        Ok(Answer::new(
+            name_from(&self.name)?,
+            r_type,
+            class.code().to_be_bytes(),
+            self.ttl.to_be_bytes(),
+            length.to_be_bytes(),
+            data,
+        ))
+    }
+}
+
+fn name_from(name: &str) -> Result<Name, String> {
+    Name::from_string(name.to_string()).map_err(|err| format!("Invalid name {}: {}", name, err))
+}
+
+fn type_from(code: &Code) -> Result<RecordType, String> {
+    match code {
+        Code::Number(n) => Ok(RecordType::from_code(*n)),
+        Code::Name(name) => RecordType::try_from_string(name),
+    }
+}
+
+fn class_from(code: &Code) -> Result<QClass, String> {
+    match code {
+        Code::Number(n) => Ok(QClass::from_code(*n)),
+        Code::Name(name) => QClass::try_from_string(name),
+    }
+}
+
+/// Opcodes and RCODEs are four bit fields, given as a number or one of
+/// `names` in the order of their values.
+fn nibble_from(code: &Code, names: &[&str], what: &str) -> Result<u8, String> {
+    let value = match code {
+        Code::Number(n) => Some(*n),
+        Code::Name(name) => names
+            .iter()
+            .position(|known| known.eq_ignore_ascii_case(name))
+            .map(|pos| pos as u16)
+            .or_else(|| name.parse().ok()),
+    };
+    match value {
+        Some(n) if n < 16 => Ok(n as u8),
+        _ => Err(format!(
+            "Invalid {}, expected 0 to 15 or one of {:?}",
+            what, names
+        )),
+    }
+}
+
+fn opcode_from(code: &Code) -> Result<u8, String> {
+    let names = ["QUERY", "IQUERY", "STATUS", "", "NOTIFY", "UPDATE", "DSO"];
+    nibble_from(code, &names, "opcode")
+}
+
+fn rcode_from(code: &Code) -> Result<u8, String> {
+    let names = [
+        "NOERROR", "FORMERR", "SERVFAIL", "NXDOMAIN", "NOTIMP", "REFUSED", "YXDOMAIN", "YXRRSET",
+        "NXRRSET", "NOTAUTH", "NOTZONE",
+    ];
+    nibble_from(code, &names, "RCODE")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spec_to_bytes() {
+        let spec: MessageSpec = toml::from_str(
+            r#"
+            id = 4660
+            opcode = "NOTIFY"
+            flags = ["aa", "tc", "z"]
+
+            [[question]]
+            name = "example.com"
+            type = "SOA"
+
+            [[question]]
+            name = "version.bind"
+            type = 16
+            class = "CH"
+
+            [[authority]]
+            name = "example.com"
+            ttl = 60
+            type = "NS"
+            data = "ns1.example.com."
+            "#,
+        )
+        .unwrap();
+        let bytes = spec.to_bytes().unwrap();
+        assert_eq!(
+            &bytes[..12],
+            &[0x12, 0x34, 0x26, 0x40, 0, 2, 0, 0, 0, 1, 0, 0]
+        );
+        // The second question asks for TXT in class CHAOS.
+        assert_eq!(&bytes[29 + 14..29 + 18], &[0, 16, 0, 3]);
+        assert_eq!(bytes.len(), 47 + 13 + 10 + 17);
+
+        let bad: MessageSpec = serde_json::from_str(r#"{ "flags": ["xx"] }"#).unwrap();
+        assert!(bad.to_bytes().is_err());
+        assert!(serde_json::from_str::<MessageSpec>(r#"{ "idd": 1 }"#).is_err());
+    }
+
+    #[test]
+    fn test_record_from_string() {
+        let rr = RecordSpec::from_string("example.com. 300 IN MX 10 mail.example.com.").unwrap();
+        assert_eq!(rr.ttl, 300);
+        assert!(matches!(rr.class, Some(Code::Name(ref c)) if c == "IN"));
+        assert_eq!(rr.data, "10 mail.example.com.");
+        let bytes = rr.to_answer().unwrap().to_bytes();
+        assert_eq!(&bytes[13..23], &[0, 15, 0, 1, 0, 0, 1, 44, 0, 20]);
+
+        let rr = RecordSpec::from_string("example.com CH TYPE99 \\# 2 abcd").unwrap();
+        assert_eq!(rr.ttl, 0);
+        assert_eq!(rr.to_answer().unwrap().get_data(), &vec![0xab, 0xcd]);
+        assert!(RecordSpec::from_string("example.com 300").is_err());
+
+        let q = QuestionSpec::from_string("example.com AAAA").unwrap();
+        assert!(q.class.is_none());
+        assert!(QuestionSpec::from_string("a b c d").is_err());
+    }
+}
@@ -0,0 +1,77 @@
+use std::{fs, path::Path};
+
+use base64::{
+    alphabet,
+    engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig},
+    Engine,
+};
+use utility::from_hex;
+
+/// The raw message given on the command line. When `input` names a file,
+/// the file is read: text files may hold the message in any of the text
+/// forms, anything else is taken as the message itself.
+pub fn read_message(input: &str) -> Result<Vec<u8>, String> {
+    if !Path::new(input).is_file() {
+        return decode_text(input);
+    }
+    let bytes = fs::read(input).map_err(|err| format!("Failed to read {}: {}", input, err))?;
+    let is_text = bytes
+        .iter()
+        .all(|b| b.is_ascii_graphic() || b.is_ascii_whitespace());
+    match std::str::from_utf8(&bytes) {
+        Ok(text) if is_text && !text.trim().is_empty() => decode_text(text).or(Ok(bytes)),
+        _ => Ok(bytes),
+    }
+}
+
+/// A message written as hex digits, with or without whitespace between
+/// them, or as base64 in the standard or the URL safe alphabet, padded or
+/// not. A DoH GET URL or its `dns=` parameter (RFC 8484) works as well.
+pub fn decode_text(text: &str) -> Result<Vec<u8>, String> {
+    let text = match text.split_once("dns=") {
+        Some((_, param)) => param.split('&').next().unwrap_or_default(),
+        None => text,
+    };
+    let compact: String = text.split_whitespace().collect();
+    if compact.is_empty() {
+        return Err(String::from("There is no message to decode."));
+    }
+    if compact.bytes().all(|b| b.is_ascii_hexdigit()) {
+        // An odd number of digits may still be base64.
+        if let Ok(bytes) = from_hex(&compact) {
+            return Ok(bytes);
+        }
+    }
+    let alphabet = if compact.contains(['-', '_']) {
+        alphabet::URL_SAFE
+    } else {
+        alphabet::STANDARD
+    };
+    let config =
+        GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent);
+    GeneralPurpose::new(&alphabet, config)
+        .decode(compact)
+        .map_err(|err| format!("The message is neither hex nor base64: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_text() {
+        // The query for www.example.com A from RFC 8484.
+        let query = decode_text("AAABAAABAAAAAAAAA3d3dwdleGFtcGxlA2NvbQAAAQAB").unwrap();
+        assert_eq!(&query[..4], &[0, 0, 1, 0]);
+        assert_eq!(query.len(), 33);
+        let url = "https://dns.example.com/dns-query?dns=AAABAAABAAAAAAAAA3d3dwdleGFtcGxlA2NvbQAAAQAB&x=1";
+        assert_eq!(decode_text(url).unwrap(), query);
+        assert_eq!(
+            decode_text("c0af 0100\n0001").unwrap(),
+            vec![192, 175, 1, 0, 0, 1]
+        );
+        assert_eq!(decode_text("_-8=").unwrap(), vec![255, 239]);
+        assert!(decode_text("not a message!").is_err());
+        assert!(decode_text("  ").is_err());
+    }
+}
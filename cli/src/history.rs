@@ -0,0 +1,271 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use dns::{header_flags::RCODE, record::RecordType};
+use parsing::Response;
+use rusqlite::{params, Connection};
+
+use crate::iterative::normalize;
+
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS lookups (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL,
+    r_type INTEGER NOT NULL,
+    server TEXT NOT NULL,
+    looked_up_at INTEGER NOT NULL,
+    rcode INTEGER NOT NULL,
+    answers TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS lookups_name ON lookups (name, r_type, looked_up_at)";
+
+/// Every lookup askrs made, kept in SQLite to see how the answers for a
+/// name changed over time, like a small passive DNS database.
+///
+/// The answer records are stored in presentation format without their
+/// TTL, so two lookups with the same records compare equal.
+pub struct History {
+    conn: Connection,
+}
+
+/// A period in which the lookups of a name and type got the same answer.
+pub struct Period {
+    pub r_type: RecordType,
+    pub first_seen: String,
+    pub last_seen: String,
+    pub lookups: usize,
+    pub servers: Vec<String>,
+    pub rcode: u8,
+    pub answers: Vec<String>,
+    /// Records missing from the previous period, empty for the first one.
+    pub added: Vec<String>,
+    /// Records of the previous period which are gone.
+    pub removed: Vec<String>,
+}
+
+impl Period {
+    pub fn print(&self) {
+        let seen = if self.lookups == 1 {
+            self.first_seen.clone()
+        } else {
+            format!(
+                "{} - {} ({} lookups)",
+                self.first_seen, self.last_seen, self.lookups
+            )
+        };
+        println!("{} from {}", seen, self.servers.join(", "));
+        if self.rcode != 0 {
+            println!("\t{}", RCODE::from_byte(self.rcode).mnemonic());
+        } else if self.answers.is_empty() {
+            println!("\tNo records");
+        }
+        for rr in self.answers.iter() {
+            let mark = if self.added.contains(rr) { "+" } else { " " };
+            println!("\t{} {}", mark, rr);
+        }
+        for rr in self.removed.iter() {
+            println!("\t- {}", rr);
+        }
+    }
+}
+
+impl History {
+    pub fn open(path: &str) -> Result<Self, String> {
+        let conn = Connection::open(path)
+            .map_err(|err| format!("Failed to open history database {}: {}", path, err))?;
+        conn.execute_batch(SCHEMA)
+            .map_err(|err| format!("Failed to create history table in {}: {}", path, err))?;
+        Ok(Self { conn })
+    }
+
+    /// Records the answer `server` gave for the question of `resp`.
+    pub fn record(&self, resp: &Response, server: &str) -> Result<(), String> {
+        self.record_at(resp, server, unix_now())
+    }
+
+    fn record_at(&self, resp: &Response, server: &str, now: u64) -> Result<(), String> {
+        // Without a question there is no name to record the answer for.
+        let question = match resp.question() {
+            Some(question) => question,
+            None => return Ok(()),
+        };
+        let name = question
+            .get_name()
+            .get_string()
+            .map_err(|err| err.to_string())?;
+        let src = resp.get_bytes();
+        let mut answers: Vec<String> = resp
+            .answers()
+            .iter()
+            .map(|rr| {
+                format!(
+                    "{}. {} {}",
+                    normalize(&rr.get_name().get_string().unwrap_or_default()),
+                    rr.get_type().to_string(),
+                    rr.data_string(src.to_vec())
+                )
+            })
+            .collect();
+        answers.sort();
+        answers.dedup();
+        self.conn
+            .execute(
+                "INSERT INTO lookups (name, r_type, server, looked_up_at, rcode, answers)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    normalize(&name),
+                    question.get_type().code(),
+                    server,
+                    now,
+                    src[3] & 0b0000_1111,
+                    answers.join("\n"),
+                ],
+            )
+            .map_err(|err| format!("Failed to record lookup of {}: {}", name, err))?;
+        Ok(())
+    }
+
+    /// The periods in which the answers for `name` stayed the same, oldest
+    /// first and grouped by record type. Without `r_type` all types which
+    /// were looked up are returned.
+    pub fn changes(&self, name: &str, r_type: Option<&RecordType>) -> Result<Vec<Period>, String> {
+        let read_err = |err: rusqlite::Error| format!("Failed to read history: {}", err);
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT r_type, datetime(looked_up_at, 'unixepoch'), server, rcode, answers
+                FROM lookups WHERE name = ?1 AND (?2 IS NULL OR r_type = ?2)
+                ORDER BY r_type, looked_up_at, id",
+            )
+            .map_err(read_err)?;
+        let rows = stmt
+            .query_map(params![normalize(name), r_type.map(|t| t.code())], |row| {
+                Ok((
+                    row.get::<_, u16>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, u8>(3)?,
+                    row.get::<_, String>(4)?,
+                ))
+            })
+            .map_err(read_err)?;
+        let mut periods: Vec<Period> = vec![];
+        for row in rows {
+            let (code, seen, server, rcode, answers) = row.map_err(read_err)?;
+            let answers: Vec<String> = answers
+                .lines()
+                .filter(|l| !l.is_empty())
+                .map(String::from)
+                .collect();
+            let previous = periods.last_mut().filter(|p| p.r_type.code() == code);
+            match previous {
+                Some(p) if p.rcode == rcode && p.answers == answers => {
+                    p.last_seen = seen;
+                    p.lookups += 1;
+                    if !p.servers.contains(&server) {
+                        p.servers.push(server);
+                    }
+                }
+                previous => {
+                    let (added, removed) = match previous {
+                        Some(p) => (
+                            answers
+                                .iter()
+                                .filter(|rr| !p.answers.contains(rr))
+                                .cloned()
+                                .collect(),
+                            p.answers
+                                .iter()
+                                .filter(|rr| !answers.contains(rr))
+                                .cloned()
+                                .collect(),
+                        ),
+                        None => (vec![], vec![]),
+                    };
+                    periods.push(Period {
+                        r_type: RecordType::from_code(code),
+                        first_seen: seen.clone(),
+                        last_seen: seen,
+                        lookups: 1,
+                        servers: vec![server],
+                        rcode,
+                        answers,
+                        added,
+                        removed,
+                    });
+                }
+            }
+        }
+        Ok(periods)
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use dns::{answer::Answer, name::Name};
+    use parsing::Query;
+
+    use super::*;
+
+    fn response(name: &str, addrs: &[[u8; 4]]) -> Response {
+        let qry = Query::new(name.to_string(), RecordType::A, true);
+        let answers = addrs
+            .iter()
+            .map(|addr| {
+                Answer::new(
+                    Name::from_string(name.to_string()).unwrap(),
+                    RecordType::A,
+                    [0, 1],
+                    300u32.to_be_bytes(),
+                    [0, 4],
+                    addr.to_vec(),
+                )
+            })
+            .collect();
+        Response::synthesize(&qry, answers, false).unwrap()
+    }
+
+    #[test]
+    fn test_changes() {
+        let history = History::open(":memory:").unwrap();
+        let old = [192, 0, 2, 1];
+        let new = [198, 51, 100, 7];
+        history
+            .record_at(&response("example.com", &[old]), "google", 0)
+            .unwrap();
+        history
+            .record_at(&response("example.com", &[old]), "cloudflare", 3600)
+            .unwrap();
+        history
+            .record_at(&response("example.com", &[new, old]), "google", 7200)
+            .unwrap();
+        history
+            .record_at(&response("example.com", &[new]), "google", 86400)
+            .unwrap();
+        history
+            .record_at(&response("example.org", &[new]), "google", 86400)
+            .unwrap();
+
+        let periods = history.changes("Example.com.", None).unwrap();
+        assert_eq!(periods.len(), 3);
+        assert_eq!(periods[0].first_seen, "1970-01-01 00:00:00");
+        assert_eq!(periods[0].last_seen, "1970-01-01 01:00:00");
+        assert_eq!(periods[0].lookups, 2);
+        assert_eq!(periods[0].servers, vec!["google", "cloudflare"]);
+        assert_eq!(periods[0].answers, vec!["example.com. A 192.0.2.1"]);
+        assert_eq!(periods[1].added, vec!["example.com. A 198.51.100.7"]);
+        assert!(periods[1].removed.is_empty());
+        assert!(periods[2].added.is_empty());
+        assert_eq!(periods[2].removed, vec!["example.com. A 192.0.2.1"]);
+
+        assert!(history
+            .changes("example.com", Some(&RecordType::MX))
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,171 @@
+use std::{
+    fs,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+use dns::{answer::Answer, name::Name, record::RecordType};
+
+pub struct HostsEntry {
+    pub addr: IpAddr,
+    pub names: Vec<String>,
+}
+
+/// Static host name mappings as read from `hosts(5)`.
+pub struct HostsFile {
+    entries: Vec<HostsEntry>,
+}
+
+impl HostsFile {
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        match fs::read_to_string(path) {
+            Ok(content) => Ok(Self::parse(&content)),
+            Err(err) => Err(format!("Failed to read {}: {}", path, err)),
+        }
+    }
+
+    pub fn parse(content: &str) -> Self {
+        let mut entries: Vec<HostsEntry> = vec![];
+        for line in content.lines() {
+            let line = match line.find('#') {
+                Some(idx) => &line[..idx],
+                None => line,
+            };
+            let mut parts = line.split_whitespace();
+            let addr = match parts.next().map(|a| a.parse::<IpAddr>()) {
+                Some(Ok(addr)) => addr,
+                _ => continue,
+            };
+            let names: Vec<String> = parts.map(|n| n.to_ascii_lowercase()).collect();
+            if !names.is_empty() {
+                entries.push(HostsEntry { addr, names });
+            }
+        }
+        Self { entries }
+    }
+
+    /// Answers for `name` as a name server would return them. A and AAAA
+    /// queries match any name of an entry, PTR queries return the canonical
+    /// name of every entry with the reversed address.
+    pub fn lookup(&self, name: &str, r_type: &RecordType) -> Vec<Answer> {
+        let name = name.trim_end_matches('.').to_ascii_lowercase();
+        let mut res: Vec<Answer> = vec![];
+        for entry in self.entries.iter() {
+            let a_data: Vec<u8> = match (r_type, entry.addr) {
+                (RecordType::A, IpAddr::V4(ip)) if entry.names.contains(&name) => {
+                    ip.octets().to_vec()
+                }
+                (RecordType::AAAA, IpAddr::V6(ip)) if entry.names.contains(&name) => {
+                    ip.octets().to_vec()
+                }
+                (RecordType::PTR, addr) if parse_reverse_name(&name) == Some(addr) => {
+                    match Name::from_string(entry.names[0].clone()) {
+                        Ok(target) => target.get_bytes(),
+                        Err(_) => continue,
+                    }
+                }
+                _ => continue,
+            };
+            let owner = match Name::from_string(name.clone()) {
+                Ok(owner) => owner,
+                Err(_) => return vec![],
+            };
+            res.push(Answer::new(
+                owner,
+                r_type.clone(),
+                [0, 1],
+                [0, 0, 0, 0],
+                (a_data.len() as u16).to_be_bytes(),
+                a_data,
+            ));
+        }
+        res
+    }
+}
+
+/// Address encoded in a reverse lookup name below in-addr.arpa or ip6.arpa.
+pub fn parse_reverse_name(name: &str) -> Option<IpAddr> {
+    if let Some(rest) = name.strip_suffix(".in-addr.arpa") {
+        let mut octets: Vec<u8> = vec![];
+        for label in rest.split('.').rev() {
+            octets.push(label.parse::<u8>().ok()?);
+        }
+        let octets: [u8; 4] = octets.try_into().ok()?;
+        return Some(IpAddr::V4(Ipv4Addr::from(octets)));
+    }
+    if let Some(rest) = name.strip_suffix(".ip6.arpa") {
+        let mut nibbles: Vec<u8> = vec![];
+        for label in rest.split('.').rev() {
+            if label.len() != 1 {
+                return None;
+            }
+            nibbles.push(u8::from_str_radix(label, 16).ok()?);
+        }
+        if nibbles.len() != 32 {
+            return None;
+        }
+        let mut octets: [u8; 16] = [0; 16];
+        for (idx, pair) in nibbles.chunks(2).enumerate() {
+            octets[idx] = (pair[0] << 4) | pair[1];
+        }
+        return Some(IpAddr::V6(Ipv6Addr::from(octets)));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOSTS: &str = "127.0.0.1\tlocalhost
+::1 localhost ip6-localhost
+192.168.1.10  build.corp.example.com build  # ci runner
+not-an-address foo
+";
+
+    #[test]
+    fn test_lookup_address() {
+        let hosts = HostsFile::parse(HOSTS);
+        assert_eq!(hosts.lookup("localhost", &RecordType::A).len(), 1);
+        assert_eq!(hosts.lookup("LOCALHOST.", &RecordType::AAAA).len(), 1);
+        assert_eq!(hosts.lookup("build", &RecordType::A).len(), 1);
+        assert_eq!(hosts.lookup("ip6-localhost", &RecordType::A).len(), 0);
+        assert_eq!(hosts.lookup("foo", &RecordType::A).len(), 0);
+        assert_eq!(hosts.lookup("build", &RecordType::MX).len(), 0);
+    }
+
+    #[test]
+    fn test_lookup_reverse() {
+        let hosts = HostsFile::parse(HOSTS);
+        let answers = hosts.lookup("10.1.168.192.in-addr.arpa", &RecordType::PTR);
+        assert_eq!(answers.len(), 1);
+        let mut expected = vec![];
+        expected.extend(
+            Name::from_string("10.1.168.192.in-addr.arpa".to_string())
+                .unwrap()
+                .get_bytes(),
+        );
+        expected.extend([0, 12, 0, 1, 0, 0, 0, 0, 0, 24]);
+        expected.extend(
+            Name::from_string("build.corp.example.com".to_string())
+                .unwrap()
+                .get_bytes(),
+        );
+        assert_eq!(answers[0].to_bytes(), expected);
+    }
+
+    #[test]
+    fn test_parse_reverse_name() {
+        assert_eq!(
+            parse_reverse_name("1.0.0.127.in-addr.arpa"),
+            Some("127.0.0.1".parse().unwrap())
+        );
+        assert_eq!(
+            parse_reverse_name(
+                "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.ip6.arpa"
+            ),
+            Some("::1".parse().unwrap())
+        );
+        assert_eq!(parse_reverse_name("0.127.in-addr.arpa"), None);
+        assert_eq!(parse_reverse_name("example.com"), None);
+    }
+}
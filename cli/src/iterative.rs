@@ -0,0 +1,638 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    time::Duration,
+};
+
+use dns::{
+    answer::Answer,
+    header_flags::RCODE,
+    record::{AAAARecord, ARecord, NSRecord, RecordType},
+};
+use parsing::{byte_stream_parser::ByteStreamParser, Query, Response};
+
+use crate::outcome::Outcome;
+use crate::pcap::Capture;
+use crate::socket::{Exchange, RetryPolicy, UDPClient};
+
+/// IPv4 addresses of a.root-servers.net to m.root-servers.net.
+pub const ROOT_HINTS: [[u8; 4]; 13] = [
+    [198, 41, 0, 4],
+    [170, 247, 170, 2],
+    [192, 33, 4, 12],
+    [199, 7, 91, 13],
+    [192, 203, 230, 10],
+    [192, 5, 5, 241],
+    [192, 112, 36, 4],
+    [198, 97, 190, 53],
+    [192, 36, 148, 17],
+    [192, 58, 128, 30],
+    [193, 0, 14, 129],
+    [199, 7, 83, 42],
+    [202, 12, 27, 33],
+];
+
+/// Referrals followed for a single name before giving up.
+const MAX_REFERRALS: usize = 16;
+/// Nested lookups of name server addresses without glue.
+const MAX_DEPTH: usize = 4;
+
+/// Why an iterative lookup failed and the outcome of the run: no server
+/// answered, a response could not be parsed or the delegations did not
+/// lead to an answer.
+pub type Failure = (Outcome, String);
+
+/// A delegation from the authority and additional section of a response.
+pub struct Referral {
+    pub zone: String,
+    pub nameservers: Vec<String>,
+    pub glue: Vec<SocketAddr>,
+}
+
+/// A response received while resolving iteratively. `depth` is above zero
+/// for the lookups of name server addresses which were missing glue.
+pub struct TraceStep<'a> {
+    pub depth: usize,
+    pub qname: &'a str,
+    pub zone: &'a str,
+    pub server: SocketAddr,
+    pub rtt: Duration,
+    pub response: &'a Response,
+    pub referral: Option<&'a Referral>,
+}
+
+/// Resolves names by walking the delegation chain down from the root
+/// servers with non-recursive queries.
+pub struct IterativeResolver {
+    roots: Vec<SocketAddr>,
+    port: u16,
+    client: UDPClient,
+}
+
+impl IterativeResolver {
+    pub fn new(policy: RetryPolicy, verbose: bool) -> Self {
+        let roots = ROOT_HINTS
+            .iter()
+            .map(|ip| SocketAddr::new(Ipv4Addr::from(*ip).into(), 53))
+            .collect();
+        Self::with_roots(roots, 53, policy, verbose)
+    }
+
+    /// Starts at `roots` instead of the root hints and contacts the name
+    /// servers learned from referrals on `port`.
+    pub fn with_roots(
+        roots: Vec<SocketAddr>,
+        port: u16,
+        policy: RetryPolicy,
+        verbose: bool,
+    ) -> Self {
+        Self {
+            roots,
+            port,
+            client: UDPClient::new(policy, verbose),
+        }
+    }
+
+    /// Records every query sent and answer received in `capture`.
+    pub fn with_capture(self, capture: Option<Capture>) -> Self {
+        Self {
+            client: self.client.with_capture(capture),
+            ..self
+        }
+    }
+
+    /// Returns the final response of the authoritative servers for `name`,
+    /// which can carry an answer, NODATA or NXDOMAIN. Answer records for
+    /// names outside of the zone of these servers are removed, as they are
+    /// not responsible for them, e.g. the records of a CNAME target.
+    pub fn resolve(&self, name: &str, r_type: &RecordType) -> Result<Response, Failure> {
+        self.resolve_at_depth(name, r_type, 0, &mut |_| {})
+    }
+
+    /// Resolves `name` like [`IterativeResolver::resolve`] and calls `trace`
+    /// with every response received on the way, including the ones of the
+    /// lookups of name servers without glue.
+    pub fn trace<F>(
+        &self,
+        name: &str,
+        r_type: &RecordType,
+        mut trace: F,
+    ) -> Result<Response, Failure>
+    where
+        F: FnMut(&TraceStep),
+    {
+        self.resolve_at_depth(name, r_type, 0, &mut trace)
+    }
+
+    fn resolve_at_depth(
+        &self,
+        name: &str,
+        r_type: &RecordType,
+        depth: usize,
+        trace: &mut dyn FnMut(&TraceStep),
+    ) -> Result<Response, Failure> {
+        let qname = normalize(name);
+        let mut zone = String::new();
+        let mut servers = self.roots.to_vec();
+        for _ in 0..MAX_REFERRALS {
+            let (resp, ex) = self.query(&qname, r_type, &servers)?;
+            let rcode = resp.header().rcode();
+            // Authoritative answers are final, including NODATA at the apex
+            // which lists the NS records of the zone in the authority section.
+            let done = matches!(rcode, RCODE::NAMEERR)
+                || !resp.answers().is_empty()
+                || (resp.header().flags().is_aa() && !rcode.is_err());
+            let referral = if done || rcode.is_err() {
+                None
+            } else {
+                get_referral(&resp, self.port)
+            };
+            // Only referrals to a zone below the current one and above the
+            // name lead closer to the answer.
+            let (referral, upward) = match referral {
+                Some(r) if !is_closer(&r.zone, &zone, &qname) => (None, Some(r.zone)),
+                referral => (referral, None),
+            };
+            trace(&TraceStep {
+                depth,
+                qname: &qname,
+                zone: display_zone(&zone),
+                server: ex.server,
+                rtt: ex.rtt,
+                response: &resp,
+                referral: referral.as_ref(),
+            });
+            if done {
+                return in_bailiwick(resp, &zone).map_err(|err| (Outcome::Parse, err));
+            }
+            if rcode.is_err() {
+                return Err(lame(format!(
+                    "Lame delegation: the servers for {} answered {} with: {}",
+                    display_zone(&zone),
+                    qname,
+                    rcode
+                )));
+            }
+            let referral = match (referral, upward) {
+                (Some(referral), _) => referral,
+                (None, Some(upward)) => {
+                    return Err(lame(format!(
+                        "Lame delegation: the servers for {} referred {} to {} which is not closer to it.",
+                        display_zone(&zone),
+                        qname,
+                        display_zone(&upward)
+                    )))
+                }
+                (None, None) => {
+                    return Err(lame(format!(
+                        "Lame delegation: the servers for {} are not authoritative for {} and gave no referral.",
+                        display_zone(&zone),
+                        qname
+                    )))
+                }
+            };
+            servers = self.server_addresses(&referral, depth, trace)?;
+            zone = referral.zone;
+        }
+        Err(lame(format!(
+            "Gave up resolving {} after {} referrals.",
+            qname, MAX_REFERRALS
+        )))
+    }
+
+    fn query(
+        &self,
+        name: &str,
+        r_type: &RecordType,
+        servers: &[SocketAddr],
+    ) -> Result<(Response, Exchange), Failure> {
+        let msg = Query::new(name.to_string(), r_type.clone(), false).to_bytes();
+        let ex = self
+            .client
+            .exchange_with_any(msg, servers)
+            .map_err(|err| (Outcome::Transport, err))?;
+        let resp = ByteStreamParser::new(&ex.answer)
+            .parse_response()
+            .map_err(|err| (Outcome::Parse, err))?;
+        Ok((resp, ex))
+    }
+
+    /// Addresses of the name servers of a referral, taken from the glue or
+    /// resolved from the root when the referral came without glue.
+    fn server_addresses(
+        &self,
+        referral: &Referral,
+        depth: usize,
+        trace: &mut dyn FnMut(&TraceStep),
+    ) -> Result<Vec<SocketAddr>, Failure> {
+        if !referral.glue.is_empty() {
+            return Ok(referral.glue.to_vec());
+        }
+        if depth >= MAX_DEPTH {
+            return Err(lame(format!(
+                "Gave up looking up the name servers of {} after {} nested lookups.",
+                display_zone(&referral.zone),
+                MAX_DEPTH
+            )));
+        }
+        for ns in referral.nameservers.iter() {
+            // Servers inside the delegated zone can not be found without glue.
+            if is_subdomain(ns, &referral.zone) {
+                continue;
+            }
+            let resp = match self.resolve_at_depth(ns, &RecordType::A, depth + 1, trace) {
+                Ok(resp) => resp,
+                Err(_) => continue,
+            };
+            let addrs = addresses_of(resp.answers(), ns, self.port);
+            if !addrs.is_empty() {
+                return Ok(addrs);
+            }
+        }
+        Err(lame(format!(
+            "Lame delegation: no address found for any name server of {} ({}).",
+            display_zone(&referral.zone),
+            referral.nameservers.join(", ")
+        )))
+    }
+}
+
+/// Delegations which do not lead to an answer end the lookup like the
+/// SERVFAIL a recursive resolver would answer with.
+fn lame(err: String) -> Failure {
+    (Outcome::ServerFailure, err)
+}
+
+/// Reads the delegation of a response without answers. Glue is only
+/// taken for the name servers of the delegated zone.
+pub fn get_referral(resp: &Response, port: u16) -> Option<Referral> {
+    let src = resp.get_bytes();
+    let mut zone: Option<String> = None;
+    let mut nameservers: Vec<String> = vec![];
+    for rr in resp.authority().iter() {
+        if !matches!(rr.get_type(), RecordType::NS) {
+            continue;
+        }
+        let owner = normalize(&rr.get_name().get_string().ok()?);
+        if zone.get_or_insert(owner.clone()) != &owner {
+            continue;
+        }
+        let ns = NSRecord::from_bytes(rr.get_data().to_vec(), src.to_vec(), 0).ok()?;
+        nameservers.push(normalize(&ns.get_nsdname().get_string().ok()?));
+    }
+    let mut glue: Vec<SocketAddr> = vec![];
+    for ns in nameservers.iter() {
+        glue.extend(addresses_of(resp.additional(), ns, port));
+    }
+    Some(Referral {
+        zone: zone?,
+        nameservers,
+        glue,
+    })
+}
+
+/// `resp` without the answer records whose owner lies outside of `zone`.
+fn in_bailiwick(resp: Response, zone: &str) -> Result<Response, String> {
+    let inside = |rr: &Answer| {
+        let owner = rr.get_name().get_string().map(|n| normalize(&n));
+        owner.is_ok_and(|owner| is_subdomain(&owner, zone))
+    };
+    if resp.answers().iter().all(inside) {
+        return Ok(resp);
+    }
+    resp.retain_answers(inside)
+}
+
+/// IPv4 addresses before IPv6 addresses of `name` among `records`.
+fn addresses_of(records: &[Answer], name: &str, port: u16) -> Vec<SocketAddr> {
+    let mut v4: Vec<SocketAddr> = vec![];
+    let mut v6: Vec<SocketAddr> = vec![];
+    for rr in records.iter() {
+        let owner = rr.get_name().get_string().map(|n| normalize(&n));
+        if owner.as_deref() != Ok(name) || rr.get_data().len() != rr_length(rr.get_type()) {
+            continue;
+        }
+        match rr.get_type() {
+            RecordType::A => {
+                let ip = ARecord::from_bytes(rr.get_data().to_vec(), 0).as_ipv4();
+                v4.push(SocketAddr::new(IpAddr::V4(ip), port));
+            }
+            RecordType::AAAA => {
+                let ip = AAAARecord::from_bytes(rr.get_data().to_vec(), 0).as_ipv6();
+                v6.push(SocketAddr::new(IpAddr::V6(ip), port));
+            }
+            _ => continue,
+        }
+    }
+    v4.extend(v6);
+    v4
+}
+
+fn rr_length(r_type: &RecordType) -> usize {
+    match r_type {
+        RecordType::A => 4,
+        RecordType::AAAA => 16,
+        _ => 0,
+    }
+}
+
+/// Lower case name without the trailing dot, the root is the empty string.
+pub fn normalize(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+fn display_zone(zone: &str) -> &str {
+    if zone.is_empty() {
+        "."
+    } else {
+        zone
+    }
+}
+
+/// True if `child` lies strictly below `zone` and `qname` equals or lies below `child`.
+fn is_closer(child: &str, zone: &str, qname: &str) -> bool {
+    child != zone && is_subdomain(child, zone) && is_subdomain(qname, child)
+}
+
+/// True if `name` equals `zone` or lies below it. Every name is below the root.
+pub fn is_subdomain(name: &str, zone: &str) -> bool {
+    zone.is_empty() || name == zone || name.ends_with(&format!(".{}", zone))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::UdpSocket, thread, time::Duration};
+
+    use super::*;
+    use crate::test_util::{name, record};
+
+    struct Reply {
+        rcode: u8,
+        aa: bool,
+        answers: Vec<Answer>,
+        authority: Vec<Answer>,
+        additional: Vec<Answer>,
+    }
+
+    impl Reply {
+        fn new(rcode: u8, aa: bool) -> Self {
+            Self {
+                rcode,
+                aa,
+                answers: vec![],
+                authority: vec![],
+                additional: vec![],
+            }
+        }
+
+        fn referral(zone: &str, ns: &str, glue: Option<[u8; 4]>) -> Self {
+            let mut reply = Self::new(0, false);
+            reply
+                .authority
+                .push(record(zone, RecordType::NS, 3600, name(ns)));
+            if let Some(ip) = glue {
+                reply
+                    .additional
+                    .push(record(ns, RecordType::A, 3600, ip.to_vec()));
+            }
+            reply
+        }
+
+        fn address(owner: &str, ip: [u8; 4]) -> Self {
+            let mut reply = Self::new(0, true);
+            reply
+                .answers
+                .push(record(owner, RecordType::A, 3600, ip.to_vec()));
+            reply
+        }
+    }
+
+    /// Stand-in for the root, the com and net servers, the authoritative
+    /// server of example.com, provider.net and glueless.com and a lame server.
+    fn zone_data(server: u8, qname: &str) -> Reply {
+        match (server, qname) {
+            (1, n) if is_subdomain(n, "com") => {
+                Reply::referral("com", "ns.tld.test", Some([127, 0, 0, 2]))
+            }
+            (1, n) if is_subdomain(n, "net") => {
+                Reply::referral("net", "ns.tld.test", Some([127, 0, 0, 2]))
+            }
+            (2, n) if is_subdomain(n, "example.com") => {
+                Reply::referral("example.com", "ns.example.com", Some([127, 0, 0, 3]))
+            }
+            (2, n) if is_subdomain(n, "provider.net") => {
+                Reply::referral("provider.net", "ns.provider.net", Some([127, 0, 0, 3]))
+            }
+            (2, n) if is_subdomain(n, "glueless.com") => {
+                Reply::referral("glueless.com", "ns.provider.net", None)
+            }
+            (2, n) if is_subdomain(n, "lame.com") => {
+                Reply::referral("lame.com", "ns.lame.com", Some([127, 0, 0, 4]))
+            }
+            (2, n) if is_subdomain(n, "upward.com") => {
+                Reply::referral(".", "a.root.test", Some([127, 0, 0, 1]))
+            }
+            (3, "example.com") => {
+                let mut reply = Reply::new(0, true);
+                let ns = record("example.com", RecordType::NS, 3600, name("ns.example.com"));
+                reply.authority.push(ns);
+                reply
+            }
+            (3, "alias.example.com") => {
+                let mut reply = Reply::new(0, true);
+                let cname = record(
+                    "alias.example.com",
+                    RecordType::CNAME,
+                    3600,
+                    name("www.glueless.com"),
+                );
+                reply.answers.push(cname);
+                let forged = record("www.glueless.com", RecordType::A, 3600, vec![6, 6, 6, 6]);
+                reply.answers.push(forged);
+                reply
+            }
+            (3, "www.example.com") => Reply::address("www.example.com", [93, 184, 216, 34]),
+            (3, "ns.provider.net") => Reply::address("ns.provider.net", [127, 0, 0, 3]),
+            (3, "www.glueless.com") => Reply::address("www.glueless.com", [192, 0, 2, 1]),
+            (4, _) => Reply::new(5, false),
+            _ => Reply::new(3, true),
+        }
+    }
+
+    fn handle(server: u8, query: &[u8]) -> Vec<u8> {
+        let query = query.to_vec();
+        let mut parser = ByteStreamParser::new(&query);
+        parser.parse_dns_header().unwrap();
+        let question = parser.parse_question().unwrap();
+        let qname = question
+            .get_name()
+            .get_string()
+            .unwrap()
+            .to_ascii_lowercase();
+        // Authoritative servers do not offer recursion.
+        let reply = if query[2] & 1 == 1 {
+            Reply::new(5, false)
+        } else {
+            zone_data(server, &qname)
+        };
+        let mut res = query[0..2].to_vec();
+        res.push(0b1000_0000 | if reply.aa { 0b100 } else { 0 });
+        res.push(reply.rcode);
+        res.extend([0, 1]);
+        res.extend((reply.answers.len() as u16).to_be_bytes());
+        res.extend((reply.authority.len() as u16).to_be_bytes());
+        res.extend((reply.additional.len() as u16).to_be_bytes());
+        res.extend(query[12..].to_vec());
+        for rr in reply
+            .answers
+            .iter()
+            .chain(reply.authority.iter())
+            .chain(reply.additional.iter())
+        {
+            res.extend(rr.to_bytes());
+        }
+        res
+    }
+
+    /// Binds the four stand-in servers to 127.0.0.1 to 127.0.0.4 on one port.
+    fn spawn_servers() -> u16 {
+        loop {
+            let root = UdpSocket::bind("127.0.0.1:0").unwrap();
+            let port = root.local_addr().unwrap().port();
+            let others: Result<Vec<UdpSocket>, _> = (2..=4)
+                .map(|n| UdpSocket::bind((Ipv4Addr::new(127, 0, 0, n), port)))
+                .collect();
+            let others = match others {
+                Ok(others) => others,
+                Err(_) => continue,
+            };
+            for (idx, socket) in [root].into_iter().chain(others).enumerate() {
+                thread::spawn(move || {
+                    let mut buf = [0; 512];
+                    while let Ok((len, src)) = socket.recv_from(&mut buf) {
+                        let answer = handle(idx as u8 + 1, &buf[..len]);
+                        socket.send_to(&answer, src).unwrap();
+                    }
+                });
+            }
+            return port;
+        }
+    }
+
+    fn resolver() -> IterativeResolver {
+        let port = spawn_servers();
+        let root = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), port);
+        let policy = RetryPolicy::new(1, Duration::from_millis(500), 2);
+        IterativeResolver::with_roots(vec![root], port, policy, false)
+    }
+
+    fn first_address(resp: &Response) -> Ipv4Addr {
+        ARecord::from_bytes(resp.answers()[0].get_data().to_vec(), 0).as_ipv4()
+    }
+
+    #[test]
+    fn test_resolve_with_glue() {
+        let resp = resolver()
+            .resolve("WWW.example.com.", &RecordType::A)
+            .unwrap();
+        assert_eq!(first_address(&resp), Ipv4Addr::new(93, 184, 216, 34));
+    }
+
+    #[test]
+    fn test_resolve_missing_glue() {
+        let resp = resolver()
+            .resolve("www.glueless.com", &RecordType::A)
+            .unwrap();
+        assert_eq!(first_address(&resp), Ipv4Addr::new(192, 0, 2, 1));
+    }
+
+    #[test]
+    fn test_resolve_nxdomain() {
+        let resp = resolver()
+            .resolve("missing.example.com", &RecordType::A)
+            .unwrap();
+        assert!(matches!(resp.header().rcode(), RCODE::NAMEERR));
+        assert!(resp.header().flags().is_aa());
+    }
+
+    #[test]
+    fn test_resolve_nodata_at_apex() {
+        let resp = resolver().resolve("example.com", &RecordType::MX).unwrap();
+        assert!(matches!(resp.header().rcode(), RCODE::NOERR));
+        assert!(resp.answers().is_empty());
+        assert_eq!(resp.authority().len(), 1);
+    }
+
+    #[test]
+    fn test_out_of_bailiwick_answers() {
+        let resp = resolver()
+            .resolve("alias.example.com", &RecordType::A)
+            .unwrap();
+        assert_eq!(resp.answers().len(), 1);
+        assert!(matches!(resp.answers()[0].get_type(), RecordType::CNAME));
+        assert_eq!(resp.header().an_count(), 1);
+    }
+
+    #[test]
+    fn test_lame_delegation() {
+        let resolver = resolver();
+        let (outcome, err) = resolver
+            .resolve("www.lame.com", &RecordType::A)
+            .err()
+            .unwrap();
+        assert_eq!(outcome, Outcome::ServerFailure);
+        assert!(err.starts_with("Lame delegation: the servers for lame.com"));
+        let (_, err) = resolver
+            .resolve("www.upward.com", &RecordType::A)
+            .err()
+            .unwrap();
+        assert!(err.contains("which is not closer to it"));
+    }
+
+    #[test]
+    fn test_unreachable_roots() {
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let root = silent.local_addr().unwrap();
+        let policy = RetryPolicy::new(1, Duration::from_millis(50), 2);
+        let resolver = IterativeResolver::with_roots(vec![root], root.port(), policy, false);
+        let (outcome, _) = resolver
+            .resolve("example.com", &RecordType::A)
+            .err()
+            .unwrap();
+        assert_eq!(outcome, Outcome::Transport);
+    }
+
+    #[test]
+    fn test_trace_steps() {
+        let mut steps: Vec<(usize, String, String)> = vec![];
+        let resp = resolver()
+            .trace("www.glueless.com", &RecordType::A, |step| {
+                let next = step
+                    .referral
+                    .map(|r| r.zone.to_string())
+                    .unwrap_or_default();
+                steps.push((step.depth, step.zone.to_string(), next));
+            })
+            .unwrap();
+        assert_eq!(first_address(&resp), Ipv4Addr::new(192, 0, 2, 1));
+        let expected = [
+            (0, ".", "com"),
+            (0, "com", "glueless.com"),
+            (1, ".", "net"),
+            (1, "net", "provider.net"),
+            (1, "provider.net", ""),
+            (0, "glueless.com", ""),
+        ];
+        assert_eq!(steps.len(), expected.len());
+        for (step, (depth, zone, next)) in steps.iter().zip(expected) {
+            assert_eq!(step, &(depth, zone.to_string(), next.to_string()));
+        }
+    }
+
+    #[test]
+    fn test_is_subdomain() {
+        assert!(is_subdomain("www.example.com", "example.com"));
+        assert!(is_subdomain("example.com", "example.com"));
+        assert!(is_subdomain("example.com", ""));
+        assert!(!is_subdomain("badexample.com", "example.com"));
+    }
+}
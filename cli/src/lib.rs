@@ -1,27 +1,107 @@
+use std::{
+    cell::RefCell,
+    collections::HashSet,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_client::AsyncUDPClient;
+use batch::{read_batch, run_batch, BatchAnswer, BatchError, BatchLine, Lookup, OutputOrder};
+use cache::{
+    cached_lookup, lock, upstream, Cache, DEFAULT_CACHE_SIZE, DEFAULT_MAX_STALE, ITERATIVE,
+};
+use cache_db::CacheDb;
+use chain::{follow_chain, Chain, DEFAULT_MAX_CHAIN};
 use clap::ArgAction;
 use clap::Parser as clapParser;
+use clap::Subcommand;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, ValueSource};
+use config::{Config, Defaults};
+use craft::{MessageSpec, QuestionSpec, RecordSpec};
+use decode::read_message;
 use dns::record::RecordType;
+use history::History;
+use hosts::HostsFile;
+use iterative::{IterativeResolver, TraceStep};
+use outcome::Outcome;
+use output::{print_response, Origin, OutputFormat};
 use parsing::byte_stream_parser::ByteStreamParser;
-use parsing::Query;
-use socket::DNSSocket;
+use parsing::explain::explain;
+use parsing::{Query, Response};
+use pcap::{read_pairs, Capture, Message, PcapWriter};
+use resolv_conf::ResolvConf;
+use socket::{DNSSocket, Exchange, RetryPolicy};
 
+pub mod async_client;
+pub mod batch;
+pub mod cache;
+pub mod cache_db;
+pub mod chain;
+pub mod config;
+pub mod craft;
+pub mod decode;
+pub mod history;
+pub mod hosts;
+pub mod iterative;
+pub mod outcome;
+pub mod output;
+pub mod pcap;
+pub mod resolv_conf;
 pub mod socket;
+#[cfg(test)]
+mod test_util;
 
 #[derive(clapParser, Debug)]
 #[clap(name = "askrs")]
 #[clap(author = "philmish")]
 #[clap(version = "0.1")]
 #[clap(about = "CLI tool for requesting dns records.", long_about = None)]
+#[clap(subcommand_negates_reqs = true)]
 struct Flags {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
     /// Target adress or domain to request records for.
-    #[clap(short, long)]
-    uri: String,
+    #[clap(short, long, required_unless_present = "batch")]
+    uri: Option<String>,
+
+    /// Read lookups from a file (or - for stdin), one `domain [TYPE] [@server]` per line.
+    #[clap(long)]
+    batch: Option<String>,
+
+    /// Maximum number of batch lookups in flight.
+    #[clap(long, default_value = "16")]
+    concurrency: usize,
+
+    /// Print batch results in input order or as they complete (input, completion).
+    #[clap(long, default_value = "input")]
+    order: String,
 
-    /// DNS server to use for request.
-    #[clap(short, long, default_value = "google")]
+    /// DNS server to use for request (system, google, cloudflare, quad9 or a server of the
+    /// configuration file).
+    #[clap(short, long, default_value = "system")]
     server: String,
 
-    /// Record type to request (A, AAAA, MX, CNAME)
+    /// Configuration file with server profiles and defaults for these options.
+    /// Defaults to ~/.config/askrs/config.toml if it exists.
+    #[clap(long, global = true, value_name = "PATH")]
+    config: Option<String>,
+
+    /// Resolver configuration used by the system server.
+    #[clap(long = "resolv-conf", default_value = "/etc/resolv.conf")]
+    resolv_conf: String,
+
+    /// Answer A, AAAA and PTR queries from the hosts file before querying the network.
+    #[clap(long = "hosts", action = ArgAction::SetTrue)]
+    use_hosts: bool,
+
+    /// Hosts file consulted with --hosts.
+    #[clap(long = "hosts-file", default_value = "/etc/hosts")]
+    hosts_file: String,
+
+    /// Record types to request (A, AAAA, MX, CNAME, PTR), comma separated and looked up
+    /// in parallel. ALL_COMMON stands for A, AAAA, CNAME, MX, NS and SOA.
     #[clap(long, default_value = "A")]
     record: String,
 
@@ -29,49 +109,1101 @@ struct Flags {
     #[clap(short = 'r', long = "recursion_desired", action = ArgAction::SetTrue)]
     rd: bool,
 
+    /// Resolve the uri from the root servers down instead of asking a recursive server.
+    #[clap(long, action = ArgAction::SetTrue)]
+    iterative: bool,
+
+    /// Resolve iteratively and print every referral on the way, like dig +trace.
+    #[clap(long, action = ArgAction::SetTrue)]
+    trace: bool,
+
+    /// Maximum number of RRsets kept in the in-memory cache, 0 disables caching.
+    #[clap(long = "cache-size", default_value_t = DEFAULT_CACHE_SIZE)]
+    cache_size: usize,
+
+    /// Seconds expired records are kept to answer with when no server responds, 0 disables.
+    #[clap(long = "max-stale", default_value_t = DEFAULT_MAX_STALE)]
+    max_stale: u64,
+
+    /// SQLite database which keeps responses across runs for as long as their TTL allows.
+    #[clap(long = "cache-db", global = true)]
+    cache_db: Option<String>,
+
+    /// SQLite database every lookup is recorded in, read by the history command.
+    #[clap(long = "history-db", global = true)]
+    history_db: Option<String>,
+
+    /// Maximum number of CNAME and DNAME links to follow, 0 prints the first answer as is.
+    #[clap(long = "max-chain", default_value_t = DEFAULT_MAX_CHAIN)]
+    max_chain: usize,
+
+    /// Output format of responses.
+    #[clap(long, global = true, default_value = "text", possible_values = ["text", "json", "rfc8427", "dig"])]
+    format: String,
+
+    /// Record every query sent and answer received in a pcap file.
+    #[clap(long = "write-pcap", value_name = "PATH")]
+    write_pcap: Option<String>,
+
+    /// Print an annotated hex dump of the query and the response.
+    #[clap(long, global = true, action = ArgAction::SetTrue)]
+    explain: bool,
+
+    /// Print only the data of the answer records, one per line.
+    #[clap(long, global = true, action = ArgAction::SetTrue)]
+    short: bool,
+
     /// Verbose Output
     #[clap(short = 'v', long = "verbose", action = ArgAction::SetTrue)]
     verbose: bool,
+
+    /// Seconds to wait for an answer on the first attempt, doubled on every retry.
+    /// Defaults to the resolv.conf timeout for the system server, 5 otherwise.
+    #[clap(long)]
+    timeout: Option<u64>,
+
+    /// Number of passes over all server addresses before giving up.
+    /// Defaults to the resolv.conf attempts for the system server, 2 otherwise.
+    #[clap(long)]
+    attempts: Option<u32>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Delete responses which expired more than --max-stale ago from the --cache-db and compact it.
+    Vacuum,
+    /// Show how the answers for a domain changed over time in the --history-db.
+    History {
+        domain: String,
+        /// Only show lookups of this record type.
+        #[clap(long = "type")]
+        r_type: Option<String>,
+    },
+    /// Parse a raw message without sending a query, given as hex, base64 or
+    /// base64url (e.g. the dns= parameter of a DoH URL) or as a binary file.
+    Decode { message: String },
+    /// Read the DNS messages sent over UDP or TCP port 53 from a pcap or
+    /// pcapng capture and pair every query with its response.
+    Pcap { file: String },
+    /// Build a message field by field, from a JSON or TOML spec and the
+    /// options below, send it to the --server and print the response.
+    Craft {
+        /// JSON or TOML file describing the message, the options override or add to it.
+        #[clap(long)]
+        spec: Option<String>,
+        /// Message ID, 49327 if neither given here nor in the spec.
+        #[clap(long)]
+        id: Option<u16>,
+        /// Opcode as a number or QUERY, IQUERY, STATUS, NOTIFY, UPDATE, DSO.
+        #[clap(long)]
+        opcode: Option<String>,
+        /// RCODE as a number or a name like NXDOMAIN.
+        #[clap(long)]
+        rcode: Option<String>,
+        /// Header bits to set, comma separated: qr, aa, tc, rd, ra, z, ad, cd.
+        #[clap(long, use_value_delimiter = true)]
+        flags: Vec<String>,
+        /// A question as "name [TYPE] [CLASS]", may be repeated.
+        #[clap(long = "question", value_name = "QUESTION")]
+        questions: Vec<String>,
+        /// An answer record as "name [TTL] [CLASS] TYPE data", may be repeated.
+        #[clap(long = "answer", value_name = "RECORD")]
+        answers: Vec<String>,
+        /// An authority record, like --answer.
+        #[clap(long = "authority", value_name = "RECORD")]
+        authority: Vec<String>,
+        /// An additional record, like --answer.
+        #[clap(long = "additional", value_name = "RECORD")]
+        additional: Vec<String>,
+    },
 }
 
 impl Flags {
-    pub fn get_rtype(&self) -> RecordType {
-        RecordType::from_string(self.record.clone())
+    /// The record types of --record in the given order, without repeats.
+    pub fn get_rtypes(&self) -> Result<Vec<RecordType>, String> {
+        let mut rtypes: Vec<RecordType> = vec![];
+        for name in self.record.split(',').map(str::trim) {
+            let parsed = if name.eq_ignore_ascii_case("ALL_COMMON") {
+                COMMON_TYPES.to_vec()
+            } else {
+                vec![RecordType::try_from_string(name)?]
+            };
+            for rtype in parsed {
+                if !rtypes.iter().any(|known| known.code() == rtype.code()) {
+                    rtypes.push(rtype);
+                }
+            }
+        }
+        Ok(rtypes)
+    }
+
+    pub fn get_uri(&self) -> String {
+        self.uri.clone().unwrap_or_default()
     }
 
-    pub fn get_server(&self) -> DNSSocket {
-        DNSSocket::from_string(&self.server)
+    pub fn uses_system_resolver(&self) -> bool {
+        self.server == "system"
+    }
+
+    pub fn get_resolv_conf(&self) -> ResolvConf {
+        // Batch lines can switch to the system server on their own.
+        if !self.uses_system_resolver() && self.batch.is_none() {
+            return ResolvConf::default();
+        }
+        ResolvConf::from_file(&self.resolv_conf).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            ResolvConf::default()
+        })
+    }
+
+    /// Takes the options not given on the command line from `defaults`.
+    pub fn apply_defaults(
+        &mut self,
+        defaults: &Defaults,
+        matches: &ArgMatches,
+    ) -> Result<(), String> {
+        let unset = |id: &str| matches.value_source(id) != Some(ValueSource::CommandLine);
+        if let (Some(server), true) = (&defaults.server, unset("server")) {
+            self.server = server.clone();
+        }
+        if let (Some(record), true) = (&defaults.record, unset("record")) {
+            self.record = record.clone();
+        }
+        if let (Some(rd), true) = (defaults.recursion_desired, unset("rd")) {
+            self.rd = rd;
+        }
+        if let (Some(hosts), true) = (defaults.hosts, unset("use-hosts")) {
+            self.use_hosts = hosts;
+        }
+        if let (Some(format), true) = (&defaults.format, unset("format")) {
+            OutputFormat::from_string(format)?;
+            self.format = format.clone();
+        }
+        // An output format on the command line overrides short output.
+        if let (Some(short), true) = (defaults.short, unset("short") && unset("format")) {
+            self.short = short;
+        }
+        if unset("timeout") {
+            self.timeout = self.timeout.or(defaults.timeout);
+        }
+        if unset("attempts") {
+            self.attempts = self.attempts.or(defaults.attempts);
+        }
+        if let (Some(max_chain), true) = (defaults.max_chain, unset("max-chain")) {
+            self.max_chain = max_chain;
+        }
+        Ok(())
+    }
+
+    pub fn get_retry_policy(&self, conf: &ResolvConf) -> RetryPolicy {
+        let defaults = RetryPolicy::default();
+        let (timeout, attempts) = if self.uses_system_resolver() {
+            (Duration::from_secs(conf.timeout), conf.attempts)
+        } else {
+            (defaults.timeout, defaults.attempts)
+        };
+        RetryPolicy::new(
+            self.attempts.unwrap_or(attempts),
+            self.timeout.map(Duration::from_secs).unwrap_or(timeout),
+            defaults.backoff,
+        )
+    }
+
+    /// Names to query for the requested uri.
+    pub fn get_names(&self, conf: &ResolvConf) -> Vec<String> {
+        names_for(&self.get_uri(), &self.server, conf)
+    }
+}
+
+/// The types looked up for --record ALL_COMMON.
+const COMMON_TYPES: [RecordType; 6] = [
+    RecordType::A,
+    RecordType::AAAA,
+    RecordType::CNAME,
+    RecordType::MX,
+    RecordType::NS,
+    RecordType::SOA,
+];
+
+/// Only the system server applies the search list from resolv.conf.
+fn names_for(uri: &str, server: &str, conf: &ResolvConf) -> Vec<String> {
+    if server == "system" {
+        conf.candidate_names(uri)
+    } else {
+        vec![uri.to_string()]
     }
 }
 
 pub struct CLI {
     flags: Flags,
+    resolv_conf: ResolvConf,
+    cache: Arc<Mutex<Cache>>,
+    cache_db: Option<CacheDb>,
+    /// The upstreams whose responses were loaded from the cache database.
+    loaded: RefCell<HashSet<String>>,
+    history: Option<History>,
+    format: OutputFormat,
+    capture: Option<Capture>,
+    config: Config,
 }
 
 impl CLI {
+    /// Parses the command line and reads the configuration file. Invalid
+    /// configurations and capture files which can not be created end the
+    /// process like invalid options do.
     pub fn init() -> Self {
-        let flags = Flags::parse();
-        Self { flags }
+        let matches = Flags::command().get_matches();
+        let mut flags = Flags::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+        let config = Config::load(flags.config.as_deref())
+            .and_then(|config| {
+                flags.apply_defaults(&config.defaults, &matches)?;
+                Ok(config)
+            })
+            .unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                std::process::exit(Outcome::Failure.code())
+            });
+        let resolv_conf = flags.get_resolv_conf();
+        let cache =
+            Cache::new(flags.cache_size).with_max_stale(Duration::from_secs(flags.max_stale));
+        let cache_db = flags
+            .cache_db
+            .as_ref()
+            .and_then(|path| CacheDb::open(path).map_err(|err| eprintln!("{}", err)).ok());
+        let history = flags
+            .history_db
+            .as_ref()
+            .and_then(|path| History::open(path).map_err(|err| eprintln!("{}", err)).ok());
+        // Without the capture file the queries would go unrecorded.
+        let capture = flags.write_pcap.as_ref().map(|path| {
+            let writer = PcapWriter::create(path).unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                std::process::exit(Outcome::Failure.code())
+            });
+            Arc::new(Mutex::new(writer))
+        });
+        let format = if flags.short {
+            OutputFormat::Short
+        } else {
+            // clap only accepts the known formats, the defaults are checked.
+            OutputFormat::from_string(&flags.format).unwrap()
+        };
+        Self {
+            flags,
+            resolv_conf,
+            cache: Arc::new(Mutex::new(cache)),
+            cache_db,
+            loaded: RefCell::new(HashSet::new()),
+            history,
+            format,
+            capture,
+            config,
+        }
+    }
+
+    /// The server called `name`: the resolv.conf servers for `system`, a
+    /// profile of the configuration file, a preset or an address.
+    fn server(&self, name: &str) -> Result<DNSSocket, String> {
+        if name == "system" {
+            return Ok(DNSSocket::from_resolv_conf(&self.resolv_conf));
+        }
+        self.config
+            .server(name)
+            .unwrap_or_else(|| DNSSocket::from_string(name))
     }
 
-    fn send_query(&self, q: Query, srv: DNSSocket, verbose: bool) -> Vec<u8> {
-        let client = socket::UDPClient {};
-        let msg = q.to_bytes();
-        let a = client.send_and_recieve(msg, srv).unwrap();
+    /// Keeps `resp` of the upstream `server` in the cache database, if it
+    /// is used. Answers from the cache are not stored again.
+    fn store(&self, resp: &Response, server: &str) {
+        if let Some(db) = &self.cache_db {
+            if let Err(err) = db.store(resp, server) {
+                eprintln!("{}", err);
+            }
+        }
+    }
+
+    /// Loads the responses of the upstream `server` from the cache database,
+    /// if it is used, before it is first asked.
+    fn load_cached(&self, server: &str) {
+        let db = match &self.cache_db {
+            Some(db) => db,
+            None => return,
+        };
+        if !self.loaded.borrow_mut().insert(server.to_string()) {
+            return;
+        }
+        if let Err(err) = db.load_into(&mut lock(&self.cache), server) {
+            eprintln!("{}", err);
+        }
+    }
+
+    /// Adds the final answer of a lookup to the history, if it is used.
+    fn record(&self, resp: &Response, origin: &Origin) {
+        if let Some(history) = &self.history {
+            if let Err(err) = history.record(resp, &origin.server) {
+                eprintln!("{}", err);
+            }
+        }
+    }
+
+    fn run_history(&self, domain: &str, r_type: &Option<String>) -> Outcome {
+        let history = match &self.history {
+            Some(history) => history,
+            None => return fail("history needs the --history-db to read lookups from."),
+        };
+        let r_type = match r_type.as_deref().map(RecordType::try_from_string) {
+            Some(Err(err)) => return fail(err),
+            r_type => r_type.and_then(Result::ok),
+        };
+        let periods = match history.changes(domain, r_type.as_ref()) {
+            Ok(periods) => periods,
+            Err(err) => return fail(err),
+        };
+        if periods.is_empty() {
+            println!("No lookups of {} recorded.", domain);
+            return Outcome::NoData;
+        }
+        let mut current: Option<u16> = None;
+        for period in periods.iter() {
+            if current != Some(period.r_type.code()) {
+                if current.is_some() {
+                    println!();
+                }
+                println!("{} {}:", domain, period.r_type.to_string());
+                current = Some(period.r_type.code());
+            }
+            period.print();
+        }
+        Outcome::Answer
+    }
+
+    fn run_vacuum(&self) -> Outcome {
+        let db = match &self.cache_db {
+            Some(db) => db,
+            None => return fail("vacuum needs the --cache-db to clean up."),
+        };
+        match db
+            .evict(self.flags.max_stale)
+            .and_then(|deleted| Ok((deleted, db.len()?)))
+        {
+            Ok((deleted, kept)) => {
+                println!(
+                    "Deleted {} expired responses, {} responses left.",
+                    deleted, kept
+                );
+                Outcome::Answer
+            }
+            Err(err) => fail(err),
+        }
+    }
+
+    fn run_decode(&self, message: &str) -> Outcome {
+        let bytes = match read_message(message) {
+            Ok(bytes) => bytes,
+            Err(err) => return fail(err),
+        };
+        if self.flags.explain {
+            self.diagnostic(format!("{}\n", explain(&bytes)));
+        }
+        match ByteStreamParser::new(&bytes).parse_response() {
+            Ok(resp) => {
+                let source = if Path::new(message).is_file() {
+                    message
+                } else {
+                    "command line"
+                };
+                self.print_response(&resp, &Origin::new(source, None));
+                Outcome::Answer
+            }
+            Err(err) => {
+                eprintln!("Failed to parse the message: {}", err);
+                Outcome::Parse
+            }
+        }
+    }
+
+    /// Exits with a parse error if any message of the capture could not
+    /// be parsed.
+    fn run_pcap(&self, file: &str) -> Outcome {
+        let pairs = match read_pairs(file) {
+            Ok(pairs) => pairs,
+            Err(err) => return fail(err),
+        };
+        let mut outcome = Outcome::Answer;
+        let start = pairs
+            .iter()
+            .filter_map(|pair| pair.query.as_ref().or(pair.response.as_ref()))
+            .map(|msg| msg.time)
+            .min()
+            .unwrap_or_default();
+        for pair in pairs.iter() {
+            // Every pair has at least one message.
+            let first = match pair.query.as_ref().or(pair.response.as_ref()) {
+                Some(msg) => msg,
+                None => continue,
+            };
+            let (client, server) = if pair.query.is_some() {
+                (first.src, first.dst)
+            } else {
+                (first.dst, first.src)
+            };
+            if matches!(self.format, OutputFormat::Text | OutputFormat::Dig) {
+                let outcome = match (&pair.query, pair.latency()) {
+                    (None, _) => "response without query".to_string(),
+                    (Some(_), Some(latency)) => {
+                        format!("{:.3} ms", latency.as_secs_f64() * 1000.0)
+                    }
+                    (Some(_), None) => "no response".to_string(),
+                };
+                println!(
+                    ";; {:.6} {} -> {} {} ID {}: {}",
+                    first.time.saturating_sub(start).as_secs_f64(),
+                    client,
+                    server,
+                    first.transport,
+                    first.id(),
+                    outcome
+                );
+            }
+            // Unanswered queries are printed themselves.
+            let msg: &Message = pair.response.as_ref().unwrap_or(first);
+            match ByteStreamParser::new(&msg.bytes).parse_response() {
+                Ok(resp) => {
+                    let origin = Origin::new(&server.to_string(), pair.latency());
+                    self.print_response(&resp, &origin)
+                }
+                Err(err) => {
+                    eprintln!("Failed to parse the message from {}: {}", msg.src, err);
+                    outcome = Outcome::Parse;
+                }
+            }
+        }
+        outcome
+    }
+
+    /// Builds the message described by the spec file and the options of
+    /// the craft command, sends it and prints the response.
+    fn run_craft(&self, command: &Command) -> Outcome {
+        let msg = match self.craft_spec(command).and_then(|spec| spec.to_bytes()) {
+            Ok(msg) => msg,
+            Err(err) => return fail(err),
+        };
+        let srv = match self.server(&self.flags.server) {
+            Ok(srv) => srv,
+            Err(err) => return fail(err),
+        };
+        let ex = match self.send_message(msg, &srv, self.flags.verbose) {
+            Ok(ex) => ex,
+            Err(err) => {
+                eprintln!("{}", err);
+                return Outcome::Transport;
+            }
+        };
+        match ByteStreamParser::new(&ex.answer).parse_response() {
+            Ok(resp) => {
+                self.print_response(&resp, &Origin::new(&ex.server.to_string(), Some(ex.rtt)));
+                self.outcome_of(&resp)
+            }
+            Err(err) => {
+                eprintln!("Failed to parse the response from {}: {}", ex.server, err);
+                Outcome::Parse
+            }
+        }
+    }
+
+    fn craft_spec(&self, command: &Command) -> Result<MessageSpec, String> {
+        let Command::Craft {
+            spec,
+            id,
+            opcode,
+            rcode,
+            flags,
+            questions,
+            answers,
+            authority,
+            additional,
+        } = command
+        else {
+            return Err(String::from("Not a craft command."));
+        };
+        let mut spec = match spec {
+            Some(path) => MessageSpec::from_file(path)?,
+            None => MessageSpec::default(),
+        };
+        if id.is_some() {
+            spec.id = *id;
+        }
+        if let Some(opcode) = opcode {
+            spec.opcode = Some(craft::Code::Name(opcode.clone()));
+        }
+        if let Some(rcode) = rcode {
+            spec.rcode = Some(craft::Code::Name(rcode.clone()));
+        }
+        spec.flags.extend(flags.iter().cloned());
+        for q in questions.iter() {
+            spec.question.push(QuestionSpec::from_string(q)?);
+        }
+        for (records, section) in [
+            (answers, &mut spec.answer),
+            (authority, &mut spec.authority),
+            (additional, &mut spec.additional),
+        ] {
+            for rr in records.iter() {
+                section.push(RecordSpec::from_string(rr)?);
+            }
+        }
+        Ok(spec)
+    }
+
+    fn send_query(&self, q: Query, srv: &DNSSocket, verbose: bool) -> Result<Exchange, String> {
+        let ex = self.send_message(q.to_bytes(), srv, verbose)?;
         if verbose {
-            q.print(verbose);
+            self.diagnostic(q.header());
+            for question in q.questions() {
+                self.diagnostic(question);
+            }
         }
-        a
+        Ok(ex)
     }
 
-    pub fn run(&self) {
+    fn send_message(
+        &self,
+        msg: Vec<u8>,
+        srv: &DNSSocket,
+        verbose: bool,
+    ) -> Result<Exchange, String> {
+        let client =
+            socket::UDPClient::new(self.flags.get_retry_policy(&self.resolv_conf), verbose)
+                .with_capture(self.capture.clone());
+        if self.flags.explain {
+            self.diagnostic(format!(";; Query\n{}\n", explain(&msg)));
+        }
+        let ex = client.exchange_with_any(msg, &srv.get_addresses())?;
+        if self.flags.explain {
+            self.diagnostic(format!(
+                ";; Response from {}\n{}\n",
+                ex.server,
+                explain(&ex.answer)
+            ));
+        }
+        Ok(ex)
+    }
+
+    fn print_response(&self, resp: &Response, origin: &Origin) {
+        print_response(resp, &self.format, origin, self.flags.verbose)
+    }
+
+    /// Diagnostics are part of the text output. Other formats are read by
+    /// programs, so stdout only gets the responses and diagnostics go to
+    /// stderr.
+    fn diagnostic(&self, text: impl std::fmt::Display) {
+        if matches!(self.format, OutputFormat::Text) {
+            println!("{}", text);
+        } else {
+            eprintln!("{}", text);
+        }
+    }
+
+    /// Prints the server asked and its response in the output format.
+    /// Lookups of name servers without glue are indented below the referral
+    /// which needed them.
+    fn print_trace_step(&self, step: &TraceStep) {
+        let indent = "    ".repeat(step.depth);
+        self.diagnostic(format!(
+            "{};; {} {} from {} for zone {} in {} ms",
+            indent,
+            step.qname,
+            step.response
+                .question()
+                .map(|q| q.get_type().to_string())
+                .unwrap_or_default(),
+            step.server,
+            step.zone,
+            step.rtt.as_millis()
+        ));
+        if let Some(referral) = step.referral {
+            self.diagnostic(format!(
+                "{};; Referred to {}: {}",
+                indent,
+                referral.zone,
+                referral.nameservers.join(", ")
+            ));
+            if referral.glue.is_empty() {
+                self.diagnostic(format!(
+                    "{};; No glue, looking up the name servers.",
+                    indent
+                ));
+            } else {
+                let glue: Vec<String> = referral.glue.iter().map(|a| a.ip().to_string()).collect();
+                self.diagnostic(format!("{};; Glue: {}", indent, glue.join(", ")));
+            }
+        }
+        self.print_response(
+            step.response,
+            &Origin::new(&step.server.to_string(), Some(step.rtt)),
+        );
+        if matches!(self.format, OutputFormat::Text) {
+            println!();
+        }
+    }
+
+    /// The outcome of a lookup answered by `resp`. Error RCODEs are
+    /// described on stderr, as the output itself may not show them.
+    fn outcome_of(&self, resp: &Response) -> Outcome {
+        let rcode = resp.header().rcode();
+        if rcode.is_err() {
+            eprintln!("{}: {}", rcode.mnemonic(), rcode);
+        }
+        Outcome::of(resp)
+    }
+
+    /// Prints the aliases followed, if any, and the final response unless
+    /// `origin` is `None`. JSON output only holds the final response.
+    fn print_chain(&self, chain: &Chain, origin: Option<&Origin>) {
+        if !chain.links.is_empty() && matches!(self.format, OutputFormat::Text) {
+            chain.print();
+        }
+        if let Some(origin) = origin {
+            self.print_response(&chain.response, origin);
+        }
+    }
+
+    /// The answer of the hosts file for the uri, if enabled and it has one.
+    fn lookup_hosts(&self, rtype: &RecordType) -> Option<Response> {
+        if !self.flags.use_hosts {
+            return None;
+        }
+        let hosts = HostsFile::from_file(&self.flags.hosts_file)
+            .map_err(|err| eprintln!("{}", err))
+            .ok()?;
+        let uri = self.flags.get_uri();
+        let answers = hosts.lookup(&uri, rtype);
+        if answers.is_empty() {
+            return None;
+        }
         let qry = Query::new(
-            self.flags.uri.clone(),
-            self.flags.get_rtype(),
+            uri.trim_end_matches('.').to_string(),
+            rtype.clone(),
             self.flags.rd,
         );
-        let a = self.send_query(qry, self.flags.get_server(), self.flags.verbose);
-        let resp = ByteStreamParser::new(&a).parse_response().unwrap();
-        resp.print(self.flags.verbose);
+        Response::synthesize(&qry, answers, true).ok()
+    }
+
+    fn print_hosts_answer(&self, resp: &Response) {
+        if matches!(self.format, OutputFormat::Text) {
+            println!("Answer from hosts file: {}", self.flags.hosts_file);
+        }
+        self.print_response(resp, &Origin::new(&self.flags.hosts_file, None));
+    }
+
+    /// Lines of a batch without a type are looked up with every type of
+    /// --record. The aliases of every answer are followed.
+    fn run_batch(&self, path: &str, rtypes: &[RecordType]) -> Outcome {
+        let order = match OutputOrder::from_string(&self.flags.order) {
+            Ok(order) => order,
+            Err(err) => return fail(err),
+        };
+        let lines = match read_batch(path) {
+            Ok(lines) => lines,
+            Err(err) => return fail(err),
+        };
+        // A line with a bad type or server is reported in its place and
+        // doesn't stop the other lines.
+        let entries: Vec<Result<Lookup, BatchError>> = lines
+            .into_iter()
+            .flat_map(|line| {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(err) => return vec![Err(err)],
+                };
+                let server = line.server.clone().unwrap_or(self.flags.server.clone());
+                let srv = match self.server(&server) {
+                    Ok(srv) => srv,
+                    Err(err) => {
+                        return vec![Err(BatchError {
+                            number: line.number,
+                            message: format!("line {}: {}", line.number, err),
+                        })]
+                    }
+                };
+                let line_types = match &line.r_type {
+                    Some(rtype) => vec![rtype.clone()],
+                    None => rtypes.to_vec(),
+                };
+                line_types
+                    .into_iter()
+                    .map(|rtype| {
+                        Ok(Lookup {
+                            names: names_for(&line.name, &server, &self.resolv_conf),
+                            r_type: rtype,
+                            addrs: srv.get_addresses(),
+                            rd: self.flags.rd,
+                            edns: srv.edns(),
+                            line: line.clone(),
+                        })
+                    })
+                    .collect()
+            })
+            .collect();
+        let heading = |lookup: &Lookup| {
+            if matches!(self.format, OutputFormat::Text) {
+                println!(
+                    "=== {}: {} {} ===",
+                    lookup.line.number,
+                    lookup.line.name,
+                    lookup.r_type.to_string()
+                );
+            }
+        };
+        let mut outcome = Outcome::Answer;
+        // The queries and answers are shown as they are sent and received,
+        // so the lookups go one after the other.
+        if self.flags.explain || self.flags.verbose {
+            for entry in entries.iter() {
+                let lookup = match entry {
+                    Ok(lookup) => lookup,
+                    Err(err) => {
+                        outcome = outcome.and(fail(err));
+                        continue;
+                    }
+                };
+                heading(lookup);
+                outcome = outcome.and(match self.lookup_server(lookup) {
+                    Ok(srv) => self.lookup_names(&lookup.names, &lookup.r_type, &srv, None),
+                    Err(err) => fail(err),
+                });
+            }
+            return outcome;
+        }
+        let (lookups, errors): (Vec<_>, Vec<_>) = entries.into_iter().partition(Result::is_ok);
+        let lookups: Vec<Lookup> = lookups.into_iter().filter_map(Result::ok).collect();
+        let mut errors = errors.into_iter().filter_map(Result::err).peekable();
+        // In completion order the bad lines are known first, in input order
+        // they are reported before the first lookup of a later line.
+        if matches!(order, OutputOrder::Completion) {
+            for err in errors.by_ref() {
+                outcome = outcome.and(fail(err));
+            }
+        }
+        let resolved = self.resolve_lookups(lookups, order, |lookup, answer| {
+            while let Some(err) = errors.next_if(|err| err.number < lookup.line.number) {
+                outcome = outcome.and(fail(err));
+            }
+            heading(lookup);
+            outcome = outcome.and(self.print_lookup(lookup, answer))
+        });
+        for err in errors {
+            outcome = outcome.and(fail(err));
+        }
+        match resolved {
+            Ok(()) => outcome,
+            Err(err) => outcome.and(fail(err)),
+        }
+    }
+
+    /// Looks the uri up with all `rtypes` in parallel and prints the
+    /// responses in the order of the types.
+    fn run_types(&self, rtypes: &[RecordType]) -> Outcome {
+        let uri = self.flags.get_uri();
+        let heading = |rtype: &RecordType| {
+            if matches!(self.format, OutputFormat::Text) {
+                println!("=== {} {} ===", uri, rtype.to_string());
+            }
+        };
+        // The iterative resolver walks the delegations one query at a time
+        // and the queries and answers are shown as they are sent and received.
+        if self.flags.iterative || self.flags.trace || self.flags.explain || self.flags.verbose {
+            let mut outcome = Outcome::Answer;
+            for rtype in rtypes.iter() {
+                heading(rtype);
+                outcome = outcome.and(self.run_lookup(rtype));
+            }
+            return outcome;
+        }
+        let mut local: Vec<Option<Response>> = rtypes
+            .iter()
+            .map(|rtype| self.lookup_hosts(rtype))
+            .collect();
+        let srv = match self.server(&self.flags.server) {
+            Ok(srv) => srv,
+            Err(err) => return fail(err),
+        };
+        let lookups: Vec<Lookup> = rtypes
+            .iter()
+            .enumerate()
+            .filter(|(idx, _)| local[*idx].is_none())
+            .map(|(idx, rtype)| Lookup {
+                line: BatchLine {
+                    number: idx,
+                    name: uri.clone(),
+                    r_type: Some(rtype.clone()),
+                    server: None,
+                },
+                names: self.flags.get_names(&self.resolv_conf),
+                r_type: rtype.clone(),
+                addrs: srv.get_addresses(),
+                rd: self.flags.rd,
+                edns: srv.edns(),
+            })
+            .collect();
+        // Answers from the hosts file go between the network lookups.
+        let mut outcome = Outcome::Answer;
+        let mut next = 0;
+        let mut print_local = |until: usize| {
+            while next < until {
+                if let Some(resp) = local[next].take() {
+                    heading(&rtypes[next]);
+                    self.print_hosts_answer(&resp);
+                }
+                next += 1;
+            }
+        };
+        let resolved = self.resolve_lookups(lookups, OutputOrder::Input, |lookup, answer| {
+            print_local(lookup.line.number + 1);
+            heading(&lookup.r_type);
+            outcome = outcome.and(self.print_lookup(lookup, answer))
+        });
+        print_local(rtypes.len());
+        match resolved {
+            Ok(()) => outcome,
+            Err(err) => fail(err),
+        }
+    }
+
+    /// Runs `lookups` on the async client, see [`run_batch`].
+    fn resolve_lookups<F>(
+        &self,
+        lookups: Vec<Lookup>,
+        order: OutputOrder,
+        emit: F,
+    ) -> Result<(), String>
+    where
+        F: FnMut(&Lookup, Result<BatchAnswer, String>),
+    {
+        for lookup in lookups.iter() {
+            self.load_cached(&upstream(&lookup.addrs));
+        }
+        let runtime = tokio::runtime::Runtime::new()
+            .map_err(|err| format!("Failed to start the async runtime: {}", err))?;
+        runtime.block_on(async {
+            let policy = self.flags.get_retry_policy(&self.resolv_conf);
+            let client = Arc::new(
+                AsyncUDPClient::new(policy)
+                    .await?
+                    .with_capture(self.capture.clone()),
+            );
+            run_batch(
+                lookups,
+                client,
+                self.cache.clone(),
+                self.flags.concurrency,
+                order,
+                emit,
+            )
+            .await;
+            Ok(())
+        })
+    }
+
+    fn print_lookup(&self, lookup: &Lookup, answer: Result<BatchAnswer, String>) -> Outcome {
+        let answer = match answer {
+            Ok(answer) => answer,
+            Err(err) => {
+                eprintln!("{}: {}", lookup.line.name, err);
+                return Outcome::Transport;
+            }
+        };
+        let resp = match ByteStreamParser::new(&answer.answer).parse_response() {
+            Ok(resp) => resp,
+            Err(err) => {
+                eprintln!("{}: {}", lookup.line.name, err);
+                return Outcome::Parse;
+            }
+        };
+        let origin = match answer.server {
+            Some(server) => {
+                self.store(&resp, &upstream(&lookup.addrs));
+                Origin::new(&server.to_string(), answer.rtt)
+            }
+            None => Origin::new("cache", None),
+        };
+        // The aliases of the answer are followed like those of a single lookup.
+        let name = resp
+            .question()
+            .and_then(|q| q.get_name().get_string().ok())
+            .unwrap_or_else(|| lookup.line.name.clone());
+        match self.lookup_server(lookup) {
+            Ok(srv) => self.lookup_names(&[name], &lookup.r_type, &srv, Some((resp, origin))),
+            Err(err) => fail(err),
+        }
+    }
+
+    /// The server a line of a batch is looked up at.
+    fn lookup_server(&self, lookup: &Lookup) -> Result<DNSSocket, String> {
+        self.server(lookup.line.server.as_ref().unwrap_or(&self.flags.server))
     }
+
+    /// Failed iterative lookups exit like a SERVFAIL, which a recursive
+    /// resolver would have answered with, unless no server answered or a
+    /// response could not be parsed.
+    fn run_iterative(&self, rtype: &RecordType) -> Outcome {
+        let resolver = IterativeResolver::new(
+            self.flags.get_retry_policy(&self.resolv_conf),
+            self.flags.verbose,
+        )
+        .with_capture(self.capture.clone());
+        let uri = self.flags.get_uri();
+        let mut origin = Origin::new("cache", None);
+        let mut failure = Outcome::ServerFailure;
+        self.load_cached(ITERATIVE);
+        let chain = follow_chain(&uri, rtype, self.flags.max_chain, |name| {
+            // A trace shows every step, so it never answers from the cache.
+            if self.flags.trace {
+                return resolver
+                    .trace(name, rtype, |step| {
+                        self.print_trace_step(step);
+                        origin = Origin::new(&step.server.to_string(), Some(step.rtt))
+                    })
+                    .map_err(|(outcome, err)| {
+                        failure = outcome;
+                        err
+                    });
+            }
+            cached_lookup(&self.cache, ITERATIVE, name, rtype, false, || {
+                // The last step is the answer of the authoritative server.
+                let resp = resolver
+                    .trace(name, rtype, |step| {
+                        origin = Origin::new(&step.server.to_string(), Some(step.rtt))
+                    })
+                    .map_err(|(outcome, err)| {
+                        failure = outcome;
+                        err
+                    })?;
+                self.store(&resp, ITERATIVE);
+                Ok(resp)
+            })
+        });
+        let chain = match chain {
+            Ok(chain) => chain,
+            Err(err) => {
+                eprintln!("{}", err);
+                return failure;
+            }
+        };
+        self.record(&chain.response, &origin);
+        // The trace already printed the final response.
+        if self.flags.trace {
+            self.print_chain(&chain, None);
+        } else {
+            self.print_chain(&chain, Some(&origin));
+        }
+        self.outcome_of(&chain.response)
+    }
+
+    /// Runs the command or lookup given on the command line, the outcome
+    /// is the exit code of the process.
+    pub fn run(&self) -> Outcome {
+        match &self.flags.command {
+            Some(Command::Vacuum) => return self.run_vacuum(),
+            Some(Command::History { domain, r_type }) => return self.run_history(domain, r_type),
+            Some(Command::Decode { message }) => return self.run_decode(message),
+            Some(Command::Pcap { file }) => return self.run_pcap(file),
+            Some(command @ Command::Craft { .. }) => return self.run_craft(command),
+            None => {}
+        }
+        let rtypes = match self.flags.get_rtypes() {
+            Ok(rtypes) => rtypes,
+            Err(err) => return fail(err),
+        };
+        if let Some(path) = &self.flags.batch {
+            return self.run_batch(path, &rtypes);
+        }
+        match rtypes.as_slice() {
+            [rtype] => self.run_lookup(rtype),
+            _ => self.run_types(&rtypes),
+        }
+    }
+
+    /// Looks the uri up with a single record type.
+    fn run_lookup(&self, rtype: &RecordType) -> Outcome {
+        if let Some(resp) = self.lookup_hosts(rtype) {
+            self.print_hosts_answer(&resp);
+            return Outcome::of(&resp);
+        }
+        if self.flags.iterative || self.flags.trace {
+            return self.run_iterative(rtype);
+        }
+        let srv = match self.server(&self.flags.server) {
+            Ok(srv) => srv,
+            Err(err) => return fail(err),
+        };
+        self.lookup_names(&self.flags.get_names(&self.resolv_conf), rtype, &srv, None)
+    }
+
+    /// Looks `names` up at `srv` and follows the aliases of the answer.
+    /// `answered` is the response to the first name if it was received
+    /// already, e.g. by the async client.
+    fn lookup_names(
+        &self,
+        names: &[String],
+        rtype: &RecordType,
+        srv: &DNSSocket,
+        mut answered: Option<(Response, Origin)>,
+    ) -> Outcome {
+        let mut chain: Option<Chain> = None;
+        let mut origin = Origin::new("cache", None);
+        // Errors of the lookup are strings, remember what failed.
+        let mut failure = Outcome::Failure;
+        let server = upstream(&srv.get_addresses());
+        self.load_cached(&server);
+        // Like the libc resolver, move on to the next search domain as long
+        // as a name does not exist or has no records of the requested type.
+        for name in names {
+            let res = follow_chain(name, rtype, self.flags.max_chain, |alias| {
+                if let Some((resp, from)) = answered.take() {
+                    origin = from;
+                    return Ok(resp);
+                }
+                cached_lookup(&self.cache, &server, alias, rtype, self.flags.rd, || {
+                    let qry = Query::new(alias.to_string(), rtype.clone(), self.flags.rd)
+                        .with_edns(srv.edns());
+                    let ex = self
+                        .send_query(qry, srv, self.flags.verbose)
+                        .inspect_err(|_| failure = Outcome::Transport)?;
+                    let resp = ByteStreamParser::new(&ex.answer)
+                        .parse_response()
+                        .inspect_err(|_| failure = Outcome::Parse)?;
+                    origin = Origin::new(&ex.server.to_string(), Some(ex.rtt));
+                    self.store(&resp, &server);
+                    Ok(resp)
+                })
+            });
+            let c = match res {
+                Ok(c) => c,
+                Err(err) => {
+                    eprintln!("{}", err);
+                    return failure;
+                }
+            };
+            let found = !c.response.header().rcode().is_err() && !c.answers.is_empty();
+            chain = Some(c);
+            if found {
+                break;
+            }
+        }
+        match chain {
+            Some(chain) => {
+                self.record(&chain.response, &origin);
+                self.print_chain(&chain, Some(&origin));
+                self.outcome_of(&chain.response)
+            }
+            None => fail("There is no name to look up."),
+        }
+    }
+}
+
+/// Reports an error which is not about the answer of a lookup.
+fn fail(err: impl std::fmt::Display) -> Outcome {
+    eprintln!("{}", err);
+    Outcome::Failure
 }
@@ -1,6 +1,10 @@
+use std::process;
+
 use cli::CLI;
 
 fn main() {
-    let client = CLI::init();
-    client.run();
+    // The client is dropped before exiting, so captures and databases are
+    // written out completely.
+    let outcome = CLI::init().run();
+    process::exit(outcome.code());
 }
@@ -0,0 +1,92 @@
+use dns::header_flags::RCODE;
+use parsing::Response;
+
+/*
+    Exit codes, so scripts can tell the results of a lookup apart:
+
+        0   NOERROR with answer records
+        1   any other error, e.g. an unreadable file or invalid options
+        2   invalid command line (reported by clap)
+        3   NODATA, NOERROR without answer records
+        4   NXDOMAIN
+        5   SERVFAIL, REFUSED and the other error RCODEs
+        6   no server answered, e.g. a timeout
+        7   the response could not be parsed
+
+    Runs with several lookups exit with the worst outcome among them.
+*/
+
+/// How a run ended, ordered from best to worst.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Outcome {
+    Answer,
+    NoData,
+    NxDomain,
+    ServerFailure,
+    Transport,
+    Parse,
+    Failure,
+}
+
+impl Outcome {
+    /// The outcome of a lookup answered by `resp`.
+    pub fn of(resp: &Response) -> Self {
+        match resp.header().rcode() {
+            RCODE::NOERR if resp.answers().is_empty() => Outcome::NoData,
+            RCODE::NOERR => Outcome::Answer,
+            RCODE::NAMEERR => Outcome::NxDomain,
+            _ => Outcome::ServerFailure,
+        }
+    }
+
+    pub fn code(&self) -> i32 {
+        match self {
+            Outcome::Answer => 0,
+            Outcome::Failure => 1,
+            Outcome::NoData => 3,
+            Outcome::NxDomain => 4,
+            Outcome::ServerFailure => 5,
+            Outcome::Transport => 6,
+            Outcome::Parse => 7,
+        }
+    }
+
+    /// The worse of both outcomes.
+    pub fn and(self, other: Outcome) -> Self {
+        self.max(other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dns::{answer::Answer, name::Name, record::RecordType};
+    use parsing::Query;
+
+    use super::*;
+
+    #[test]
+    fn test_outcome_of_response() {
+        let qry = Query::new("example.com".to_string(), RecordType::A, true);
+        let a = Answer::new(
+            Name::from_string("example.com".to_string()).unwrap(),
+            RecordType::A,
+            [0, 1],
+            [0, 0, 0, 60],
+            [0, 4],
+            vec![192, 0, 2, 1],
+        );
+        let outcome = |rcode: u8, answers: Vec<Answer>| {
+            let resp = Response::synthesize_with(&qry, rcode, answers, vec![], false).unwrap();
+            Outcome::of(&resp)
+        };
+        assert_eq!(outcome(0, vec![a]), Outcome::Answer);
+        assert_eq!(outcome(0, vec![]), Outcome::NoData);
+        assert_eq!(outcome(3, vec![]), Outcome::NxDomain);
+        assert_eq!(outcome(2, vec![]), Outcome::ServerFailure);
+        assert_eq!(outcome(5, vec![]), Outcome::ServerFailure);
+
+        assert_eq!(Outcome::NoData.and(Outcome::Answer), Outcome::NoData);
+        assert_eq!(Outcome::Transport.and(Outcome::NxDomain).code(), 6);
+        assert_eq!(Outcome::Answer.and(Outcome::Failure).code(), 1);
+    }
+}
@@ -0,0 +1,350 @@
+use std::{net::SocketAddr, time::Duration};
+
+use dns::{
+    answer::{class_string, Answer},
+    record::RecordType,
+};
+use parsing::Response;
+use serde::Serialize;
+use utility::to_hex;
+
+pub enum OutputFormat {
+    Text,
+    Json,
+    Rfc8427,
+    Dig,
+    /// The data of the answer records only, set by `--short`.
+    Short,
+}
+
+impl OutputFormat {
+    pub fn from_string(format: &str) -> Result<Self, String> {
+        match format {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "rfc8427" => Ok(OutputFormat::Rfc8427),
+            "dig" => Ok(OutputFormat::Dig),
+            _ => Err(format!("Unknown output format {}", format)),
+        }
+    }
+}
+
+/// Where a response came from: the address of the server, the hosts file
+/// or the cache, and the round trip time if it was sent over the network.
+pub struct Origin {
+    pub server: String,
+    pub rtt: Option<Duration>,
+}
+
+impl Origin {
+    pub fn new(server: &str, rtt: Option<Duration>) -> Self {
+        Self {
+            server: server.to_string(),
+            rtt,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonOutput<'a> {
+    server: &'a str,
+    query_time_ms: Option<u128>,
+    response: &'a Response,
+}
+
+/// One JSON object per response, on a single line so batches can be read
+/// as JSON lines.
+pub fn to_json(resp: &Response, origin: &Origin) -> Result<String, String> {
+    let output = JsonOutput {
+        server: &origin.server,
+        query_time_ms: origin.rtt.map(|rtt| rtt.as_millis()),
+        response: resp,
+    };
+    serde_json::to_string(&output).map_err(|err| format!("Failed to write JSON: {}", err))
+}
+
+pub fn print_response(resp: &Response, format: &OutputFormat, origin: &Origin, verbose: bool) {
+    match format {
+        OutputFormat::Text => resp.print(verbose),
+        OutputFormat::Json => match to_json(resp, origin) {
+            Ok(json) => println!("{}", json),
+            Err(err) => eprintln!("{}", err),
+        },
+        OutputFormat::Rfc8427 => match resp.to_rfc8427() {
+            Ok(json) => println!("{}", json),
+            Err(err) => eprintln!("{}", err),
+        },
+        OutputFormat::Dig => println!("{}", to_dig(resp, origin)),
+        OutputFormat::Short => {
+            for line in to_short(resp) {
+                println!("{}", line)
+            }
+        }
+    }
+}
+
+/// The data of every answer record in presentation format, e.g. the
+/// address of A records or `preference exchange` of MX records.
+pub fn to_short(resp: &Response) -> Vec<String> {
+    resp.answers()
+        .iter()
+        .map(|rr| rr.data_string(resp.get_bytes()))
+        .collect()
+}
+
+/// The type of the EDNS pseudo record of RFC 6891.
+const OPT: u16 = 41;
+
+/// The response laid out like the output of dig: the header line, the
+/// OPT pseudo-section, every section in zone file format and a footer
+/// with the query time, the server and the size of the message.
+pub fn to_dig(resp: &Response, origin: &Origin) -> String {
+    let header = resp.header();
+    let flags = header.flags();
+    let set_flags: Vec<&str> = [
+        ("qr", flags.is_response()),
+        ("aa", flags.is_aa()),
+        ("tc", flags.is_truncated()),
+        ("rd", flags.is_recursion_desired()),
+        ("ra", flags.is_recursion_available()),
+        ("ad", flags.is_authentic_data()),
+        ("cd", flags.is_checking_disabled()),
+    ]
+    .iter()
+    .filter(|(_, set)| *set)
+    .map(|(name, _)| *name)
+    .collect();
+    let mut lines = vec![
+        format!(
+            ";; ->>HEADER<<- opcode: {}, status: {}, id: {}",
+            flags.get_opcode().mnemonic(),
+            header.rcode().mnemonic(),
+            header.id()
+        ),
+        format!(
+            ";; flags: {}; QUERY: {}, ANSWER: {}, AUTHORITY: {}, ADDITIONAL: {}",
+            set_flags.join(" "),
+            header.q_count(),
+            header.an_count(),
+            header.ns_count(),
+            header.ar_count()
+        ),
+    ];
+    let is_opt = |rr: &&Answer| matches!(rr.get_type(), RecordType::UNKNOWN(OPT));
+    if let Some(opt) = resp.additional().iter().find(is_opt) {
+        lines.push(String::new());
+        lines.push(";; OPT PSEUDOSECTION:".to_string());
+        lines.extend(opt_lines(opt));
+    }
+
+    lines.push(String::new());
+    lines.push(";; QUESTION SECTION:".to_string());
+    for question in resp.questions() {
+        lines.push(format!(
+            ";{}.\t\t{}\t{}",
+            question.get_name().get_string().unwrap_or_default(),
+            class_string(question.get_class().code()),
+            question.get_type().to_string()
+        ));
+    }
+    let additional: Vec<Answer> = resp
+        .additional()
+        .iter()
+        .filter(|rr| !is_opt(rr))
+        .cloned()
+        .collect();
+    let sections = [
+        ("ANSWER", resp.answers()),
+        ("AUTHORITY", resp.authority()),
+        ("ADDITIONAL", &additional),
+    ];
+    for (name, records) in sections {
+        if records.is_empty() {
+            continue;
+        }
+        lines.push(String::new());
+        lines.push(format!(";; {} SECTION:", name));
+        for rr in records {
+            lines.push(rr_line(rr, resp.get_bytes()));
+        }
+    }
+
+    lines.push(String::new());
+    if let Some(rtt) = origin.rtt {
+        lines.push(format!(";; Query time: {} msec", rtt.as_millis()));
+    }
+    let server = match origin.server.parse::<SocketAddr>() {
+        Ok(addr) => format!("{}#{}({})", addr.ip(), addr.port(), addr.ip()),
+        Err(_) => origin.server.clone(),
+    };
+    lines.push(format!(";; SERVER: {}", server));
+    lines.push(format!(";; MSG SIZE  rcvd: {}", resp.get_bytes().len()));
+    lines.join("\n")
+}
+
+/// One record as a line of a zone file, with the fields separated by tabs.
+fn rr_line(rr: &Answer, src: Vec<u8>) -> String {
+    format!(
+        "{}.\t{}\t{}\t{}\t{}",
+        rr.get_name().get_string().unwrap_or_default(),
+        rr.get_ttl(),
+        class_string(rr.get_class()),
+        rr.get_type().to_string(),
+        rr.data_string(src)
+    )
+}
+
+/*
+    The OPT record reuses the fixed fields: CLASS is the UDP payload size
+    of the sender and TTL holds the extended RCODE, the EDNS version and
+    the flags, of which only DO is defined. The data is a list of options,
+    each a 16 bit code and length followed by the option data.
+*/
+fn opt_lines(opt: &Answer) -> Vec<String> {
+    let ttl = opt.get_ttl();
+    let flags = if ttl & 0x8000 != 0 { " do" } else { "" };
+    let mut lines = vec![format!(
+        "; EDNS: version: {}, flags:{}; udp: {}",
+        (ttl >> 16) & 0xff,
+        flags,
+        opt.get_class()
+    )];
+    let mut data = opt.get_data().as_slice();
+    while data.len() >= 4 {
+        let code = u16::from_be_bytes([data[0], data[1]]);
+        let len = (u16::from_be_bytes([data[2], data[3]]) as usize).min(data.len() - 4);
+        let value = to_hex(&data[4..4 + len]).to_uppercase();
+        lines.push(match code {
+            3 => format!("; NSID: {}", value),
+            10 => format!("; COOKIE: {}", value.to_lowercase()),
+            12 => format!("; PADDING: {} bytes", len),
+            _ => format!("; OPT={}: {}", code, value),
+        });
+        data = &data[4 + len..];
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use dns::name::Name;
+    use parsing::Query;
+
+    use super::*;
+
+    #[test]
+    fn test_to_json() {
+        let qry = Query::new("example.com".to_string(), RecordType::A, true);
+        let resp = Response::synthesize_with(&qry, 3, vec![], vec![], false).unwrap();
+        let origin = Origin::new("8.8.8.8:53", Some(Duration::from_millis(12)));
+        let json: serde_json::Value =
+            serde_json::from_str(&to_json(&resp, &origin).unwrap()).unwrap();
+        assert_eq!(json["server"], "8.8.8.8:53");
+        assert_eq!(json["query_time_ms"], 12);
+        assert_eq!(json["response"]["header"]["flags"]["rcode"], "NXDOMAIN");
+        assert_eq!(json["response"]["question"]["type"], "A");
+        assert!(json["response"].get("questions").is_none());
+
+        let cached = to_json(&resp, &Origin::new("cache", None)).unwrap();
+        assert!(cached.contains("\"query_time_ms\":null"));
+    }
+
+    #[test]
+    fn test_to_json_bad_data() {
+        let qry = Query::new("example.com".to_string(), RecordType::NS, true);
+        let base = Response::synthesize(&qry, vec![], false).unwrap();
+        // The parser rejects the pointer past the end of the message.
+        let answer = Answer::new(
+            Name::from_string("example.com".to_string()).unwrap(),
+            RecordType::NS,
+            [0, 1],
+            300u32.to_be_bytes(),
+            [0, 2],
+            vec![0xC0, 0xFF],
+        );
+        let resp = Response::new(
+            base.get_bytes(),
+            base.header().clone(),
+            vec![],
+            vec![answer],
+            vec![],
+            vec![],
+        );
+        let err = to_json(&resp, &Origin::new("cache", None)).unwrap_err();
+        assert!(err.starts_with("Failed to write JSON"));
+    }
+
+    #[test]
+    fn test_to_dig() {
+        let qry = Query::new("example.com".to_string(), RecordType::A, true);
+        let answer = Answer::new(
+            Name::from_string("example.com".to_string()).unwrap(),
+            RecordType::A,
+            [0, 1],
+            300u32.to_be_bytes(),
+            [0, 4],
+            vec![192, 0, 2, 1],
+        );
+        let resp = Response::synthesize(&qry, vec![answer], false).unwrap();
+        let origin = Origin::new("8.8.8.8:53", Some(Duration::from_millis(12)));
+        let dig = to_dig(&resp, &origin);
+        let lines: Vec<&str> = dig.lines().collect();
+        assert_eq!(
+            lines[0],
+            ";; ->>HEADER<<- opcode: QUERY, status: NOERROR, id: 49327"
+        );
+        assert_eq!(
+            lines[1],
+            ";; flags: qr rd ra; QUERY: 1, ANSWER: 1, AUTHORITY: 0, ADDITIONAL: 0"
+        );
+        assert!(lines.contains(&";example.com.\t\tIN\tA"));
+        assert!(lines.contains(&"example.com.\t300\tIN\tA\t192.0.2.1"));
+        assert!(!dig.contains("OPT PSEUDOSECTION"));
+        assert!(lines.contains(&";; Query time: 12 msec"));
+        assert!(lines.contains(&";; SERVER: 8.8.8.8#53(8.8.8.8)"));
+        assert_eq!(
+            lines.last().unwrap(),
+            &format!(";; MSG SIZE  rcvd: {}", resp.get_bytes().len())
+        );
+    }
+
+    #[test]
+    fn test_to_short() {
+        let qry = Query::new("example.com".to_string(), RecordType::MX, true);
+        let mut mx = vec![0, 10];
+        mx.extend(
+            Name::from_string("mail.example.com".to_string())
+                .unwrap()
+                .get_bytes(),
+        );
+        let answer = Answer::new(
+            Name::from_string("example.com".to_string()).unwrap(),
+            RecordType::MX,
+            [0, 1],
+            300u32.to_be_bytes(),
+            (mx.len() as u16).to_be_bytes(),
+            mx,
+        );
+        let resp = Response::synthesize(&qry, vec![answer], false).unwrap();
+        assert_eq!(to_short(&resp), vec!["10 mail.example.com."]);
+    }
+
+    #[test]
+    fn test_opt_lines() {
+        let opt = Answer::new(
+            Name::from_string("".to_string()).unwrap(),
+            RecordType::UNKNOWN(OPT),
+            1232u16.to_be_bytes(),
+            [0, 0, 0x80, 0],
+            [0, 8],
+            vec![0, 10, 0, 4, 0xde, 0xad, 0xbe, 0xef],
+        );
+        assert_eq!(
+            opt_lines(&opt),
+            vec![
+                "; EDNS: version: 0, flags: do; udp: 1232",
+                "; COOKIE: deadbeef"
+            ]
+        );
+    }
+}
@@ -0,0 +1,984 @@
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    fmt,
+    fs::File,
+    io::Write,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/*
+    A capture is read in three steps. The frames are taken from the pcap
+    or pcapng file, then the link, IP and UDP or TCP headers are stripped
+    to get the DNS messages sent to or from port 53, and last the queries
+    are paired with their responses.
+
+    pcap files start with a 24 byte header holding the link type, followed
+    by records of a 16 byte header (seconds, micro- or nanoseconds,
+    captured and original length) and the frame. The magic number tells
+    the byte order and the resolution of the timestamps.
+
+    pcapng files are a list of blocks, each starting with its type and
+    length. The section header block sets the byte order, interface
+    description blocks the link type and timestamp resolution of an
+    interface and enhanced or simple packet blocks hold the frames.
+*/
+
+/// The link types askrs can read, see <https://www.tcpdump.org/linktypes.html>.
+pub const LINKTYPE_ETHERNET: u32 = 1;
+pub const LINKTYPE_RAW: u32 = 101;
+pub const LINKTYPE_LINUX_SLL: u32 = 113;
+pub const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const DNS_PORT: u16 = 53;
+
+/// A captured frame with the time it was captured at, since the epoch.
+pub struct Frame {
+    pub time: Duration,
+    pub link_type: u32,
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Transport {
+    Udp,
+    Tcp,
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Transport::Udp => write!(f, "UDP"),
+            Transport::Tcp => write!(f, "TCP"),
+        }
+    }
+}
+
+/// A DNS message found in a capture. Messages sent over TCP are taken
+/// from the reassembled stream, without the length prefix.
+pub struct Message {
+    pub time: Duration,
+    pub transport: Transport,
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+    pub bytes: Vec<u8>,
+}
+
+impl Message {
+    pub fn id(&self) -> u16 {
+        u16::from_be_bytes([self.bytes[0], self.bytes[1]])
+    }
+
+    pub fn is_query(&self) -> bool {
+        self.bytes[2] & 0x80 == 0
+    }
+}
+
+/// A query and its response. Either of them is missing when the capture
+/// holds only one side of the exchange.
+pub struct Pair {
+    pub query: Option<Message>,
+    pub response: Option<Message>,
+}
+
+impl Pair {
+    /// Time from the query to the response.
+    pub fn latency(&self) -> Option<Duration> {
+        match (&self.query, &self.response) {
+            (Some(query), Some(response)) => response.time.checked_sub(query.time),
+            _ => None,
+        }
+    }
+}
+
+/// Reads the DNS messages of a capture file and pairs them up.
+pub fn read_pairs(path: &str) -> Result<Vec<Pair>, String> {
+    let bytes = std::fs::read(path).map_err(|err| format!("Failed to read {}: {}", path, err))?;
+    let frames = read_capture(&bytes)?;
+    Ok(pair_messages(dns_messages(&frames)))
+}
+
+#[derive(Clone, Copy)]
+struct Endian {
+    big: bool,
+}
+
+impl Endian {
+    fn u16(&self, bytes: &[u8], at: usize) -> Result<u16, String> {
+        let b: [u8; 2] = bytes
+            .get(at..at + 2)
+            .and_then(|b| b.try_into().ok())
+            .ok_or_else(truncated)?;
+        Ok(if self.big {
+            u16::from_be_bytes(b)
+        } else {
+            u16::from_le_bytes(b)
+        })
+    }
+
+    fn u32(&self, bytes: &[u8], at: usize) -> Result<u32, String> {
+        let b: [u8; 4] = bytes
+            .get(at..at + 4)
+            .and_then(|b| b.try_into().ok())
+            .ok_or_else(truncated)?;
+        Ok(if self.big {
+            u32::from_be_bytes(b)
+        } else {
+            u32::from_le_bytes(b)
+        })
+    }
+}
+
+fn truncated() -> String {
+    String::from("The capture ends in the middle of a header.")
+}
+
+/// The frames of a pcap or pcapng file. A last record which was cut off,
+/// e.g. because the capture was killed, is left out.
+pub fn read_capture(bytes: &[u8]) -> Result<Vec<Frame>, String> {
+    match bytes.get(0..4) {
+        Some([0x0a, 0x0d, 0x0d, 0x0a]) => read_pcapng(bytes),
+        Some(_) => read_pcap(bytes),
+        None => Err(String::from("The capture is empty.")),
+    }
+}
+
+fn read_pcap(bytes: &[u8]) -> Result<Vec<Frame>, String> {
+    let magic = Endian { big: false }.u32(bytes, 0)?;
+    let (endian, nanos) = match magic {
+        0xa1b2_c3d4 => (Endian { big: false }, false),
+        0xd4c3_b2a1 => (Endian { big: true }, false),
+        0xa1b2_3c4d => (Endian { big: false }, true),
+        0x4d3c_b2a1 => (Endian { big: true }, true),
+        _ => return Err(String::from("Not a pcap or pcapng file.")),
+    };
+    // The upper bits may hold the FCS length.
+    let link_type = endian.u32(bytes, 20)? & 0x0fff_ffff;
+    let mut frames = vec![];
+    let mut pos = 24;
+    while pos + 16 <= bytes.len() {
+        let secs = endian.u32(bytes, pos)? as u64;
+        let frac = endian.u32(bytes, pos + 4)? as u64;
+        let start = pos + 16;
+        let end = start + endian.u32(bytes, pos + 8)? as usize;
+        if end > bytes.len() {
+            break;
+        }
+        let frac = if nanos {
+            Duration::from_nanos(frac)
+        } else {
+            Duration::from_micros(frac)
+        };
+        frames.push(Frame {
+            time: Duration::from_secs(secs) + frac,
+            link_type,
+            data: bytes[start..end].to_vec(),
+        });
+        pos = end;
+    }
+    Ok(frames)
+}
+
+/// An interface of a pcapng section: its link type and timestamp units
+/// per second.
+struct Interface {
+    link_type: u32,
+    units: u64,
+}
+
+fn read_pcapng(bytes: &[u8]) -> Result<Vec<Frame>, String> {
+    let mut endian = Endian { big: false };
+    let mut interfaces: Vec<Interface> = vec![];
+    let mut frames = vec![];
+    let mut pos = 0;
+    while pos + 12 <= bytes.len() {
+        if bytes[pos..pos + 4] == [0x0a, 0x0d, 0x0d, 0x0a] {
+            endian = match bytes[pos + 8..pos + 12] {
+                [0x4d, 0x3c, 0x2b, 0x1a] => Endian { big: false },
+                [0x1a, 0x2b, 0x3c, 0x4d] => Endian { big: true },
+                _ => return Err(String::from("Invalid byte order magic in pcapng section.")),
+            };
+            interfaces.clear();
+        }
+        let block_type = endian.u32(bytes, pos)?;
+        let total = endian.u32(bytes, pos + 4)? as usize;
+        if total < 12 || pos + total > bytes.len() {
+            break;
+        }
+        let body = &bytes[pos + 8..pos + total - 4];
+        match block_type {
+            // Interface description
+            1 => interfaces.push(Interface {
+                link_type: endian.u16(body, 0)? as u32,
+                units: timestamp_units(endian, body.get(8..).unwrap_or_default())?,
+            }),
+            // Enhanced packet
+            6 => {
+                let interface = interfaces
+                    .get(endian.u32(body, 0)? as usize)
+                    .ok_or_else(|| String::from("Packet of an undescribed interface."))?;
+                let ts = (endian.u32(body, 4)? as u64) << 32 | endian.u32(body, 8)? as u64;
+                let len = endian.u32(body, 12)? as usize;
+                let data = body.get(20..20 + len).ok_or_else(truncated)?;
+                frames.push(Frame {
+                    time: Duration::from_secs(ts / interface.units)
+                        + Duration::from_nanos(
+                            ((ts % interface.units) as u128 * 1_000_000_000
+                                / interface.units as u128) as u64,
+                        ),
+                    link_type: interface.link_type,
+                    data: data.to_vec(),
+                });
+            }
+            // Simple packet, without a timestamp
+            3 => {
+                let interface = interfaces
+                    .first()
+                    .ok_or_else(|| String::from("Packet of an undescribed interface."))?;
+                let len = (endian.u32(body, 0)? as usize).min(body.len().saturating_sub(4));
+                frames.push(Frame {
+                    time: Duration::ZERO,
+                    link_type: interface.link_type,
+                    data: body[4..4 + len].to_vec(),
+                });
+            }
+            _ => {}
+        }
+        pos += total;
+    }
+    Ok(frames)
+}
+
+/// The if_tsresol option of an interface, microseconds when it is missing.
+fn timestamp_units(endian: Endian, mut options: &[u8]) -> Result<u64, String> {
+    while options.len() >= 4 {
+        let code = endian.u16(options, 0)?;
+        let len = endian.u16(options, 2)? as usize;
+        if code == 0 {
+            break;
+        }
+        if code == 9 && len >= 1 {
+            let resol = *options.get(4).ok_or_else(truncated)?;
+            let exp = (resol & 0x7f) as u32;
+            let units = if resol & 0x80 == 0 {
+                10u64.checked_pow(exp)
+            } else {
+                2u64.checked_pow(exp)
+            };
+            return units.ok_or_else(|| String::from("Unsupported timestamp resolution."));
+        }
+        let padded = 4 + len.div_ceil(4) * 4;
+        options = options.get(padded..).unwrap_or_default();
+    }
+    Ok(1_000_000)
+}
+
+/// The DNS messages sent over UDP or TCP from or to port 53, in the order
+/// of the capture. Frames of other protocols, IP fragments and messages
+/// shorter than a DNS header are skipped.
+pub fn dns_messages(frames: &[Frame]) -> Vec<Message> {
+    let mut streams: HashMap<(SocketAddr, SocketAddr), Stream> = HashMap::new();
+    let mut messages = vec![];
+    for frame in frames {
+        let (src, dst, protocol, payload) = match link_payload(frame).and_then(ip_payload) {
+            Some(packet) => packet,
+            None => continue,
+        };
+        let port = |at: usize| u16::from_be_bytes([payload[at], payload[at + 1]]);
+        let mut found: Vec<(Transport, u16, u16, Vec<u8>)> = vec![];
+        match protocol {
+            17 if payload.len() >= 8 => {
+                let len = (port(4) as usize).clamp(8, payload.len());
+                found.push((Transport::Udp, port(0), port(2), payload[8..len].to_vec()));
+            }
+            6 if payload.len() >= 20 => {
+                let (sport, dport) = (port(0), port(2));
+                if sport != DNS_PORT && dport != DNS_PORT {
+                    continue;
+                }
+                let seq = u32::from_be_bytes([payload[4], payload[5], payload[6], payload[7]]);
+                let offset = ((payload[12] >> 4) as usize * 4).clamp(20, payload.len());
+                let syn = payload[13] & 0x02 != 0;
+                let key = (SocketAddr::new(src, sport), SocketAddr::new(dst, dport));
+                let stream = streams.entry(key).or_default();
+                for msg in stream.add(seq, syn, &payload[offset..]) {
+                    found.push((Transport::Tcp, sport, dport, msg));
+                }
+            }
+            _ => {}
+        }
+        for (transport, sport, dport, bytes) in found {
+            if (sport == DNS_PORT || dport == DNS_PORT) && bytes.len() >= 12 {
+                messages.push(Message {
+                    time: frame.time,
+                    transport,
+                    src: SocketAddr::new(src, sport),
+                    dst: SocketAddr::new(dst, dport),
+                    bytes,
+                });
+            }
+        }
+    }
+    messages
+}
+
+/// The IP packet in a frame.
+fn link_payload(frame: &Frame) -> Option<&[u8]> {
+    let data = frame.data.as_slice();
+    let ether_type = |at: usize| {
+        data.get(at..at + 2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]))
+    };
+    let (ether_type, start) = match frame.link_type {
+        LINKTYPE_ETHERNET => {
+            let mut at = 12;
+            // Skip 802.1Q and 802.1ad VLAN tags.
+            while matches!(ether_type(at)?, 0x8100 | 0x88a8) {
+                at += 4;
+            }
+            (ether_type(at)?, at + 2)
+        }
+        LINKTYPE_LINUX_SLL => (ether_type(14)?, 16),
+        LINKTYPE_LINUX_SLL2 => (ether_type(0)?, 20),
+        LINKTYPE_RAW => match data.first()? >> 4 {
+            4 => (ETHERTYPE_IPV4, 0),
+            6 => (ETHERTYPE_IPV6, 0),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    match ether_type {
+        ETHERTYPE_IPV4 | ETHERTYPE_IPV6 => data.get(start..),
+        _ => None,
+    }
+}
+
+/// Source, destination, protocol and payload of an IPv4 or IPv6 packet.
+fn ip_payload(packet: &[u8]) -> Option<(IpAddr, IpAddr, u8, &[u8])> {
+    match packet.first()? >> 4 {
+        4 if packet.len() >= 20 => {
+            let header = (packet[0] & 0x0f) as usize * 4;
+            let total = (u16::from_be_bytes([packet[2], packet[3]]) as usize).min(packet.len());
+            let fragment = u16::from_be_bytes([packet[6], packet[7]]);
+            if fragment & 0x3fff != 0 || header < 20 || header > total {
+                return None;
+            }
+            let src: [u8; 4] = packet[12..16].try_into().ok()?;
+            let dst: [u8; 4] = packet[16..20].try_into().ok()?;
+            Some((
+                IpAddr::V4(Ipv4Addr::from(src)),
+                IpAddr::V4(Ipv4Addr::from(dst)),
+                packet[9],
+                &packet[header..total],
+            ))
+        }
+        6 if packet.len() >= 40 => {
+            let end = (40 + u16::from_be_bytes([packet[4], packet[5]]) as usize).min(packet.len());
+            let src: [u8; 16] = packet[8..24].try_into().ok()?;
+            let dst: [u8; 16] = packet[24..40].try_into().ok()?;
+            let mut next = packet[6];
+            let mut pos = 40;
+            // Hop-by-hop, routing and destination options headers.
+            while matches!(next, 0 | 43 | 60) {
+                next = *packet.get(pos)?;
+                pos += (*packet.get(pos + 1)? as usize + 1) * 8;
+            }
+            if pos > end {
+                return None;
+            }
+            Some((
+                IpAddr::V6(Ipv6Addr::from(src)),
+                IpAddr::V6(Ipv6Addr::from(dst)),
+                next,
+                &packet[pos..end],
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// One direction of a TCP connection. Segments are put in order by their
+/// offset from the first sequence number seen, retransmitted bytes are
+/// dropped and the messages are cut from the stream by their length prefix.
+#[derive(Default)]
+struct Stream {
+    base: Option<u32>,
+    delivered: u32,
+    pending: BTreeMap<u32, Vec<u8>>,
+    buffer: Vec<u8>,
+}
+
+impl Stream {
+    /// Adds a segment and returns the messages it completes.
+    fn add(&mut self, seq: u32, syn: bool, data: &[u8]) -> Vec<Vec<u8>> {
+        let mut seq = seq;
+        if syn {
+            // The SYN takes up one sequence number.
+            seq = seq.wrapping_add(1);
+            *self = Stream::default();
+            self.base = Some(seq);
+        }
+        let base = *self.base.get_or_insert(seq);
+        let offset = seq.wrapping_sub(base);
+        // Offsets in the upper half were sent before the first segment seen.
+        if !data.is_empty() && offset < u32::MAX / 2 {
+            let segment = self.pending.entry(offset).or_default();
+            if data.len() > segment.len() {
+                *segment = data.to_vec();
+            }
+        }
+        while let Some((&start, _)) = self.pending.iter().next() {
+            if start > self.delivered {
+                break;
+            }
+            let segment = self.pending.remove(&start).unwrap_or_default();
+            let end = start + segment.len() as u32;
+            if end > self.delivered {
+                self.buffer
+                    .extend(&segment[(self.delivered - start) as usize..]);
+                self.delivered = end;
+            }
+        }
+        let mut messages = vec![];
+        while self.buffer.len() >= 2 {
+            let len = u16::from_be_bytes([self.buffer[0], self.buffer[1]]) as usize;
+            if self.buffer.len() < 2 + len {
+                break;
+            }
+            messages.push(self.buffer[2..2 + len].to_vec());
+            self.buffer.drain(..2 + len);
+        }
+        messages
+    }
+}
+
+/// Pairs every response with the oldest unanswered query of the same ID
+/// sent over the same transport between the same addresses and ports.
+/// The pairs are in the order of their first message.
+pub fn pair_messages(messages: Vec<Message>) -> Vec<Pair> {
+    let mut pairs: Vec<Pair> = vec![];
+    let mut open: HashMap<(Transport, SocketAddr, SocketAddr, u16), VecDeque<usize>> =
+        HashMap::new();
+    for msg in messages {
+        if msg.is_query() {
+            let key = (msg.transport, msg.src, msg.dst, msg.id());
+            open.entry(key).or_default().push_back(pairs.len());
+            pairs.push(Pair {
+                query: Some(msg),
+                response: None,
+            });
+            continue;
+        }
+        let key = (msg.transport, msg.dst, msg.src, msg.id());
+        match open.get_mut(&key).and_then(VecDeque::pop_front) {
+            Some(idx) => pairs[idx].response = Some(msg),
+            None => pairs.push(Pair {
+                query: None,
+                response: Some(msg),
+            }),
+        }
+    }
+    pairs
+}
+
+/// A capture file shared by the clients which record their traffic.
+pub type Capture = Arc<Mutex<PcapWriter>>;
+
+/// Writes messages as synthetic IP packets with UDP or TCP headers to a
+/// pcap file of raw IP frames, which Wireshark and tcpdump can open.
+/// Messages over TCP are preceded by a handshake the first time a
+/// connection is seen, so the stream can be followed.
+pub struct PcapWriter {
+    file: File,
+    /// The next sequence number of each direction of a TCP connection.
+    tcp_seq: HashMap<(SocketAddr, SocketAddr), u32>,
+}
+
+const TCP_SYN: u8 = 0x02;
+const TCP_ACK: u8 = 0x10;
+const TCP_PSH: u8 = 0x08;
+
+impl PcapWriter {
+    pub fn create(path: &str) -> Result<Self, String> {
+        let mut file =
+            File::create(path).map_err(|err| format!("Failed to create {}: {}", path, err))?;
+        let mut header = 0xa1b2_c3d4u32.to_le_bytes().to_vec();
+        header.extend(2u16.to_le_bytes());
+        header.extend(4u16.to_le_bytes());
+        header.extend([0; 8]);
+        header.extend(65535u32.to_le_bytes());
+        header.extend(LINKTYPE_RAW.to_le_bytes());
+        file.write_all(&header)
+            .map_err(|err| format!("Failed to write {}: {}", path, err))?;
+        Ok(Self {
+            file,
+            tcp_seq: HashMap::new(),
+        })
+    }
+
+    /// Wraps `msg` into a packet from `src` to `dst` captured at `time`.
+    pub fn write(
+        &mut self,
+        time: SystemTime,
+        transport: Transport,
+        src: SocketAddr,
+        dst: SocketAddr,
+        msg: &[u8],
+    ) -> Result<(), String> {
+        match transport {
+            Transport::Udp => {
+                let mut udp = src.port().to_be_bytes().to_vec();
+                udp.extend(dst.port().to_be_bytes());
+                udp.extend(length_field(8 + msg.len())?);
+                udp.extend([0, 0]);
+                udp.extend(msg);
+                self.write_packet(time, src, dst, 17, udp)
+            }
+            Transport::Tcp => {
+                if !self.tcp_seq.contains_key(&(src, dst)) {
+                    self.handshake(time, src, dst)?;
+                }
+                let mut data = length_field(msg.len())?.to_vec();
+                data.extend(msg);
+                self.write_segment(time, src, dst, TCP_PSH | TCP_ACK, &data)
+            }
+        }
+    }
+
+    fn handshake(
+        &mut self,
+        time: SystemTime,
+        src: SocketAddr,
+        dst: SocketAddr,
+    ) -> Result<(), String> {
+        self.tcp_seq.insert((src, dst), 0);
+        self.tcp_seq.insert((dst, src), 0);
+        self.write_segment(time, src, dst, TCP_SYN, &[])?;
+        self.write_segment(time, dst, src, TCP_SYN | TCP_ACK, &[])?;
+        self.write_segment(time, src, dst, TCP_ACK, &[])
+    }
+
+    fn write_segment(
+        &mut self,
+        time: SystemTime,
+        src: SocketAddr,
+        dst: SocketAddr,
+        flags: u8,
+        data: &[u8],
+    ) -> Result<(), String> {
+        let seq = self.tcp_seq.get(&(src, dst)).copied().unwrap_or_default();
+        let ack = self.tcp_seq.get(&(dst, src)).copied().unwrap_or_default();
+        // A SYN takes up one sequence number.
+        let used = data.len() as u32 + (flags & TCP_SYN != 0) as u32;
+        self.tcp_seq.insert((src, dst), seq.wrapping_add(used));
+        let mut tcp = src.port().to_be_bytes().to_vec();
+        tcp.extend(dst.port().to_be_bytes());
+        tcp.extend(seq.to_be_bytes());
+        let ack = if flags & TCP_ACK != 0 { ack } else { 0 };
+        tcp.extend(ack.to_be_bytes());
+        tcp.extend([0x50, flags, 0xff, 0xff, 0, 0, 0, 0]);
+        tcp.extend(data);
+        self.write_packet(time, src, dst, 6, tcp)
+    }
+
+    /// Adds the IP header, fills in the checksum of the UDP or TCP header
+    /// and writes the record.
+    fn write_packet(
+        &mut self,
+        time: SystemTime,
+        src: SocketAddr,
+        dst: SocketAddr,
+        protocol: u8,
+        mut segment: Vec<u8>,
+    ) -> Result<(), String> {
+        let mut pseudo = vec![];
+        let mut packet = match (src.ip(), dst.ip()) {
+            (IpAddr::V4(s), IpAddr::V4(d)) => {
+                pseudo.extend(s.octets());
+                pseudo.extend(d.octets());
+                pseudo.extend([0, protocol]);
+                pseudo.extend(length_field(segment.len())?);
+                let mut ip = vec![0x45, 0];
+                ip.extend(length_field(20 + segment.len())?);
+                ip.extend([0, 0, 0x40, 0, 64, protocol, 0, 0]);
+                ip.extend(s.octets());
+                ip.extend(d.octets());
+                let sum = checksum(&ip);
+                ip[10..12].copy_from_slice(&sum.to_be_bytes());
+                ip
+            }
+            (IpAddr::V6(s), IpAddr::V6(d)) => {
+                pseudo.extend(s.octets());
+                pseudo.extend(d.octets());
+                pseudo.extend((segment.len() as u32).to_be_bytes());
+                pseudo.extend([0, 0, 0, protocol]);
+                let mut ip = vec![0x60, 0, 0, 0];
+                ip.extend(length_field(segment.len())?);
+                ip.extend([protocol, 64]);
+                ip.extend(s.octets());
+                ip.extend(d.octets());
+                ip
+            }
+            _ => {
+                return Err(format!(
+                    "{} and {} are of different address families.",
+                    src, dst
+                ))
+            }
+        };
+        let at = if protocol == 17 { 6 } else { 16 };
+        pseudo.extend(&segment);
+        let sum = match checksum(&pseudo) {
+            // Zero means no checksum for UDP.
+            0 if protocol == 17 => 0xffff,
+            sum => sum,
+        };
+        segment[at..at + 2].copy_from_slice(&sum.to_be_bytes());
+        packet.extend(segment);
+
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let mut record = (since_epoch.as_secs() as u32).to_le_bytes().to_vec();
+        record.extend(since_epoch.subsec_micros().to_le_bytes());
+        record.extend((packet.len() as u32).to_le_bytes());
+        record.extend((packet.len() as u32).to_le_bytes());
+        record.extend(packet);
+        // Written at once, so the file stays readable if askrs is killed.
+        self.file
+            .write_all(&record)
+            .map_err(|err| format!("Failed to write the capture: {}", err))
+    }
+}
+
+/// `len` as a 16 bit length field of a header, which larger packets
+/// don't fit.
+fn length_field(len: usize) -> Result<[u8; 2], String> {
+    u16::try_from(len)
+        .map(u16::to_be_bytes)
+        .map_err(|_| format!("A packet of {} bytes is too long to capture.", len))
+}
+
+/// The Internet checksum of RFC 1071.
+fn checksum(bytes: &[u8]) -> u16 {
+    let mut sum: u32 = bytes
+        .chunks(2)
+        .map(|pair| u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)]) as u32)
+        .sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Writes `msg` to the capture, if there is one. Failures are reported
+/// without failing the lookup.
+pub fn record(capture: &Option<Capture>, src: SocketAddr, dst: SocketAddr, msg: &[u8]) {
+    if let Some(capture) = capture {
+        let mut writer = capture.lock().unwrap();
+        if let Err(err) = writer.write(SystemTime::now(), Transport::Udp, src, dst, msg) {
+            eprintln!("{}", err);
+        }
+    }
+}
+
+/// The address a socket bound to the unspecified address sends from to
+/// reach `server`, as picked by the routing table.
+pub fn source_address(port: u16, server: &SocketAddr) -> SocketAddr {
+    let unspecified: IpAddr = match server {
+        SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    };
+    let ip = UdpSocket::bind((unspecified, 0))
+        .and_then(|socket| {
+            socket.connect(server)?;
+            socket.local_addr()
+        })
+        .map(|addr| addr.ip())
+        .unwrap_or(unspecified);
+    SocketAddr::new(ip, port)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUERY: [u8; 29] = [
+        0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0, 7, b'e', b'x', b'a', b'm', b'p', b'l',
+        b'e', 3, b'c', b'o', b'm', 0, 0, 1, 0, 1,
+    ];
+
+    fn response() -> Vec<u8> {
+        let mut resp = QUERY.to_vec();
+        resp[2] = 0x81;
+        resp[3] = 0x80;
+        resp[7] = 1;
+        resp.extend([0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 192, 0, 2, 1]);
+        resp
+    }
+
+    fn ipv4_udp(src: [u8; 4], dst: [u8; 4], sport: u16, dport: u16, payload: &[u8]) -> Vec<u8> {
+        let mut udp = sport.to_be_bytes().to_vec();
+        udp.extend(dport.to_be_bytes());
+        udp.extend((8 + payload.len() as u16).to_be_bytes());
+        udp.extend([0, 0]);
+        udp.extend(payload);
+        let mut ip = vec![0x45, 0];
+        ip.extend((20 + udp.len() as u16).to_be_bytes());
+        ip.extend([0, 0, 0x40, 0, 64, 17, 0, 0]);
+        ip.extend(src);
+        ip.extend(dst);
+        ip.extend(udp);
+        ip
+    }
+
+    fn ethernet(ip: Vec<u8>) -> Vec<u8> {
+        let mut frame = vec![0; 12];
+        frame.extend(ETHERTYPE_IPV4.to_be_bytes());
+        frame.extend(ip);
+        frame
+    }
+
+    fn pcap(frames: &[(u32, u32, Vec<u8>)]) -> Vec<u8> {
+        let mut file = vec![0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0];
+        file.extend([0; 8]);
+        file.extend(65535u32.to_le_bytes());
+        file.extend(LINKTYPE_ETHERNET.to_le_bytes());
+        for (secs, micros, data) in frames {
+            file.extend(secs.to_le_bytes());
+            file.extend(micros.to_le_bytes());
+            file.extend((data.len() as u32).to_le_bytes());
+            file.extend((data.len() as u32).to_le_bytes());
+            file.extend(data);
+        }
+        file
+    }
+
+    #[test]
+    fn test_pcap_udp_pairs() {
+        let client = [192, 0, 2, 10];
+        let server = [198, 51, 100, 53];
+        let file = pcap(&[
+            (
+                100,
+                0,
+                ethernet(ipv4_udp(client, server, 40000, 53, &QUERY)),
+            ),
+            (100, 1000, ethernet(vec![0x45])),
+            (
+                100,
+                25000,
+                ethernet(ipv4_udp(server, client, 53, 40000, &response())),
+            ),
+        ]);
+        let frames = read_capture(&file).unwrap();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[2].time, Duration::new(100, 25_000_000));
+        let pairs = pair_messages(dns_messages(&frames));
+        assert_eq!(pairs.len(), 1);
+        let query = pairs[0].query.as_ref().unwrap();
+        assert_eq!(query.src, "192.0.2.10:40000".parse().unwrap());
+        assert_eq!(query.transport, Transport::Udp);
+        assert_eq!(pairs[0].response.as_ref().unwrap().bytes, response());
+        assert_eq!(pairs[0].latency(), Some(Duration::from_millis(25)));
+
+        // A cut off record at the end is left out.
+        assert_eq!(read_capture(&file[..file.len() - 1]).unwrap().len(), 2);
+    }
+
+    fn ipv6_tcp(
+        src: Ipv6Addr,
+        dst: Ipv6Addr,
+        ports: (u16, u16),
+        seq: u32,
+        flags: u8,
+        data: &[u8],
+    ) -> Vec<u8> {
+        let mut tcp = ports.0.to_be_bytes().to_vec();
+        tcp.extend(ports.1.to_be_bytes());
+        tcp.extend(seq.to_be_bytes());
+        tcp.extend([0, 0, 0, 0, 0x50, flags, 0xff, 0xff, 0, 0, 0, 0]);
+        tcp.extend(data);
+        let mut ip = vec![0x60, 0, 0, 0];
+        ip.extend((tcp.len() as u16).to_be_bytes());
+        ip.extend([6, 64]);
+        ip.extend(src.octets());
+        ip.extend(dst.octets());
+        ip.extend(tcp);
+        ip
+    }
+
+    fn linux_sll(ip: Vec<u8>) -> Vec<u8> {
+        let mut frame = vec![0; 14];
+        frame.extend(ETHERTYPE_IPV6.to_be_bytes());
+        frame.extend(ip);
+        frame
+    }
+
+    fn block(block_type: u32, body: Vec<u8>) -> Vec<u8> {
+        let mut body = body;
+        body.resize(body.len().div_ceil(4) * 4, 0);
+        let total = (body.len() + 12) as u32;
+        let mut block = block_type.to_le_bytes().to_vec();
+        block.extend(total.to_le_bytes());
+        block.extend(body);
+        block.extend(total.to_le_bytes());
+        block
+    }
+
+    fn enhanced_packet(nanos: u64, data: Vec<u8>) -> Vec<u8> {
+        let mut body = 0u32.to_le_bytes().to_vec();
+        body.extend(((nanos >> 32) as u32).to_le_bytes());
+        body.extend((nanos as u32).to_le_bytes());
+        body.extend((data.len() as u32).to_le_bytes());
+        body.extend((data.len() as u32).to_le_bytes());
+        body.extend(data);
+        block(6, body)
+    }
+
+    #[test]
+    fn test_pcapng_tcp_reassembly() {
+        let client: Ipv6Addr = "2001:db8::10".parse().unwrap();
+        let server: Ipv6Addr = "2001:db8::53".parse().unwrap();
+        let mut query = (QUERY.len() as u16).to_be_bytes().to_vec();
+        query.extend(QUERY);
+        let mut resp = (response().len() as u16).to_be_bytes().to_vec();
+        resp.extend(response());
+
+        let mut file = block(0x0a0d_0d0a, {
+            let mut body = vec![0x4d, 0x3c, 0x2b, 0x1a, 1, 0, 0, 0];
+            body.extend([0xff; 8]);
+            body
+        });
+        // LINKTYPE_LINUX_SLL with nanosecond timestamps.
+        file.extend(block(
+            1,
+            vec![113, 0, 0, 0, 0, 0, 0, 0, 9, 0, 1, 0, 9, 0, 0, 0],
+        ));
+        let to_server = (40000, 53);
+        let to_client = (53, 40000);
+        let packets = [
+            (0, ipv6_tcp(client, server, to_server, 999, 0x02, &[])),
+            (
+                1_000,
+                ipv6_tcp(client, server, to_server, 1000, 0x18, &query),
+            ),
+            (2_000, ipv6_tcp(server, client, to_client, 5000, 0x12, &[])),
+            // The second half of the response overtakes the first one.
+            (
+                9_000_000,
+                ipv6_tcp(server, client, to_client, 5011, 0x18, &resp[10..]),
+            ),
+            (
+                9_500_000,
+                ipv6_tcp(server, client, to_client, 5001, 0x18, &resp[..10]),
+            ),
+            // A retransmission of the first half is dropped.
+            (
+                9_600_000,
+                ipv6_tcp(server, client, to_client, 5001, 0x18, &resp[..10]),
+            ),
+        ];
+        for (nanos, ip) in packets {
+            file.extend(enhanced_packet(
+                1_700_000_000_000_000_000 + nanos,
+                linux_sll(ip),
+            ));
+        }
+
+        let frames = read_capture(&file).unwrap();
+        assert_eq!(frames.len(), 6);
+        assert_eq!(frames[1].time, Duration::new(1_700_000_000, 1_000));
+        let messages = dns_messages(&frames);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].bytes, QUERY.to_vec());
+        assert_eq!(messages[1].bytes, response());
+        assert_eq!(messages[1].transport, Transport::Tcp);
+        let pairs = pair_messages(messages);
+        assert_eq!(pairs.len(), 1);
+        assert_eq!(pairs[0].latency(), Some(Duration::new(0, 9_499_000)));
+    }
+
+    #[test]
+    fn test_write_pcap() {
+        let path = std::env::temp_dir().join("askrs_test_write.pcap");
+        let path = path.to_str().unwrap();
+        let client: SocketAddr = "192.0.2.10:40000".parse().unwrap();
+        let server: SocketAddr = "192.0.2.53:53".parse().unwrap();
+        let client6: SocketAddr = "[2001:db8::10]:40001".parse().unwrap();
+        let server6: SocketAddr = "[2001:db8::53]:53".parse().unwrap();
+        let sent = UNIX_EPOCH + Duration::new(1_700_000_000, 250_000_000);
+        let received = sent + Duration::from_millis(20);
+        let mut writer = PcapWriter::create(path).unwrap();
+        writer
+            .write(sent, Transport::Udp, client, server, &QUERY)
+            .unwrap();
+        writer
+            .write(received, Transport::Udp, server, client, &response())
+            .unwrap();
+        writer
+            .write(sent, Transport::Tcp, client6, server6, &QUERY)
+            .unwrap();
+        writer
+            .write(received, Transport::Tcp, server6, client6, &response())
+            .unwrap();
+        // Messages whose length does not fit the headers are not written.
+        let oversized = vec![0; 65535];
+        assert!(writer
+            .write(sent, Transport::Udp, client, server, &oversized)
+            .is_err());
+        assert!(writer
+            .write(sent, Transport::Tcp, client6, server6, &oversized)
+            .is_err());
+        drop(writer);
+
+        let frames = read_capture(&std::fs::read(path).unwrap()).unwrap();
+        // Two datagrams, the handshake and two segments.
+        assert_eq!(frames.len(), 7);
+        assert_eq!(frames[0].link_type, LINKTYPE_RAW);
+        assert_eq!(frames[1].time, Duration::new(1_700_000_000, 270_000_000));
+        // The IPv4 header checksum verifies to zero.
+        assert_eq!(checksum(&frames[0].data[..20]), 0);
+        let pairs = pair_messages(dns_messages(&frames));
+        assert_eq!(pairs.len(), 2);
+        assert_eq!(pairs[0].latency(), Some(Duration::from_millis(20)));
+        assert_eq!(pairs[1].query.as_ref().unwrap().transport, Transport::Tcp);
+        assert_eq!(pairs[1].query.as_ref().unwrap().src, client6);
+        assert_eq!(pairs[1].response.as_ref().unwrap().bytes, response());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_unpaired_messages() {
+        let msg = |bytes: Vec<u8>, src: &str, dst: &str| Message {
+            time: Duration::ZERO,
+            transport: Transport::Udp,
+            src: src.parse().unwrap(),
+            dst: dst.parse().unwrap(),
+            bytes,
+        };
+        let pairs = pair_messages(vec![
+            msg(QUERY.to_vec(), "192.0.2.1:1000", "192.0.2.53:53"),
+            // Another client port, so it does not answer the query.
+            msg(response(), "192.0.2.53:53", "192.0.2.1:1001"),
+        ]);
+        assert_eq!(pairs.len(), 2);
+        assert!(pairs[0].response.is_none());
+        assert!(pairs[1].query.is_none());
+        assert_eq!(pairs[1].latency(), None);
+    }
+
+    #[test]
+    fn test_timestamp_units() {
+        let endian = Endian { big: false };
+        assert_eq!(timestamp_units(endian, &[]).unwrap(), 1_000_000);
+        assert_eq!(
+            timestamp_units(endian, &[9, 0, 1, 0, 9, 0, 0, 0]).unwrap(),
+            1_000_000_000
+        );
+        assert_eq!(timestamp_units(endian, &[9, 0, 1, 0, 0x83]).unwrap(), 8);
+        // The option claims a byte of data, but the block ends before it.
+        assert!(timestamp_units(endian, &[9, 0, 1, 0]).is_err());
+    }
+}
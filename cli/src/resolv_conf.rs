@@ -0,0 +1,199 @@
+use std::{
+    fs,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Upper bounds the libc resolver applies to the values it reads.
+const MAX_NAMESERVERS: usize = 3;
+const MAX_NDOTS: u8 = 15;
+const MAX_TIMEOUT: u64 = 30;
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Stub resolver configuration as read from `resolv.conf(5)`.
+pub struct ResolvConf {
+    pub nameservers: Vec<SocketAddr>,
+    pub search: Vec<String>,
+    pub ndots: u8,
+    pub timeout: u64,
+    pub attempts: u32,
+    pub rotate: bool,
+}
+
+impl Default for ResolvConf {
+    fn default() -> Self {
+        Self {
+            nameservers: vec![],
+            search: vec![],
+            ndots: 1,
+            timeout: 5,
+            attempts: 2,
+            rotate: false,
+        }
+    }
+}
+
+impl ResolvConf {
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        match fs::read_to_string(path) {
+            Ok(content) => Ok(Self::parse(&content)),
+            Err(err) => Err(format!("Failed to read {}: {}", path, err)),
+        }
+    }
+
+    /// Parses the content of a resolv.conf file. Unknown keywords and
+    /// malformed values are ignored the same way the libc resolver does.
+    pub fn parse(content: &str) -> Self {
+        let mut conf = Self::default();
+        for line in content.lines() {
+            let line = match line.find(['#', ';']) {
+                Some(idx) => &line[..idx],
+                None => line,
+            };
+            let mut parts = line.split_whitespace();
+            match parts.next() {
+                Some("nameserver") => {
+                    let addr = parts.next().and_then(|a| a.parse::<IpAddr>().ok());
+                    if let Some(addr) = addr {
+                        if conf.nameservers.len() < MAX_NAMESERVERS {
+                            conf.nameservers.push(SocketAddr::new(addr, 53));
+                        }
+                    }
+                }
+                Some("domain") => {
+                    if let Some(domain) = parts.next() {
+                        conf.search = vec![domain.trim_end_matches('.').to_string()];
+                    }
+                }
+                Some("search") => {
+                    conf.search = parts.map(|d| d.trim_end_matches('.').to_string()).collect();
+                }
+                Some("options") => {
+                    for opt in parts {
+                        conf.set_option(opt);
+                    }
+                }
+                _ => continue,
+            }
+        }
+        conf
+    }
+
+    fn set_option(&mut self, opt: &str) {
+        let (key, value) = match opt.split_once(':') {
+            Some((key, value)) => (key, value.parse::<u64>().ok()),
+            None => (opt, None),
+        };
+        match (key, value) {
+            ("ndots", Some(n)) => self.ndots = n.min(MAX_NDOTS as u64) as u8,
+            ("timeout", Some(n)) => self.timeout = n.clamp(1, MAX_TIMEOUT),
+            ("attempts", Some(n)) => self.attempts = n.clamp(1, MAX_ATTEMPTS as u64) as u32,
+            ("rotate", None) => self.rotate = true,
+            _ => (),
+        }
+    }
+
+    /// Nameservers to query in order. Without any configured nameserver the
+    /// local host is used, like the libc resolver does. With the `rotate`
+    /// option the list starts at a different server on every invocation.
+    pub fn get_addresses(&self) -> Vec<SocketAddr> {
+        if self.nameservers.is_empty() {
+            return vec![SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 53)];
+        }
+        let mut addrs = self.nameservers.to_vec();
+        if self.rotate {
+            let seed = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.subsec_nanos() as usize)
+                .unwrap_or_default();
+            let start = seed % addrs.len();
+            addrs.rotate_left(start);
+        }
+        addrs
+    }
+
+    /// Names to look up for `name` in order, applying the search list.
+    ///
+    /// Names ending with a dot are absolute and never extended. Names with
+    /// at least `ndots` dots are tried as given before the search list,
+    /// all other names are tried as given after the search list.
+    pub fn candidate_names(&self, name: &str) -> Vec<String> {
+        if let Some(absolute) = name.strip_suffix('.') {
+            return vec![absolute.to_string()];
+        }
+        let searched: Vec<String> = self
+            .search
+            .iter()
+            .map(|domain| format!("{}.{}", name, domain))
+            .collect();
+        let mut names: Vec<String> = vec![];
+        if name.matches('.').count() >= self.ndots as usize {
+            names.push(name.to_string());
+            names.extend(searched);
+        } else {
+            names.extend(searched);
+            names.push(name.to_string());
+        }
+        names
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONF: &str = "# generated by NetworkManager
+domain example.net
+search corp.example.com example.com.
+nameserver 10.0.0.53 ; primary
+nameserver 2001:db8::53
+nameserver not-an-address
+nameserver 10.0.0.54
+nameserver 10.0.0.55
+options ndots:2 timeout:3 attempts:9 rotate edns0
+";
+
+    #[test]
+    fn test_parse() {
+        let conf = ResolvConf::parse(CONF);
+        assert_eq!(
+            conf.nameservers,
+            vec![
+                "10.0.0.53:53".parse().unwrap(),
+                "[2001:db8::53]:53".parse().unwrap(),
+                "10.0.0.54:53".parse().unwrap(),
+            ]
+        );
+        assert_eq!(conf.search, vec!["corp.example.com", "example.com"]);
+        assert_eq!(conf.ndots, 2);
+        assert_eq!(conf.timeout, 3);
+        assert_eq!(conf.attempts, MAX_ATTEMPTS);
+        assert!(conf.rotate);
+        assert_eq!(conf.get_addresses().len(), 3);
+    }
+
+    #[test]
+    fn test_empty_conf_uses_localhost() {
+        let conf = ResolvConf::parse("");
+        assert_eq!(conf.ndots, 1);
+        assert_eq!(conf.get_addresses(), vec!["127.0.0.1:53".parse().unwrap()]);
+    }
+
+    #[test]
+    fn test_candidate_names() {
+        let conf = ResolvConf::parse(CONF);
+        assert_eq!(
+            conf.candidate_names("www"),
+            vec!["www.corp.example.com", "www.example.com", "www"]
+        );
+        assert_eq!(
+            conf.candidate_names("www.example.org"),
+            vec![
+                "www.example.org",
+                "www.example.org.corp.example.com",
+                "www.example.org.example.com"
+            ]
+        );
+        assert_eq!(conf.candidate_names("www."), vec!["www"]);
+    }
+}
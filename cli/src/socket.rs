@@ -1,66 +1,399 @@
 use std::{
-    net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket},
-    time::Duration,
+    io::ErrorKind,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    time::{Duration, Instant},
 };
 
+use dns::header::Header;
+use dns::header_flags::RCODE;
+use parsing::Edns;
 use utility::Blob;
 
+use crate::pcap::{record, Capture};
+use crate::resolv_conf::ResolvConf;
+
 pub enum DNSSocket {
     GOOGLE,
     CLOUDFLARE,
     QUAD9,
+    SYSTEM(Vec<SocketAddr>),
+    ADDRESS(SocketAddr),
+    /// A server of the configuration file with its EDNS options.
+    PROFILE(Vec<SocketAddr>, Option<Edns>),
 }
 
 impl DNSSocket {
-    fn get_ip(&self) -> SocketAddr {
+    fn preset(
+        primary_v4: Ipv4Addr,
+        secondary_v4: Ipv4Addr,
+        primary_v6: Ipv6Addr,
+        secondary_v6: Ipv6Addr,
+    ) -> Vec<SocketAddr> {
+        vec![
+            SocketAddr::new(primary_v4.into(), 53),
+            SocketAddr::new(secondary_v4.into(), 53),
+            SocketAddr::new(primary_v6.into(), 53),
+            SocketAddr::new(secondary_v6.into(), 53),
+        ]
+    }
+
+    /// Addresses of the server in the order they are tried. Presets list
+    /// primary and secondary IPv4 first, then primary and secondary IPv6.
+    pub fn get_addresses(&self) -> Vec<SocketAddr> {
         match self {
-            DNSSocket::GOOGLE => SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(8, 8, 4, 4), 53)),
-            DNSSocket::CLOUDFLARE => {
-                SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(1, 1, 1, 1), 53))
-            }
-            DNSSocket::QUAD9 => SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(9, 9, 9, 9), 53)),
+            DNSSocket::GOOGLE => Self::preset(
+                Ipv4Addr::new(8, 8, 8, 8),
+                Ipv4Addr::new(8, 8, 4, 4),
+                Ipv6Addr::new(0x2001, 0x4860, 0x4860, 0, 0, 0, 0, 0x8888),
+                Ipv6Addr::new(0x2001, 0x4860, 0x4860, 0, 0, 0, 0, 0x8844),
+            ),
+            DNSSocket::CLOUDFLARE => Self::preset(
+                Ipv4Addr::new(1, 1, 1, 1),
+                Ipv4Addr::new(1, 0, 0, 1),
+                Ipv6Addr::new(0x2606, 0x4700, 0x4700, 0, 0, 0, 0, 0x1111),
+                Ipv6Addr::new(0x2606, 0x4700, 0x4700, 0, 0, 0, 0, 0x1001),
+            ),
+            DNSSocket::QUAD9 => Self::preset(
+                Ipv4Addr::new(9, 9, 9, 9),
+                Ipv4Addr::new(149, 112, 112, 112),
+                Ipv6Addr::new(0x2620, 0xfe, 0, 0, 0, 0, 0, 0xfe),
+                Ipv6Addr::new(0x2620, 0xfe, 0, 0, 0, 0, 0, 0x9),
+            ),
+            DNSSocket::SYSTEM(addrs) => addrs.to_vec(),
+            DNSSocket::ADDRESS(addr) => vec![*addr],
+            DNSSocket::PROFILE(addrs, _) => addrs.to_vec(),
+        }
+    }
+
+    /// The EDNS options to send queries with, only profiles have any.
+    pub fn edns(&self) -> Option<Edns> {
+        match self {
+            DNSSocket::PROFILE(_, edns) => *edns,
+            _ => None,
         }
     }
 
-    pub fn from_string(name: &str) -> Self {
+    /// Resolves one of the preset names or an IP address with an optional
+    /// port, `system` has to be resolved with [`DNSSocket::from_resolv_conf`].
+    pub fn from_string(name: &str) -> Result<Self, String> {
+        if let Ok(addr) = name.parse::<SocketAddr>() {
+            return Ok(DNSSocket::ADDRESS(addr));
+        }
+        if let Ok(ip) = name.parse::<IpAddr>() {
+            return Ok(DNSSocket::ADDRESS(SocketAddr::new(ip, 53)));
+        }
         match name {
-            "google" => DNSSocket::GOOGLE,
-            "cloudflare" => DNSSocket::CLOUDFLARE,
-            "quad9" => DNSSocket::QUAD9,
-            _ => DNSSocket::GOOGLE,
+            "google" => Ok(DNSSocket::GOOGLE),
+            "cloudflare" => Ok(DNSSocket::CLOUDFLARE),
+            "quad9" => Ok(DNSSocket::QUAD9),
+            _ => Err(format!(
+                "Unknown server {}, expected system, google, cloudflare, quad9, a configured server or an IP address.",
+                name
+            )),
+        }
+    }
+
+    pub fn from_resolv_conf(conf: &ResolvConf) -> Self {
+        DNSSocket::SYSTEM(conf.get_addresses())
+    }
+}
+
+/// Controls how often and how long the client waits for an answer.
+///
+/// Every attempt is a full pass over all addresses of a server. The
+/// timeout of each attempt is the previous one multiplied by `backoff`.
+pub struct RetryPolicy {
+    pub attempts: u32,
+    pub timeout: Duration,
+    pub backoff: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 2,
+            timeout: Duration::from_secs(5),
+            backoff: 2,
         }
     }
 }
 
-pub struct UDPClient {}
+impl RetryPolicy {
+    pub fn new(attempts: u32, timeout: Duration, backoff: u32) -> Self {
+        Self {
+            attempts,
+            timeout,
+            backoff,
+        }
+    }
+
+    pub fn timeout_for(&self, attempt: u32) -> Duration {
+        self.timeout
+            .saturating_mul(self.backoff.saturating_pow(attempt))
+    }
+}
+
+/// Answers with SERVFAIL or REFUSED are worth asking the next server for.
+pub fn should_fail_over(answer: &[u8]) -> bool {
+    let rcode = Header::from_bytes(answer.to_vec()).rcode();
+    matches!(rcode, RCODE::SRVFAIL | RCODE::REFUSED)
+}
+
+pub struct UDPClient {
+    policy: RetryPolicy,
+    verbose: bool,
+    capture: Option<Capture>,
+}
+
+/// An answer together with the address it came from and the round trip time.
+pub struct Exchange {
+    pub answer: Vec<u8>,
+    pub server: SocketAddr,
+    pub rtt: Duration,
+}
+
 impl UDPClient {
-    pub fn send_and_recieve(&self, msg: Vec<u8>, server: DNSSocket) -> Result<Vec<u8>, String> {
-        let msg_bytes = msg
-            .to_socket_msg()
-            .expect("Failed to parse msg to socket data.");
+    pub fn new(policy: RetryPolicy, verbose: bool) -> Self {
+        Self {
+            policy,
+            verbose,
+            capture: None,
+        }
+    }
 
-        let socket =
-            UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).expect("Failed to initialize udp socket.");
-        println!("Socket bound to local address 0.0.0.0:0");
+    /// Records every query sent and answer received in `capture`.
+    pub fn with_capture(self, capture: Option<Capture>) -> Self {
+        Self { capture, ..self }
+    }
+
+    pub fn send_and_recieve(&self, msg: Vec<u8>, server: &DNSSocket) -> Result<Vec<u8>, String> {
+        self.send_to_any(msg, &server.get_addresses())
+    }
+
+    /// Sends `msg` to the first address that answers. Timeouts, socket errors
+    /// and SERVFAIL or REFUSED answers make the client fail over to the next
+    /// address. If every address answered with an error code, the last of
+    /// those answers is returned so the caller can inspect the RCODE.
+    pub fn send_to_any(&self, msg: Vec<u8>, addrs: &[SocketAddr]) -> Result<Vec<u8>, String> {
+        self.exchange_with_any(msg, addrs).map(|ex| ex.answer)
+    }
+
+    /// Like [`UDPClient::send_to_any`], but also reports which address
+    /// answered and how long the answer took.
+    pub fn exchange_with_any(
+        &self,
+        msg: Vec<u8>,
+        addrs: &[SocketAddr],
+    ) -> Result<Exchange, String> {
+        if addrs.is_empty() {
+            return Err(String::from("No server addresses to send the query to."));
+        }
+        let mut last_err = String::new();
+        let mut last_answer: Option<Exchange> = None;
+        for attempt in 0..self.policy.attempts.max(1) {
+            let timeout = self.policy.timeout_for(attempt);
+            for addr in addrs {
+                match self.exchange(&msg, addr, timeout) {
+                    Ok(ex) => {
+                        if !should_fail_over(&ex.answer) {
+                            return Ok(ex);
+                        }
+                        eprintln!(
+                            "{} answered with an error: {}",
+                            addr,
+                            Header::from_bytes(ex.answer.to_vec()).rcode()
+                        );
+                        last_answer = Some(ex);
+                    }
+                    Err(err) => {
+                        eprintln!("{}: {}", addr, err);
+                        last_err = err;
+                    }
+                }
+            }
+        }
+        match last_answer {
+            Some(ex) => Ok(ex),
+            None => Err(format!(
+                "No server answered after {} attempts: {}",
+                self.policy.attempts.max(1),
+                last_err
+            )),
+        }
+    }
+
+    fn exchange(
+        &self,
+        msg: &Vec<u8>,
+        addr: &SocketAddr,
+        timeout: Duration,
+    ) -> Result<Exchange, String> {
+        let msg_bytes = msg.to_socket_msg()?;
+        let socket = match addr {
+            SocketAddr::V4(_) => UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)),
+            SocketAddr::V6(_) => UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0)),
+        }
+        .map_err(|err| format!("Failed to initialize udp socket: {}", err))?;
+        if self.verbose {
+            eprintln!(
+                "Socket bound to local address {}",
+                socket.local_addr().unwrap()
+            );
+        }
 
         socket
-            .set_read_timeout(Some(Duration::from_secs(5)))
-            .expect("Failed to set socket read time out");
+            .connect(addr)
+            .map_err(|err| format!("Failed to connect to DNS socket: {}", err))?;
         socket
-            .connect(server.get_ip())
-            .expect("Failed to connect to DNS socket.");
+            .send(msg_bytes)
+            .map_err(|err| format!("Failed to send message over socket: {}", err))?;
+        // The connected socket knows the address it sends from.
+        let local = socket
+            .local_addr()
+            .map_err(|err| format!("Failed to read the local address: {}", err))?;
+        record(&self.capture, local, *addr, msg_bytes);
 
+        let sent = Instant::now();
+        let deadline = sent + timeout;
         let mut buf = vec![0; 4096];
-        let _send_bytes = socket
-            .send(msg_bytes)
-            .expect("Failed to send message over socket.");
-        let length: usize = socket.recv(&mut buf).unwrap_or_default();
-
-        if length == 0 {
-            Err(String::from("Failed to read bytes from socket."))
-        } else {
-            buf = buf.get_slice(0, length as u16).unwrap();
-            Ok(buf)
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(String::from("Timed out waiting for an answer."));
+            }
+            socket
+                .set_read_timeout(Some(remaining))
+                .map_err(|err| format!("Failed to set socket read time out: {}", err))?;
+            let length = match socket.recv(&mut buf) {
+                Ok(length) => length,
+                Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    return Err(String::from("Timed out waiting for an answer."))
+                }
+                Err(err) => return Err(format!("Failed to receive an answer: {}", err)),
+            };
+            // Datagrams which are too short or belong to another query are dropped.
+            if length >= 12 && buf[0..2] == msg[0..2] {
+                record(&self.capture, *addr, local, &buf[..length]);
+                return Ok(Exchange {
+                    answer: buf.get_slice(0, length as u16)?,
+                    server: *addr,
+                    rtt: sent.elapsed(),
+                });
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{net::UdpSocket, time::Duration};
+
+    use dns::record::RecordType;
+    use parsing::Query;
+
+    use super::*;
+    use crate::test_util::{response_to, spawn_server};
+
+    fn answering(rcode: u8) -> SocketAddr {
+        spawn_server(move |query, src| {
+            let mut answer = response_to(query);
+            answer[3] = rcode;
+            vec![(answer, src)]
+        })
+    }
+
+    fn query() -> Vec<u8> {
+        Query::new("example.com".to_string(), RecordType::A, true).to_bytes()
+    }
+
+    fn client() -> UDPClient {
+        UDPClient::new(RetryPolicy::new(1, Duration::from_millis(100), 2), false)
+    }
+
+    #[test]
+    fn test_addresses_failover_order() {
+        let addrs = DNSSocket::CLOUDFLARE.get_addresses();
+        assert_eq!(addrs[0], "1.1.1.1:53".parse().unwrap());
+        assert_eq!(addrs[1], "1.0.0.1:53".parse().unwrap());
+        assert_eq!(addrs[2], "[2606:4700:4700::1111]:53".parse().unwrap());
+        assert_eq!(addrs[3], "[2606:4700:4700::1001]:53".parse().unwrap());
+    }
+
+    #[test]
+    fn test_server_from_address() {
+        assert_eq!(
+            DNSSocket::from_string("10.0.0.53").unwrap().get_addresses(),
+            vec!["10.0.0.53:53".parse().unwrap()]
+        );
+        assert_eq!(
+            DNSSocket::from_string("[::1]:5353")
+                .unwrap()
+                .get_addresses(),
+            vec!["[::1]:5353".parse().unwrap()]
+        );
+        assert!(matches!(
+            DNSSocket::from_string("quad9"),
+            Ok(DNSSocket::QUAD9)
+        ));
+        assert!(DNSSocket::from_string("corpp").is_err());
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy::new(3, Duration::from_secs(1), 2);
+        assert_eq!(policy.timeout_for(0), Duration::from_secs(1));
+        assert_eq!(policy.timeout_for(2), Duration::from_secs(4));
+    }
+
+    #[test]
+    fn test_failover_on_timeout() {
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addrs = vec![silent.local_addr().unwrap(), answering(0)];
+        let answer = client().send_to_any(query(), &addrs).unwrap();
+        assert!(matches!(Header::from_bytes(answer).rcode(), RCODE::NOERR));
+    }
+
+    #[test]
+    fn test_exchange_reports_server() {
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server = answering(0);
+        let addrs = vec![silent.local_addr().unwrap(), server];
+        let ex = client().exchange_with_any(query(), &addrs).unwrap();
+        assert_eq!(ex.server, server);
+        assert!(ex.rtt < Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_failover_on_servfail() {
+        let addrs = vec![answering(2), answering(5), answering(0)];
+        let answer = client().send_to_any(query(), &addrs).unwrap();
+        assert!(matches!(Header::from_bytes(answer).rcode(), RCODE::NOERR));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_refused_is_no_timeout() {
+        // Nothing listens on the port once the socket is closed, the ICMP
+        // port unreachable surfaces as a refused connection.
+        let closed = UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let err = client()
+            .exchange_with_any(query(), &[closed])
+            .err()
+            .unwrap();
+        assert!(err.contains("Failed to receive an answer"));
+        assert!(!err.contains("Timed out"));
+    }
+
+    #[test]
+    fn test_all_servers_fail() {
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addrs = vec![silent.local_addr().unwrap()];
+        assert!(client().send_to_any(query(), &addrs).is_err());
+
+        let answer = client().send_to_any(query(), &[answering(2)]).unwrap();
+        assert!(matches!(Header::from_bytes(answer).rcode(), RCODE::SRVFAIL));
+    }
+}
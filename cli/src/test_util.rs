@@ -0,0 +1,50 @@
+use std::{
+    net::{SocketAddr, UdpSocket},
+    thread,
+};
+
+use dns::{answer::Answer, name::Name, record::RecordType};
+
+/// `name` in wire format without compression.
+pub fn name(name: &str) -> Vec<u8> {
+    Name::from_string(name.to_string()).unwrap().get_bytes()
+}
+
+/// A record of class IN with `data` as is.
+pub fn record(owner: &str, r_type: RecordType, ttl: u32, data: Vec<u8>) -> Answer {
+    Answer::new(
+        Name::from_string(owner.to_string()).unwrap(),
+        r_type,
+        [0, 1],
+        ttl.to_be_bytes(),
+        (data.len() as u16).to_be_bytes(),
+        data,
+    )
+}
+
+/// The query echoed back with the QR bit set, an empty response.
+pub fn response_to(query: &[u8]) -> Vec<u8> {
+    let mut answer = query.to_vec();
+    answer[2] |= 0b1000_0000;
+    answer
+}
+
+/// Binds a UDP server to 127.0.0.1 which calls `reply` with every query
+/// and its source and sends the datagrams it returns, so a server can hold
+/// answers back and send them later in any order.
+pub fn spawn_server<F>(mut reply: F) -> SocketAddr
+where
+    F: FnMut(&[u8], SocketAddr) -> Vec<(Vec<u8>, SocketAddr)> + Send + 'static,
+{
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = socket.local_addr().unwrap();
+    thread::spawn(move || {
+        let mut buf = [0; 512];
+        while let Ok((len, src)) = socket.recv_from(&mut buf) {
+            for (answer, dst) in reply(&buf[..len], src) {
+                socket.send_to(&answer, dst).unwrap();
+            }
+        }
+    });
+    addr
+}
@@ -1,8 +1,17 @@
-use utility::Row;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use serde::{
+    ser::{Error, SerializeStruct},
+    Serialize, Serializer,
+};
+use utility::{from_hex, to_hex, Row};
 
 use crate::{
     name::Name,
-    record::{AAAARecord, ARecord, CNAMERecord, MXRecord, NSRecord, RecordType},
+    record::{
+        AAAARecord, ARecord, CNAMERecord, DNAMERecord, MXRecord, NSRecord, PTRRecord, RecordType,
+        SOARecord,
+    },
 };
 
 /*
@@ -71,6 +80,86 @@ impl Answer {
         };
     }
 
+    pub fn get_name(&self) -> &Name {
+        &self.name
+    }
+
+    pub fn get_type(&self) -> &RecordType {
+        &self.r_type
+    }
+
+    pub fn get_class(&self) -> u16 {
+        self.class.as_u16()
+    }
+
+    pub fn get_ttl(&self) -> u32 {
+        self.ttl_as_u32()
+    }
+
+    pub fn get_data(&self) -> &Vec<u8> {
+        &self.a_data
+    }
+
+    pub fn set_ttl(&mut self, ttl: u32) {
+        self.ttl = ttl.to_be_bytes();
+    }
+
+    /// Checks that the data can be read: addresses have their length and
+    /// the names decompress within the message `src`.
+    pub fn validate(&self, src: Vec<u8>) -> Result<(), &'static str> {
+        match (&self.r_type, self.a_data.len()) {
+            (RecordType::A, 4) | (RecordType::AAAA, 16) => Ok(()),
+            (RecordType::A, _) => Err("A record data is not 4 bytes long."),
+            (RecordType::AAAA, _) => Err("AAAA record data is not 16 bytes long."),
+            _ => self.expand(src).map(|_| ()),
+        }
+    }
+
+    /// Copy of the record with the names in its data written out in full,
+    /// so it stays valid outside of the message `src` it was read from.
+    /// Fails for names which are cut off or point outside of `src`.
+    pub fn expand(&self, src: Vec<u8>) -> Result<Answer, &'static str> {
+        let data = self.a_data.to_vec();
+        let a_data: Vec<u8> = match self.r_type {
+            RecordType::CNAME => CNAMERecord::from_bytes(data, src, 0)?
+                .get_name()
+                .get_bytes(),
+            RecordType::NS => NSRecord::from_bytes(data, src, 0)?
+                .get_nsdname()
+                .get_bytes(),
+            RecordType::PTR => PTRRecord::from_bytes(data, src, 0)?
+                .get_ptrdname()
+                .get_bytes(),
+            RecordType::DNAME => DNAMERecord::from_bytes(data, src, 0)?
+                .get_target()
+                .get_bytes(),
+            RecordType::MX => {
+                let mx = MXRecord::from_bytes(data, src, 0)?;
+                let mut res = mx.get_preference().to_be_bytes().to_vec();
+                res.extend(mx.get_exchange().get_bytes());
+                res
+            }
+            RecordType::SOA => {
+                // The five numbers follow the names unchanged.
+                let numbers = data[data.len().saturating_sub(20)..].to_vec();
+                let soa = SOARecord::from_bytes(data, src, 0)?;
+                let mut res = soa.get_mname().get_bytes();
+                res.extend(soa.get_rname().get_bytes());
+                res.extend(numbers);
+                res
+            }
+            _ => data,
+        };
+        Ok(Answer {
+            name: self.name.clone(),
+            r_type: self.r_type.clone(),
+            class: self.class,
+            ttl: self.ttl,
+            length: (a_data.len() as u16).to_be_bytes(),
+            a_data,
+        })
+    }
+
     fn ttl_as_u32(&self) -> u32 {
         ((self.ttl[0] as u32) << 24)
             + ((self.ttl[1] as u32) << 16)
@@ -78,15 +167,82 @@ impl Answer {
             + ((self.ttl[3] as u32) << 0)
     }
 
+    /// Wire format of the record. Compression pointers inside the record
+    /// data are copied as is, so they are only valid within the original message.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut res: Vec<u8> = self.name.get_bytes();
+        res.extend(self.r_type.to_bytes());
+        res.extend(self.class);
+        res.extend(self.ttl);
+        res.extend(self.length);
+        res.extend(self.a_data.to_vec());
+        res
+    }
+
     fn print_record(&self, src: Vec<u8>) {
-        match self.r_type.to_string().as_str() {
-            "A" => ARecord::from_bytes(self.a_data.to_vec(), 0).print(),
-            "AAAA" => AAAARecord::from_bytes(self.a_data.to_vec(), 0).print(),
-            "CNAME" => CNAMERecord::from_bytes(self.a_data.to_vec(), src, 0).print(),
-            "MX" => MXRecord::from_bytes(self.a_data.to_vec(), src, 0).print(),
-            "NS" => NSRecord::from_bytes(self.a_data.to_vec(), src, 0).print(),
-            _ => println!("\tunparseable answer data."),
+        let data = self.a_data.to_vec();
+        let printed = match self.r_type.to_string().as_str() {
+            "A" if data.len() == 4 => {
+                ARecord::from_bytes(data, 0).print();
+                Ok(())
+            }
+            "AAAA" if data.len() == 16 => {
+                AAAARecord::from_bytes(data, 0).print();
+                Ok(())
+            }
+            "CNAME" => CNAMERecord::from_bytes(data, src, 0).map(|r| r.print()),
+            "MX" => MXRecord::from_bytes(data, src, 0).map(|r| r.print()),
+            "NS" => NSRecord::from_bytes(data, src, 0).map(|r| r.print()),
+            "PTR" => PTRRecord::from_bytes(data, src, 0).map(|r| r.print()),
+            "SOA" => SOARecord::from_bytes(data, src, 0).map(|r| r.print()),
+            "DNAME" => DNAMERecord::from_bytes(data, src, 0).map(|r| r.print()),
+            _ => Err("no parser for the type"),
+        };
+        if printed.is_err() {
+            println!("\tunparseable answer data.");
+        }
+    }
+
+    /// The record data in zone file presentation format, with names
+    /// written fully qualified. Types without a parser and data which can
+    /// not be read use the generic `\# length hex` form of RFC 3597.
+    pub fn data_string(&self, src: Vec<u8>) -> String {
+        let generic = || {
+            format!("\\# {} {}", self.a_data.len(), to_hex(&self.a_data))
+                .trim_end()
+                .to_string()
         };
+        let data = self.a_data.to_vec();
+        let text = match self.r_type {
+            RecordType::A if data.len() == 4 => {
+                Ok(ARecord::from_bytes(data, 0).as_ipv4().to_string())
+            }
+            RecordType::AAAA if data.len() == 16 => {
+                Ok(AAAARecord::from_bytes(data, 0).as_ipv6().to_string())
+            }
+            RecordType::CNAME => CNAMERecord::from_bytes(data, src, 0).map(|r| fqdn(r.get_name())),
+            RecordType::NS => NSRecord::from_bytes(data, src, 0).map(|r| fqdn(r.get_nsdname())),
+            RecordType::PTR => PTRRecord::from_bytes(data, src, 0).map(|r| fqdn(r.get_ptrdname())),
+            RecordType::DNAME => {
+                DNAMERecord::from_bytes(data, src, 0).map(|r| fqdn(r.get_target()))
+            }
+            RecordType::MX => MXRecord::from_bytes(data, src, 0)
+                .map(|mx| format!("{} {}", mx.get_preference(), fqdn(mx.get_exchange()))),
+            RecordType::SOA => SOARecord::from_bytes(data, src, 0).map(|soa| {
+                format!(
+                    "{} {} {} {} {} {} {}",
+                    fqdn(soa.get_mname()),
+                    fqdn(soa.get_rname()),
+                    soa.get_serial(),
+                    soa.get_refresh(),
+                    soa.get_retry(),
+                    soa.get_expire(),
+                    soa.get_minimum()
+                )
+            }),
+            _ => return generic(),
+        };
+        text.unwrap_or_else(|_| generic())
     }
 
     pub fn print(&self, src: Vec<u8>) {
@@ -100,3 +256,181 @@ impl Answer {
         println!("---------------------");
     }
 }
+
+/// Record data in wire format from the presentation format written by
+/// [`Answer::data_string`]. Any type can be given in the generic
+/// `\# length hex` form of RFC 3597.
+pub fn data_from_string(r_type: &RecordType, text: &str) -> Result<Vec<u8>, String> {
+    let text = text.trim();
+    if let Some(generic) = text.strip_prefix("\\#") {
+        let mut parts = generic.split_whitespace();
+        let length: usize = parts
+            .next()
+            .and_then(|n| n.parse().ok())
+            .ok_or_else(|| format!("Missing data length in {}", text))?;
+        let data = from_hex(&parts.collect::<String>()).map_err(String::from)?;
+        if data.len() != length {
+            return Err(format!(
+                "Data of {} holds {} bytes instead of {}.",
+                text,
+                data.len(),
+                length
+            ));
+        }
+        return Ok(data);
+    }
+    let name = |name: &str| -> Result<Vec<u8>, String> {
+        Name::from_string(name.to_string())
+            .map(|n| n.get_bytes())
+            .map_err(|err| format!("Invalid name {}: {}", name, err))
+    };
+    let number = |n: &str| -> Result<u32, String> {
+        n.parse::<u32>()
+            .map_err(|_| format!("Invalid number {} in {}", n, text))
+    };
+    let fields: Vec<&str> = text.split_whitespace().collect();
+    match (r_type, fields.as_slice()) {
+        (RecordType::A, [addr]) => addr
+            .parse::<Ipv4Addr>()
+            .map(|a| a.octets().to_vec())
+            .map_err(|_| format!("Invalid IPv4 address {}", addr)),
+        (RecordType::AAAA, [addr]) => addr
+            .parse::<Ipv6Addr>()
+            .map(|a| a.octets().to_vec())
+            .map_err(|_| format!("Invalid IPv6 address {}", addr)),
+        (RecordType::CNAME | RecordType::NS | RecordType::PTR | RecordType::DNAME, [target]) => {
+            name(target)
+        }
+        (RecordType::MX, [preference, exchange]) => {
+            let preference = u16::try_from(number(preference)?)
+                .map_err(|_| format!("Invalid preference in {}", text))?;
+            let mut data = preference.to_be_bytes().to_vec();
+            data.extend(name(exchange)?);
+            Ok(data)
+        }
+        (RecordType::SOA, [mname, rname, numbers @ ..]) if numbers.len() == 5 => {
+            let mut data = name(mname)?;
+            data.extend(name(rname)?);
+            for n in numbers {
+                data.extend(number(n)?.to_be_bytes());
+            }
+            Ok(data)
+        }
+        _ => Err(format!(
+            "Can not read {} data from {}",
+            r_type.to_string(),
+            text
+        )),
+    }
+}
+
+/// Names in the record data are read without the message they came from,
+/// so compressed records have to be expanded first, see [`Answer::expand`].
+/// Data of types without a parser is written as hex.
+impl Serialize for Answer {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("Answer", 5)?;
+        s.serialize_field("name", &self.name)?;
+        s.serialize_field("type", &self.r_type)?;
+        s.serialize_field("class", &class_string(self.get_class()))?;
+        s.serialize_field("ttl", &self.ttl_as_u32())?;
+        let data = self.a_data.to_vec();
+        let invalid = |err: &str| {
+            S::Error::custom(format!(
+                "Invalid {} data of {}: {}",
+                self.r_type.to_string(),
+                self.name.get_string().unwrap_or_default(),
+                err
+            ))
+        };
+        match self.r_type {
+            RecordType::A if data.len() == 4 => {
+                s.serialize_field("rdata", &ARecord::from_bytes(data, 0))?
+            }
+            RecordType::AAAA if data.len() == 16 => {
+                s.serialize_field("rdata", &AAAARecord::from_bytes(data, 0))?
+            }
+            RecordType::A | RecordType::AAAA => return Err(invalid("wrong length")),
+            RecordType::CNAME => {
+                let rdata = CNAMERecord::from_bytes(data, vec![], 0).map_err(invalid)?;
+                s.serialize_field("rdata", &rdata)?
+            }
+            RecordType::MX => {
+                let rdata = MXRecord::from_bytes(data, vec![], 0).map_err(invalid)?;
+                s.serialize_field("rdata", &rdata)?
+            }
+            RecordType::NS => {
+                let rdata = NSRecord::from_bytes(data, vec![], 0).map_err(invalid)?;
+                s.serialize_field("rdata", &rdata)?
+            }
+            RecordType::PTR => {
+                let rdata = PTRRecord::from_bytes(data, vec![], 0).map_err(invalid)?;
+                s.serialize_field("rdata", &rdata)?
+            }
+            RecordType::SOA => {
+                let rdata = SOARecord::from_bytes(data, vec![], 0).map_err(invalid)?;
+                s.serialize_field("rdata", &rdata)?
+            }
+            RecordType::DNAME => {
+                let rdata = DNAMERecord::from_bytes(data, vec![], 0).map_err(invalid)?;
+                s.serialize_field("rdata", &rdata)?
+            }
+            RecordType::UNKNOWN(_) => {
+                let hex = to_hex(&data);
+                s.serialize_field("rdata", &UnknownData { hex: &hex })?
+            }
+        }
+        s.end()
+    }
+}
+
+#[derive(Serialize)]
+struct UnknownData<'a> {
+    hex: &'a str,
+}
+
+/// The mnemonic of IN and the RFC 3597 form for other classes.
+pub fn class_string(class: u16) -> String {
+    match class {
+        1 => "IN".to_string(),
+        3 => "CH".to_string(),
+        4 => "HS".to_string(),
+        n => format!("CLASS{}", n),
+    }
+}
+
+fn fqdn(name: &Name) -> String {
+    format!("{}.", name.get_string().unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn answer(r_type: RecordType, data: Vec<u8>) -> Answer {
+        Answer::new(
+            Name::from_string("example.com".to_string()).unwrap(),
+            r_type,
+            [0, 1],
+            [0, 0, 1, 44],
+            (data.len() as u16).to_be_bytes(),
+            data,
+        )
+    }
+
+    #[test]
+    fn test_data_string() {
+        let a = answer(RecordType::A, vec![192, 0, 2, 1]);
+        assert_eq!(a.data_string(vec![]), "192.0.2.1");
+        let mut mx = vec![0, 10];
+        mx.extend(
+            Name::from_string("mail.example.com".to_string())
+                .unwrap()
+                .get_bytes(),
+        );
+        let mx = answer(RecordType::MX, mx);
+        assert_eq!(mx.data_string(vec![]), "10 mail.example.com.");
+        let unknown = answer(RecordType::UNKNOWN(99), vec![1, 171]);
+        assert_eq!(unknown.data_string(vec![]), "\\# 2 01ab");
+    }
+}
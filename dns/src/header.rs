@@ -1,10 +1,12 @@
 use std::fmt;
 
+use serde::{ser::SerializeStruct, Serialize, Serializer};
 use utility::{Blob, Row};
 
 use crate::header_flags::Flags;
 use crate::header_flags::RCODE;
 
+#[derive(Clone)]
 pub struct Header {
     id: [u8; 2],
     flags: Flags,
@@ -46,6 +48,38 @@ impl Header {
         };
     }
 
+    pub fn id(&self) -> u16 {
+        self.id.as_u16()
+    }
+
+    pub fn flags(&self) -> &Flags {
+        &self.flags
+    }
+
+    pub fn flags_mut(&mut self) -> &mut Flags {
+        &mut self.flags
+    }
+
+    pub fn set_id(&mut self, id: u16) {
+        self.id = id.to_be_bytes();
+    }
+
+    pub fn set_q_count(&mut self, count: u16) {
+        self.q_count = count.to_be_bytes();
+    }
+
+    pub fn set_an_count(&mut self, count: u16) {
+        self.an_count = count.to_be_bytes();
+    }
+
+    pub fn set_ns_count(&mut self, count: u16) {
+        self.ns_count = count.to_be_bytes();
+    }
+
+    pub fn set_ar_count(&mut self, count: u16) {
+        self.ar_count = count.to_be_bytes();
+    }
+
     pub fn an_count(&self) -> u16 {
         return self.an_count.as_u16();
     }
@@ -89,3 +123,16 @@ impl Header {
         return res;
     }
 }
+
+impl Serialize for Header {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("Header", 6)?;
+        s.serialize_field("id", &self.id.as_u16())?;
+        s.serialize_field("flags", &self.flags)?;
+        s.serialize_field("qdcount", &self.q_count())?;
+        s.serialize_field("ancount", &self.an_count())?;
+        s.serialize_field("nscount", &self.ns_count())?;
+        s.serialize_field("arcount", &self.ar_count())?;
+        s.end()
+    }
+}
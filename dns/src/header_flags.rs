@@ -1,5 +1,6 @@
 use std::fmt;
 
+use serde::{ser::SerializeStruct, Serialize, Serializer};
 use utility::{Byte, Row};
 
 /*
@@ -88,6 +89,10 @@ pub enum OPCODE {
     QUERY,
     IQUERY,
     STATUS,
+    /// Zone change notification (RFC 1996).
+    NOTIFY,
+    /// Dynamic update (RFC 2136).
+    UPDATE,
     UNKNOWN,
 }
 
@@ -97,12 +102,25 @@ impl fmt::Display for OPCODE {
             OPCODE::QUERY => write!(f, "OPCODE: Query"),
             OPCODE::IQUERY => write!(f, "OPCODE: Inverse Query"),
             OPCODE::STATUS => write!(f, "OPCODE: Server Status"),
+            OPCODE::NOTIFY => write!(f, "OPCODE: Notify"),
+            OPCODE::UPDATE => write!(f, "OPCODE: Update"),
             OPCODE::UNKNOWN => write!(f, "OPCODE: Unknown"),
         }
     }
 }
 
 impl OPCODE {
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            OPCODE::QUERY => "QUERY",
+            OPCODE::IQUERY => "IQUERY",
+            OPCODE::STATUS => "STATUS",
+            OPCODE::NOTIFY => "NOTIFY",
+            OPCODE::UPDATE => "UPDATE",
+            OPCODE::UNKNOWN => "UNKNOWN",
+        }
+    }
+
     pub fn from_byte(byte: u8) -> Self {
         let mut code: i32 = 0;
         let mut pow: u32 = 0;
@@ -116,6 +134,8 @@ impl OPCODE {
             0 => OPCODE::QUERY,
             1 => OPCODE::IQUERY,
             2 => OPCODE::STATUS,
+            4 => OPCODE::NOTIFY,
+            5 => OPCODE::UPDATE,
             _ => OPCODE::UNKNOWN,
         };
     }
@@ -156,7 +176,7 @@ impl fmt::Display for RCODE {
             ),
             RCODE::REFUSED => write!(
                 f,
-                "The name server refuses to perform the specified operation for policy reasons.  For example, a name server may not wish to provide the information to the particular requester, or a name server may not wish to perform a particular operation (e.g., zone"
+                "The name server refuses to perform the specified operation for policy reasons.  For example, a name server may not wish to provide the information to the particular requester, or a name server may not wish to perform a particular operation (e.g., zone transfer) for particular data."
             ),
             RCODE::UNKNOWN => write!(
                 f,
@@ -192,11 +212,25 @@ impl RCODE {
         };
     }
 
+    /// The short name of the code used in zone files and by dig.
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            RCODE::NOERR => "NOERROR",
+            RCODE::FMTERR => "FORMERR",
+            RCODE::SRVFAIL => "SERVFAIL",
+            RCODE::NAMEERR => "NXDOMAIN",
+            RCODE::NOTIMPL => "NOTIMP",
+            RCODE::REFUSED => "REFUSED",
+            RCODE::UNKNOWN => "UNKNOWN",
+        }
+    }
+
     pub fn print(&self) {
         println!("{}", self)
     }
 }
 
+#[derive(Clone)]
 pub struct Flags {
     bytes: [u8; 2],
 }
@@ -251,11 +285,54 @@ impl Flags {
         self.bytes.start_set_bits(0b0000_0000);
     }
 
+    pub fn set_response(&mut self) {
+        self.bytes.start_set_bits(0b1000_0000);
+    }
+
+    pub fn set_aa(&mut self) {
+        self.bytes.start_set_bits(0b0000_0100);
+    }
+
+    pub fn set_recursion_available(&mut self) {
+        self.bytes.end_set_bits(0b1000_0000);
+    }
+
+    /// Sets the OPCODE to the low nibble of `opcode`.
+    pub fn set_opcode(&mut self, opcode: u8) {
+        self.bytes[0] = (self.bytes[0] & 0b1000_0111) | ((opcode & 0b0000_1111) << 3);
+    }
+
+    pub fn set_truncated(&mut self) {
+        self.bytes.start_set_bits(0b0000_0010);
+    }
+
+    /// Sets the reserved Z bit, which has to be zero in valid messages.
+    pub fn set_z(&mut self) {
+        self.bytes.end_set_bits(0b0100_0000);
+    }
+
+    pub fn set_authentic_data(&mut self) {
+        self.bytes.end_set_bits(0b0010_0000);
+    }
+
+    pub fn set_checking_disabled(&mut self) {
+        self.bytes.end_set_bits(0b0001_0000);
+    }
+
+    /// Sets the RCODE to the low nibble of `rcode`.
+    pub fn set_rcode(&mut self, rcode: u8) {
+        self.bytes[1] = (self.bytes[1] & 0b1111_0000) | (rcode & 0b0000_1111);
+    }
+
+    pub fn is_response(&self) -> bool {
+        self.bytes[0].bit_is_set(7)
+    }
+
     pub fn get_rcode(&self) -> RCODE {
         return RCODE::from_byte(self.bytes[1]);
     }
 
-    fn get_opcode(&self) -> OPCODE {
+    pub fn get_opcode(&self) -> OPCODE {
         return OPCODE::from_byte(self.bytes[0]);
     }
 
@@ -275,11 +352,49 @@ impl Flags {
         return self.bytes[1].bit_is_set(7);
     }
 
+    /// The AD bit of RFC 4035, set when the server validated the answer.
+    pub fn is_authentic_data(&self) -> bool {
+        self.bytes[1].bit_is_set(5)
+    }
+
+    /// The CD bit of RFC 4035, asking the server not to validate.
+    pub fn is_checking_disabled(&self) -> bool {
+        self.bytes[1].bit_is_set(4)
+    }
+
+    /// The reserved Z bit, which has to be zero in valid messages.
+    pub fn is_z(&self) -> bool {
+        self.bytes[1].bit_is_set(6)
+    }
+
     pub fn print(&self) {
         println!("{}", self)
     }
 }
 
+impl Serialize for RCODE {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.mnemonic())
+    }
+}
+
+impl Serialize for Flags {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("Flags", 10)?;
+        s.serialize_field("qr", &self.is_response())?;
+        s.serialize_field("opcode", self.get_opcode().mnemonic())?;
+        s.serialize_field("aa", &self.is_aa())?;
+        s.serialize_field("tc", &self.is_truncated())?;
+        s.serialize_field("rd", &self.is_recursion_desired())?;
+        s.serialize_field("ra", &self.is_recursion_available())?;
+        s.serialize_field("z", &self.is_z())?;
+        s.serialize_field("ad", &self.is_authentic_data())?;
+        s.serialize_field("cd", &self.is_checking_disabled())?;
+        s.serialize_field("rcode", &self.get_rcode())?;
+        s.end()
+    }
+}
+
 #[cfg(test)]
 mod test {
 
@@ -306,4 +421,42 @@ mod test {
         assert!(RCODE::REFUSED.is_err());
         assert!(RCODE::UNKNOWN.is_err());
     }
+
+    #[test]
+    fn test_set_response_flags() {
+        let mut flags = Flags::new();
+        flags.set_recursive();
+        flags.set_response();
+        flags.set_aa();
+        flags.set_recursion_available();
+        assert!(flags.is_response());
+        assert!(flags.is_aa());
+        assert!(flags.is_recursion_desired());
+        assert!(flags.is_recursion_available());
+        assert!(!flags.is_truncated());
+        assert!(matches!(flags.get_rcode(), RCODE::NOERR));
+        flags.set_rcode(3);
+        assert!(matches!(flags.get_rcode(), RCODE::NAMEERR));
+        assert!(flags.is_recursion_available());
+    }
+
+    #[test]
+    fn test_set_crafted_flags() {
+        let mut flags = Flags::new();
+        flags.set_opcode(2);
+        flags.set_truncated();
+        flags.set_z();
+        flags.set_authentic_data();
+        flags.set_checking_disabled();
+        assert_eq!(flags.data(), [0b0001_0010, 0b0111_0000]);
+        assert!(matches!(flags.get_opcode(), OPCODE::STATUS));
+        assert!(flags.is_truncated());
+        assert!(flags.is_authentic_data());
+        assert!(flags.is_checking_disabled());
+        flags.set_opcode(4);
+        assert!(matches!(flags.get_opcode(), OPCODE::NOTIFY));
+        flags.set_opcode(0);
+        assert!(matches!(flags.get_opcode(), OPCODE::QUERY));
+        assert!(flags.is_truncated());
+    }
 }
@@ -4,3 +4,4 @@ pub mod header_flags;
 pub mod name;
 pub mod question;
 pub mod record;
+pub mod rfc8427;
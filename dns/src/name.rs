@@ -1,15 +1,24 @@
+use serde::{ser::Error, Serialize, Serializer};
 use utility::Blob;
 
+/// Labels are at most 63 bytes long, the two high bits of a length byte
+/// mark a compression pointer.
+const MAX_LABEL_LENGTH: usize = 63;
+const POINTER_MASK: u8 = 0b1100_0000;
+/// Upper bound for pointers followed while decompressing a single name,
+/// protects against pointer loops in malformed messages.
+const MAX_POINTERS: usize = 127;
+
 #[derive(Clone)]
 pub struct Label {
     length: u8,
     is_compressed: bool,
-    offset: u8,
+    offset: u16,
     bytes: Vec<u8>,
 }
 
 impl Label {
-    pub fn new(length: u8, is_compressed: bool, offset: u8, bytes: Vec<u8>) -> Self {
+    pub fn new(length: u8, is_compressed: bool, offset: u16, bytes: Vec<u8>) -> Self {
         return Self {
             length,
             is_compressed,
@@ -20,7 +29,7 @@ impl Label {
 
     pub fn from_string(name: String) -> Result<Self, &'static str> {
         let bytes = name.as_bytes().to_vec();
-        if bytes.len() > 255 {
+        if bytes.len() > MAX_LABEL_LENGTH {
             return Err("Name exceeds max size for Label.");
         }
         return Ok(Label {
@@ -32,12 +41,20 @@ impl Label {
     }
 
     pub fn to_bytes(&self) -> Vec<u8> {
+        if self.is_compressed {
+            return self.bytes.to_vec();
+        }
         let mut res: Vec<u8> = vec![self.length];
         res.extend(self.bytes.to_vec());
         return res;
     }
 
+    /// Reads labels up to the terminating zero length or a compression
+    /// pointer. The root name yields no labels.
     pub fn read_labels(data: Vec<u8>) -> Result<Vec<Self>, &'static str> {
+        if data.is_empty() {
+            return Err("Something went wrong parsing the labels.");
+        }
         let mut labels: Vec<Self> = vec![];
         let mut data_vec = data.to_vec().into_iter();
         let mut complet = false;
@@ -51,13 +68,13 @@ impl Label {
             if len == 0 {
                 complet = true;
                 continue;
-            } else if len == 192 {
-                let offset = data_vec.next().unwrap();
+            } else if len & POINTER_MASK == POINTER_MASK {
+                let low = data_vec.next().ok_or("Compression pointer is cut off.")?;
                 labels.push(Label {
                     length: len,
                     is_compressed: true,
-                    offset,
-                    bytes: vec![192, offset],
+                    offset: Label::pointer_offset(len, low),
+                    bytes: vec![len, low],
                 });
                 complet = true;
                 continue;
@@ -65,7 +82,7 @@ impl Label {
                 let mut b: Vec<u8> = vec![];
                 let mut counter: u8 = 0;
                 while counter < len {
-                    b.push(data_vec.next().unwrap());
+                    b.push(data_vec.next().ok_or("Label is cut off.")?);
                     counter += 1;
                 }
                 labels.push(Label {
@@ -77,11 +94,16 @@ impl Label {
                 continue;
             }
         }
-        if labels.len() == 0 {
-            return Err("Something went wrong parsing the labels.");
-        } else {
-            return Ok(labels);
-        }
+        return Ok(labels);
+    }
+
+    /// Offset a compression pointer made of the bytes `high` and `low` points to.
+    pub fn pointer_offset(high: u8, low: u8) -> u16 {
+        (((high & !POINTER_MASK) as u16) << 8) | low as u16
+    }
+
+    pub fn is_pointer(len: u8) -> bool {
+        len & POINTER_MASK == POINTER_MASK
     }
 
     pub fn get_string(&self) -> String {
@@ -106,9 +128,8 @@ impl Label {
         if !self.is_compressed {
             return Err("Cant decompress uncompressed labels");
         } else {
-            let sliced = data.to_vec().get_from_offset(self.offset).unwrap();
-            let labels = Label::read_labels(sliced).unwrap();
-            return Ok(labels);
+            let sliced = data[self.offset as usize..].to_vec();
+            return Label::read_labels(sliced);
         }
     }
 
@@ -116,7 +137,7 @@ impl Label {
         return self.is_compressed;
     }
 
-    pub fn offset(&self) -> u8 {
+    pub fn offset(&self) -> u16 {
         self.offset
     }
 }
@@ -144,10 +165,17 @@ impl Name {
         if !name.is_ascii() {
             return Err("Names can only contain ascii symbols.");
         }
-        let parts = name.split(".");
+        // A trailing dot marks an absolute name, a single dot is the root.
+        let name = name.strip_suffix('.').unwrap_or(&name);
         let mut labels: Vec<Label> = vec![];
-        for part in parts.into_iter() {
-            labels.push(Label::from_string(part.to_string()).unwrap());
+        if name.is_empty() {
+            return Ok(Name::new(labels, false));
+        }
+        for part in name.split('.') {
+            if part.is_empty() {
+                return Err("Names can not contain empty labels.");
+            }
+            labels.push(Label::from_string(part.to_string())?);
         }
         return Ok(Name {
             labels,
@@ -171,16 +199,26 @@ impl Name {
     }
 
     pub fn from_bytes(data: Vec<u8>, offset: u8) -> Self {
-        let c = data.to_vec().get_from_offset(offset).unwrap();
-        let labels = Label::read_labels(c).unwrap();
-        let mut compressed = false;
-        for i in labels.to_vec().into_iter() {
-            if i.is_compressed {
-                compressed = true;
-                break;
-            }
+        Self::try_from_bytes(data, offset).unwrap()
+    }
+
+    /// Like [`Name::from_bytes`], but fails on names which are cut off.
+    pub fn try_from_bytes(data: Vec<u8>, offset: u8) -> Result<Self, &'static str> {
+        let c = data.to_vec().get_from_offset(offset)?;
+        let labels = Label::read_labels(c)?;
+        let compressed = labels.iter().any(|i| i.is_compressed);
+        Ok(Name { labels, compressed })
+    }
+
+    /// Reads a name from record data and follows its compression
+    /// pointers into the message `src`.
+    pub fn read_expanded(data: &[u8], src: &[u8], offset: u8) -> Result<Self, &'static str> {
+        let name = Self::try_from_bytes(data.to_vec(), offset)?;
+        if name.is_compressed() {
+            name.decompress(src.to_vec())
+        } else {
+            Ok(name)
         }
-        return Name { labels, compressed };
     }
 
     pub fn get_string(&self) -> Result<String, &'static str> {
@@ -210,15 +248,29 @@ impl Name {
             return Err("Cant decompress uncompressed Name.");
         }
 
+        // Decompressed labels can end with another pointer, which is
+        // followed until the name is complete.
         let mut labels: Vec<Label> = vec![];
-        for i in self.labels.to_vec().into_iter() {
-            if i.is_compressed {
-                let decompressed_labels = i.decompress(data.to_vec()).unwrap();
-                labels.extend(decompressed_labels);
-                continue;
-            } else {
+        let mut pending: Vec<Label> = self.labels.to_vec();
+        let mut pointers: usize = 0;
+        loop {
+            let mut pointer: Option<Label> = None;
+            for i in pending.into_iter() {
+                if i.is_compressed {
+                    pointer = Some(i);
+                    break;
+                }
                 labels.push(i);
-                continue;
+            }
+            match pointer {
+                None => break,
+                Some(p) => {
+                    pointers += 1;
+                    if pointers > MAX_POINTERS {
+                        return Err("Too many compression pointers in Name.");
+                    }
+                    pending = p.decompress(data.to_vec())?;
+                }
             }
         }
         return Ok(Name {
@@ -230,6 +282,18 @@ impl Name {
     pub fn is_compressed(&self) -> bool {
         return self.compressed;
     }
+
+    pub fn is_root(&self) -> bool {
+        self.labels.is_empty()
+    }
+}
+
+/// Serialized fully qualified, with the trailing dot.
+impl Serialize for Name {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let name = self.get_string().map_err(S::Error::custom)?;
+        serializer.serialize_str(&format!("{}.", name))
+    }
 }
 
 #[cfg(test)]
@@ -279,4 +343,35 @@ mod tests {
             assert_eq!(&data[idx], v);
         }
     }
+
+    #[test]
+    fn test_nested_decompression() {
+        // www -> pointer to offset 300 "example" -> pointer to offset 256 "com"
+        let mut data: Vec<u8> = vec![0; 256];
+        data.extend(vec![3, 99, 111, 109, 0]);
+        data.extend(vec![0; 39]);
+        data.extend(vec![7, 101, 120, 97, 109, 112, 108, 101, 0xC1, 0x00]);
+        let compressed = Name::from_bytes(vec![3, 119, 119, 119, 0xC1, 0x2C], 0);
+        let decompressed = compressed.decompress(data).unwrap();
+        assert_eq!("www.example.com", decompressed.get_string().unwrap());
+    }
+
+    #[test]
+    fn test_decompression_loop() {
+        let data: Vec<u8> = vec![3, 119, 119, 119, 0xC0, 0x00];
+        let compressed = Name::from_bytes(vec![0xC0, 0x00], 0);
+        assert!(compressed.decompress(data).is_err());
+    }
+
+    #[test]
+    fn test_root_name() {
+        let root = Name::from_bytes(vec![0], 0);
+        assert!(root.is_root());
+        assert_eq!(root.get_bytes(), vec![0]);
+        assert_eq!(root.get_string().unwrap(), "");
+        assert!(Name::from_string(".".to_string()).unwrap().is_root());
+        let absolute = Name::from_string("google.com.".to_string()).unwrap();
+        assert_eq!(absolute.get_string().unwrap(), "google.com");
+        assert!(Name::from_string("google..com".to_string()).is_err());
+    }
 }
@@ -1,44 +1,93 @@
 use std::fmt;
 
+use serde::{ser::SerializeStruct, Serialize, Serializer};
 use utility::Row;
 
-use crate::{name, record::RecordType};
+use crate::{answer::class_string, name, record::RecordType};
 
 pub enum QClass {
     INET,
+    CHAOS,
+    HESIOD,
+    /// QCLASS * of RFC 1035, any class.
+    ANY,
+    /// Any other class, kept by its numeric value.
+    OTHER(u16),
 }
 
 impl QClass {
     pub fn get_bytes(class: QClass) -> [u8; 2] {
-        return match class {
-            QClass::INET => [0b0000_0000, 0b0000_0001],
-        };
+        class.code().to_be_bytes()
     }
 
     pub fn from_bytes(data: Vec<u8>) -> Self {
         let b: [u8; 2] = data.to_vec().try_into().unwrap();
-        return match b.as_u16() {
-            1 => QClass::INET,
-            _ => QClass::INET,
-        };
+        QClass::from_code(b.as_u16())
     }
 
     pub fn from_row(data: [u8; 2]) -> Self {
-        match data.as_u16() {
+        QClass::from_code(data.as_u16())
+    }
+
+    pub fn from_code(code: u16) -> Self {
+        match code {
             1 => QClass::INET,
-            _ => QClass::INET,
+            3 => QClass::CHAOS,
+            4 => QClass::HESIOD,
+            255 => QClass::ANY,
+            other => QClass::OTHER(other),
+        }
+    }
+
+    pub fn code(&self) -> u16 {
+        match self {
+            QClass::INET => 1,
+            QClass::CHAOS => 3,
+            QClass::HESIOD => 4,
+            QClass::ANY => 255,
+            QClass::OTHER(code) => *code,
         }
     }
 
+    /// The class from its mnemonic (IN, CH, HS, ANY or `*`), the RFC 3597
+    /// form CLASSn or its number.
+    pub fn try_from_string(class: &str) -> Result<Self, String> {
+        let upper = class.to_ascii_uppercase();
+        let code = match upper.as_str() {
+            "IN" => Some(1),
+            "CH" => Some(3),
+            "HS" => Some(4),
+            "ANY" | "*" => Some(255),
+            other => other
+                .strip_prefix("CLASS")
+                .unwrap_or(other)
+                .parse::<u16>()
+                .ok(),
+        };
+        code.map(QClass::from_code)
+            .ok_or_else(|| format!("Unknown class {}", class))
+    }
+
     pub fn to_string(&self) -> String {
         match self {
             QClass::INET => "INET".to_string(),
+            QClass::CHAOS => "CHAOS".to_string(),
+            QClass::HESIOD => "HESIOD".to_string(),
+            QClass::ANY => "ANY".to_string(),
+            QClass::OTHER(code) => format!("CLASS{}", code),
         }
     }
 
     pub fn to_bytes(&self) -> Vec<u8> {
+        self.code().to_be_bytes().to_vec()
+    }
+}
+
+impl Serialize for QClass {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
         match self {
-            QClass::INET => vec![0b0000_0000, 0b0000_0001],
+            QClass::ANY => serializer.serialize_str("ANY"),
+            other => serializer.serialize_str(&class_string(other.code())),
         }
     }
 }
@@ -78,6 +127,18 @@ impl Question {
         };
     }
 
+    pub fn get_name(&self) -> &name::Name {
+        &self.q_name
+    }
+
+    pub fn get_type(&self) -> &RecordType {
+        &self.q_type
+    }
+
+    pub fn get_class(&self) -> &QClass {
+        &self.q_class
+    }
+
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut res: Vec<u8> = vec![];
         res.extend(self.q_name.get_bytes());
@@ -94,3 +155,13 @@ impl Question {
         return self.q_name.get_bytes_length() + 4;
     }
 }
+
+impl Serialize for Question {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("Question", 3)?;
+        s.serialize_field("name", &self.q_name)?;
+        s.serialize_field("type", &self.q_type)?;
+        s.serialize_field("class", &self.q_class)?;
+        s.end()
+    }
+}
@@ -1,4 +1,5 @@
 use crate::name::Name;
+use serde::{ser::SerializeStruct, Serialize, Serializer};
 use std::net::{Ipv4Addr, Ipv6Addr};
 use utility::{Blob, Row};
 
@@ -35,6 +36,11 @@ pub enum RecordType {
     CNAME,
     MX,
     NS,
+    PTR,
+    SOA,
+    DNAME,
+    /// Any type without a decoder, kept by its numeric value.
+    UNKNOWN(u16),
 }
 
 impl Clone for RecordType {
@@ -45,6 +51,10 @@ impl Clone for RecordType {
             RecordType::CNAME => RecordType::CNAME,
             RecordType::NS => RecordType::NS,
             RecordType::MX => RecordType::MX,
+            RecordType::PTR => RecordType::PTR,
+            RecordType::SOA => RecordType::SOA,
+            RecordType::DNAME => RecordType::DNAME,
+            RecordType::UNKNOWN(code) => RecordType::UNKNOWN(*code),
         }
     }
 }
@@ -57,7 +67,26 @@ impl RecordType {
             "CNAME" => RecordType::CNAME,
             "MX" => RecordType::MX,
             "NS" => RecordType::NS,
-            _ => RecordType::A,
+            "PTR" => RecordType::PTR,
+            "SOA" => RecordType::SOA,
+            "DNAME" => RecordType::DNAME,
+            // RFC 3597 notation for types without a mnemonic, e.g. TYPE65
+            other => match other.strip_prefix("TYPE").map(|n| n.parse::<u16>()) {
+                Some(Ok(code)) => RecordType::from_code(code),
+                _ => RecordType::A,
+            },
+        }
+    }
+
+    /// Like [`RecordType::from_string`], but case insensitive and without
+    /// falling back to A for unknown types.
+    pub fn try_from_string(r_type: &str) -> Result<Self, String> {
+        let upper = r_type.to_ascii_uppercase();
+        let parsed = RecordType::from_string(upper.clone());
+        if parsed.to_string() == upper {
+            Ok(parsed)
+        } else {
+            Err(format!("Unknown record type {}", r_type))
         }
     }
 
@@ -68,6 +97,10 @@ impl RecordType {
             RecordType::CNAME => "CNAME".to_string(),
             RecordType::MX => "MX".to_string(),
             RecordType::NS => "NS".to_string(),
+            RecordType::PTR => "PTR".to_string(),
+            RecordType::SOA => "SOA".to_string(),
+            RecordType::DNAME => "DNAME".to_string(),
+            RecordType::UNKNOWN(code) => format!("TYPE{}", code),
         }
     }
 
@@ -78,17 +111,37 @@ impl RecordType {
             RecordType::CNAME => vec![0b0000_0000, 0b0000_0101],
             RecordType::AAAA => vec![0b0000_0000, 0b0001_1100],
             RecordType::MX => vec![0b0000_0000, 0b0000_1111],
+            RecordType::PTR => vec![0b0000_0000, 0b0000_1100],
+            RecordType::SOA => vec![0b0000_0000, 0b0000_0110],
+            RecordType::DNAME => vec![0b0000_0000, 0b0010_0111],
+            RecordType::UNKNOWN(code) => code.to_be_bytes().to_vec(),
         }
     }
 
+    pub fn code(&self) -> u16 {
+        let bytes = self.to_bytes();
+        [bytes[0], bytes[1]].as_u16()
+    }
+
+    /// Falls back to A for unknown types, use [`RecordType::from_code`] to keep them.
     pub fn from_bytes(data: [u8; 2]) -> Self {
-        match data.as_u16() {
+        match RecordType::from_code(data.as_u16()) {
+            RecordType::UNKNOWN(_) => RecordType::A,
+            known => known,
+        }
+    }
+
+    pub fn from_code(code: u16) -> Self {
+        match code {
             1 => RecordType::A,
             2 => RecordType::NS,
             28 => RecordType::AAAA,
             5 => RecordType::CNAME,
             15 => RecordType::MX,
-            _ => RecordType::A,
+            12 => RecordType::PTR,
+            6 => RecordType::SOA,
+            39 => RecordType::DNAME,
+            _ => RecordType::UNKNOWN(code),
         }
     }
 }
@@ -174,12 +227,13 @@ pub struct CNAMERecord {
 }
 
 impl CNAMERecord {
-    pub fn from_bytes(data: Vec<u8>, src: Vec<u8>, offset: u8) -> Self {
-        let mut name = Name::from_bytes(data.to_vec(), offset);
-        if name.is_compressed() {
-            name = name.decompress(src.to_vec()).unwrap();
-        }
-        return Self { name };
+    pub fn from_bytes(data: Vec<u8>, src: Vec<u8>, offset: u8) -> Result<Self, &'static str> {
+        let name = Name::read_expanded(&data, &src, offset)?;
+        Ok(Self { name })
+    }
+
+    pub fn get_name(&self) -> &Name {
+        &self.name
     }
 
     pub fn print(&self) {
@@ -193,22 +247,25 @@ pub struct MXRecord {
 }
 
 impl MXRecord {
-    pub fn from_bytes(data: Vec<u8>, src: Vec<u8>, offset: u8) -> Self {
-        let pref_bytes: Vec<u8> = data
-            .to_vec()
-            .get_from_offset(offset)
-            .unwrap()
-            .get_slice(0, 2)
-            .unwrap();
-        let mut name = Name::from_bytes(data.to_vec(), offset + 2);
-        if name.is_compressed() {
-            name = name.decompress(src).unwrap();
+    pub fn from_bytes(data: Vec<u8>, src: Vec<u8>, offset: u8) -> Result<Self, &'static str> {
+        let pref_bytes: Vec<u8> = data.to_vec().get_from_offset(offset)?.get_slice(0, 2)?;
+        if pref_bytes.len() < 2 {
+            return Err("MX record is too short.");
         }
+        let name = Name::read_expanded(&data, &src, offset.saturating_add(2))?;
         let pref: u16 = [pref_bytes[0], pref_bytes[1]].as_u16();
-        return Self {
+        Ok(Self {
             preference: pref,
             exchange: name,
-        };
+        })
+    }
+
+    pub fn get_preference(&self) -> u16 {
+        self.preference
+    }
+
+    pub fn get_exchange(&self) -> &Name {
+        &self.exchange
     }
 
     pub fn print(&self) {
@@ -222,12 +279,13 @@ pub struct NSRecord {
 }
 
 impl NSRecord {
-    pub fn from_bytes(data: Vec<u8>, src: Vec<u8>, offset: u8) -> Self {
-        let mut nsdname = Name::from_bytes(data.to_vec(), offset);
-        if nsdname.is_compressed() {
-            nsdname = nsdname.decompress(src.to_vec()).unwrap();
-        }
-        return Self { nsdname };
+    pub fn from_bytes(data: Vec<u8>, src: Vec<u8>, offset: u8) -> Result<Self, &'static str> {
+        let nsdname = Name::read_expanded(&data, &src, offset)?;
+        Ok(Self { nsdname })
+    }
+
+    pub fn get_nsdname(&self) -> &Name {
+        &self.nsdname
     }
 
     pub fn print(&self) {
@@ -235,6 +293,200 @@ impl NSRecord {
     }
 }
 
+pub struct PTRRecord {
+    ptrdname: Name,
+}
+
+impl PTRRecord {
+    pub fn from_bytes(data: Vec<u8>, src: Vec<u8>, offset: u8) -> Result<Self, &'static str> {
+        let ptrdname = Name::read_expanded(&data, &src, offset)?;
+        Ok(Self { ptrdname })
+    }
+
+    pub fn get_ptrdname(&self) -> &Name {
+        &self.ptrdname
+    }
+
+    pub fn print(&self) {
+        println!("\tPointer: {}", self.ptrdname.get_string().unwrap());
+    }
+}
+
+/// Redirects a whole subtree to another name (RFC 6672).
+pub struct DNAMERecord {
+    target: Name,
+}
+
+impl DNAMERecord {
+    pub fn from_bytes(data: Vec<u8>, src: Vec<u8>, offset: u8) -> Result<Self, &'static str> {
+        let target = Name::read_expanded(&data, &src, offset)?;
+        Ok(Self { target })
+    }
+
+    pub fn get_target(&self) -> &Name {
+        &self.target
+    }
+
+    pub fn print(&self) {
+        println!("\tTarget: {}", self.target.get_string().unwrap());
+    }
+}
+
+pub struct SOARecord {
+    mname: Name,
+    rname: Name,
+    serial: u32,
+    refresh: u32,
+    retry: u32,
+    expire: u32,
+    minimum: u32,
+}
+
+impl SOARecord {
+    pub fn from_bytes(data: Vec<u8>, src: Vec<u8>, offset: u8) -> Result<Self, &'static str> {
+        const TOO_SHORT: &str = "SOA record is too short.";
+        let mname = Name::try_from_bytes(data.to_vec(), offset)?;
+        let rname_offset = offset
+            .checked_add(mname.get_bytes_length())
+            .ok_or(TOO_SHORT)?;
+        let rname = Name::try_from_bytes(data.to_vec(), rname_offset)?;
+        let numbers_offset = rname_offset as usize + rname.get_bytes_length() as usize;
+        if data.len() < numbers_offset + 20 {
+            return Err(TOO_SHORT);
+        }
+        let mname = Name::read_expanded(&data, &src, offset)?;
+        let rname = Name::read_expanded(&data, &src, rname_offset)?;
+        let number = |n: usize| -> u32 {
+            let start = numbers_offset + n * 4;
+            u32::from_be_bytes(data[start..start + 4].try_into().unwrap())
+        };
+        Ok(Self {
+            mname,
+            rname,
+            serial: number(0),
+            refresh: number(1),
+            retry: number(2),
+            expire: number(3),
+            minimum: number(4),
+        })
+    }
+
+    pub fn get_mname(&self) -> &Name {
+        &self.mname
+    }
+
+    pub fn get_rname(&self) -> &Name {
+        &self.rname
+    }
+
+    pub fn get_serial(&self) -> u32 {
+        self.serial
+    }
+
+    pub fn get_refresh(&self) -> u32 {
+        self.refresh
+    }
+
+    pub fn get_retry(&self) -> u32 {
+        self.retry
+    }
+
+    pub fn get_expire(&self) -> u32 {
+        self.expire
+    }
+
+    pub fn get_minimum(&self) -> u32 {
+        self.minimum
+    }
+
+    pub fn print(&self) {
+        println!("\tPrimary Nameserver: {}", self.mname.get_string().unwrap());
+        println!("\tMailbox: {}", self.rname.get_string().unwrap());
+        println!("\tSerial: {}", self.serial);
+        println!("\tRefresh: {}", self.refresh);
+        println!("\tRetry: {}", self.retry);
+        println!("\tExpire: {}", self.expire);
+        println!("\tMinimum TTL: {}", self.minimum);
+    }
+}
+
+impl Serialize for RecordType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl Serialize for ARecord {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("ARecord", 1)?;
+        s.serialize_field("address", &self.as_ipv4())?;
+        s.end()
+    }
+}
+
+impl Serialize for AAAARecord {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("AAAARecord", 1)?;
+        s.serialize_field("address", &self.as_ipv6())?;
+        s.end()
+    }
+}
+
+impl Serialize for CNAMERecord {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("CNAMERecord", 1)?;
+        s.serialize_field("target", &self.name)?;
+        s.end()
+    }
+}
+
+impl Serialize for MXRecord {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("MXRecord", 2)?;
+        s.serialize_field("preference", &self.preference)?;
+        s.serialize_field("exchange", &self.exchange)?;
+        s.end()
+    }
+}
+
+impl Serialize for NSRecord {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("NSRecord", 1)?;
+        s.serialize_field("nsdname", &self.nsdname)?;
+        s.end()
+    }
+}
+
+impl Serialize for PTRRecord {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("PTRRecord", 1)?;
+        s.serialize_field("ptrdname", &self.ptrdname)?;
+        s.end()
+    }
+}
+
+impl Serialize for DNAMERecord {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("DNAMERecord", 1)?;
+        s.serialize_field("target", &self.target)?;
+        s.end()
+    }
+}
+
+impl Serialize for SOARecord {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("SOARecord", 7)?;
+        s.serialize_field("mname", &self.mname)?;
+        s.serialize_field("rname", &self.rname)?;
+        s.serialize_field("serial", &self.serial)?;
+        s.serialize_field("refresh", &self.refresh)?;
+        s.serialize_field("retry", &self.retry)?;
+        s.serialize_field("expire", &self.expire)?;
+        s.serialize_field("minimum", &self.minimum)?;
+        s.end()
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -263,6 +515,7 @@ mod tests {
         assert!(matches!(RecordType::from_bytes([0, 5]), RecordType::CNAME));
         assert!(matches!(RecordType::from_bytes([0, 28]), RecordType::AAAA));
         assert!(matches!(RecordType::from_bytes([0, 15]), RecordType::MX));
+        assert!(matches!(RecordType::from_bytes([0, 12]), RecordType::PTR));
         assert!(matches!(RecordType::from_bytes([0, 245]), RecordType::A));
     }
 
@@ -273,6 +526,56 @@ mod tests {
         assert_eq!(RecordType::CNAME.to_string(), "CNAME".to_string());
         assert_eq!(RecordType::MX.to_string(), "MX".to_string());
         assert_eq!(RecordType::NS.to_string(), "NS".to_string());
+        assert_eq!(RecordType::PTR.to_string(), "PTR".to_string());
+    }
+
+    #[test]
+    fn test_record_type_from_code() {
+        assert!(matches!(RecordType::from_code(6), RecordType::SOA));
+        assert!(matches!(RecordType::from_code(39), RecordType::DNAME));
+        assert_eq!(RecordType::DNAME.to_bytes(), vec![0, 39]);
+        assert!(matches!(
+            RecordType::from_code(245),
+            RecordType::UNKNOWN(245)
+        ));
+        assert_eq!(RecordType::UNKNOWN(245).to_string(), "TYPE245".to_string());
+        assert_eq!(RecordType::UNKNOWN(245).to_bytes(), vec![0, 245]);
+        assert!(matches!(
+            RecordType::from_string("TYPE65".to_string()),
+            RecordType::UNKNOWN(65)
+        ));
+    }
+
+    #[test]
+    fn test_soa_record() {
+        let mut src: Vec<u8> = vec![0; 12];
+        src.extend(vec![
+            7, 101, 120, 97, 109, 112, 108, 101, 3, 99, 111, 109, 0,
+        ]);
+        // ns.<pointer example.com> admin.<pointer example.com> then the numbers
+        let mut data: Vec<u8> = vec![2, 110, 115, 0xC0, 12, 5, 97, 100, 109, 105, 110, 0xC0, 12];
+        for n in [2023, 7200, 3600, 1209600, 300u32] {
+            data.extend(n.to_be_bytes());
+        }
+        let soa = SOARecord::from_bytes(data.to_vec(), src.to_vec(), 0).unwrap();
+        assert_eq!(soa.get_mname().get_string().unwrap(), "ns.example.com");
+        assert_eq!(soa.get_rname().get_string().unwrap(), "admin.example.com");
+        assert_eq!(soa.get_serial(), 2023);
+        assert_eq!(soa.get_minimum(), 300);
+
+        assert!(SOARecord::from_bytes(data[..20].to_vec(), src.to_vec(), 0).is_err());
+        assert!(SOARecord::from_bytes(data, src[..12].to_vec(), 0).is_err());
+        assert!(NSRecord::from_bytes(vec![0xC0, 99], vec![0; 12], 0).is_err());
+        assert!(MXRecord::from_bytes(vec![0], vec![], 0).is_err());
+    }
+
+    #[test]
+    fn test_record_type_try_from_string() {
+        assert!(matches!(
+            RecordType::try_from_string("aaaa"),
+            Ok(RecordType::AAAA)
+        ));
+        assert!(RecordType::try_from_string("SRV").is_err());
     }
 
     #[test]
@@ -297,5 +600,9 @@ mod tests {
             RecordType::from_string("NS".to_string()),
             RecordType::NS
         ));
+        assert!(matches!(
+            RecordType::from_string("PTR".to_string()),
+            RecordType::PTR
+        ));
     }
 }
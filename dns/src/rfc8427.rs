@@ -0,0 +1,327 @@
+use serde_json::{Map, Value};
+use utility::{from_hex, to_hex};
+
+use crate::{
+    answer::{class_string, data_from_string, Answer},
+    name::Name,
+    question::Question,
+    record::RecordType,
+};
+
+/*
+    RFC 8427 represents a message as one JSON object. The header fields
+    are members of their own (ID, QR, Opcode, AA, TC, RD, RA, AD, CD,
+    RCODE and the four counts), followed by the question (QNAME, QTYPE,
+    QCLASS), or questionRRs holding NAME, TYPE and CLASS of every question
+    when there is not exactly one, and the sections as arrays of RR
+    objects (answerRRs, authorityRRs, additionalRRs). An RR object has
+    NAME, TYPE, CLASS, TTL, RDLENGTH and RDATAHEX, known types also the
+    data in presentation format as rdata<TYPE>, e.g. rdataA.
+    messageOctetsHEX holds the whole message as it was sent.
+*/
+
+/// The message `bytes` with its parsed questions and sections as an
+/// RFC 8427 object, including `messageOctetsHEX`.
+///
+/// Compressed names in the record data are written out in full, so
+/// RDATAHEX can be read without the rest of the message.
+pub fn message_to_json(
+    bytes: &[u8],
+    questions: &[Question],
+    sections: [&[Answer]; 3],
+) -> Result<Value, String> {
+    if bytes.len() < 12 {
+        return Err(String::from("Message is too short for a DNS header."));
+    }
+    let mut msg = Map::new();
+    let bit = |byte: u8, pos: u8| Value::from((byte >> pos) & 1);
+    msg.insert("ID".into(), u16::from_be_bytes([bytes[0], bytes[1]]).into());
+    msg.insert("QR".into(), bit(bytes[2], 7));
+    msg.insert("Opcode".into(), ((bytes[2] >> 3) & 0b1111).into());
+    msg.insert("AA".into(), bit(bytes[2], 2));
+    msg.insert("TC".into(), bit(bytes[2], 1));
+    msg.insert("RD".into(), bit(bytes[2], 0));
+    msg.insert("RA".into(), bit(bytes[3], 7));
+    msg.insert("AD".into(), bit(bytes[3], 5));
+    msg.insert("CD".into(), bit(bytes[3], 4));
+    msg.insert("RCODE".into(), (bytes[3] & 0b1111).into());
+    for (idx, count) in ["QDCOUNT", "ANCOUNT", "NSCOUNT", "ARCOUNT"]
+        .iter()
+        .enumerate()
+    {
+        let start = 4 + idx * 2;
+        msg.insert(
+            count.to_string(),
+            u16::from_be_bytes([bytes[start], bytes[start + 1]]).into(),
+        );
+    }
+    match questions {
+        [question] => {
+            for (key, value) in question_to_json(question)? {
+                msg.insert(format!("Q{}", key), value);
+            }
+        }
+        _ => {
+            let questions: Result<Vec<Value>, String> = questions
+                .iter()
+                .map(|q| question_to_json(q).map(Value::Object))
+                .collect();
+            msg.insert("questionRRs".into(), Value::Array(questions?));
+        }
+    }
+    let names = ["answerRRs", "authorityRRs", "additionalRRs"];
+    for (name, records) in names.iter().zip(sections.iter()) {
+        let rrs: Result<Vec<Value>, String> = records
+            .iter()
+            .map(|rr| rr_to_json(&rr.expand(bytes.to_vec())?))
+            .collect();
+        msg.insert(name.to_string(), Value::Array(rrs?));
+    }
+    msg.insert(
+        "messageOctetsHEX".into(),
+        to_hex(bytes).to_uppercase().into(),
+    );
+    Ok(Value::Object(msg))
+}
+
+fn question_to_json(question: &Question) -> Result<Map<String, Value>, String> {
+    let mut obj = Map::new();
+    obj.insert("NAME".into(), fqdn(question.get_name())?.into());
+    obj.insert("TYPE".into(), question.get_type().code().into());
+    obj.insert("TYPEname".into(), question.get_type().to_string().into());
+    let class = question.get_class().code();
+    obj.insert("CLASS".into(), class.into());
+    obj.insert("CLASSname".into(), class_string(class).into());
+    Ok(obj)
+}
+
+fn rr_to_json(rr: &Answer) -> Result<Value, String> {
+    let mut obj = Map::new();
+    obj.insert("NAME".into(), fqdn(rr.get_name())?.into());
+    obj.insert("TYPE".into(), rr.get_type().code().into());
+    obj.insert("TYPEname".into(), rr.get_type().to_string().into());
+    obj.insert("CLASS".into(), rr.get_class().into());
+    obj.insert("CLASSname".into(), class_string(rr.get_class()).into());
+    obj.insert("TTL".into(), rr.get_ttl().into());
+    obj.insert("RDLENGTH".into(), rr.get_data().len().into());
+    obj.insert(
+        "RDATAHEX".into(),
+        to_hex(rr.get_data()).to_uppercase().into(),
+    );
+    if !matches!(rr.get_type(), RecordType::UNKNOWN(_)) {
+        obj.insert(
+            format!("rdata{}", rr.get_type().to_string()),
+            rr.data_string(vec![]).into(),
+        );
+    }
+    Ok(Value::Object(obj))
+}
+
+/// The wire format of an RFC 8427 object. `messageOctetsHEX` is used as
+/// is when present, otherwise the message is built from the header
+/// members, the question and the RR arrays. The counts follow the arrays.
+/// Record data is read from RDATAHEX, or from rdata<TYPE> without it.
+pub fn message_from_json(json: &Value) -> Result<Vec<u8>, String> {
+    let msg = json
+        .as_object()
+        .ok_or_else(|| String::from("An RFC 8427 message has to be a JSON object."))?;
+    if let Some(hex) = msg.get("messageOctetsHEX") {
+        let hex = hex
+            .as_str()
+            .ok_or_else(|| String::from("messageOctetsHEX has to be a string."))?;
+        return from_hex(hex).map_err(String::from);
+    }
+    let flag = |key: &str| -> Result<u8, String> { Ok((number(msg, key, 0)? & 1) as u8) };
+    let mut bytes = (number(msg, "ID", 0)? as u16).to_be_bytes().to_vec();
+    bytes.push(
+        flag("QR")? << 7
+            | ((number(msg, "Opcode", 0)? & 0b1111) as u8) << 3
+            | flag("AA")? << 2
+            | flag("TC")? << 1
+            | flag("RD")?,
+    );
+    bytes.push(
+        flag("RA")? << 7
+            | flag("AD")? << 5
+            | flag("CD")? << 4
+            | (number(msg, "RCODE", 0)? & 0b1111) as u8,
+    );
+
+    let mut body: Vec<u8> = vec![];
+    let mut qd_count: u16 = 0;
+    if let Some(qname) = msg.get("QNAME") {
+        let qname = qname
+            .as_str()
+            .ok_or_else(|| String::from("QNAME has to be a string."))?;
+        body.extend(name_bytes(qname)?);
+        body.extend(r_type(msg, "QTYPE")?.to_bytes());
+        body.extend((number(msg, "QCLASS", 1)? as u16).to_be_bytes());
+        qd_count = 1;
+    } else if let Some(questions) = msg.get("questionRRs") {
+        let questions = questions
+            .as_array()
+            .ok_or_else(|| String::from("questionRRs has to be an array."))?;
+        for q in questions.iter() {
+            let q = q
+                .as_object()
+                .ok_or_else(|| String::from("A question has to be a JSON object."))?;
+            let name = q
+                .get("NAME")
+                .and_then(Value::as_str)
+                .ok_or_else(|| String::from("A question needs a NAME string."))?;
+            body.extend(name_bytes(name)?);
+            body.extend(r_type(q, "TYPE")?.to_bytes());
+            body.extend((number(q, "CLASS", 1)? as u16).to_be_bytes());
+        }
+        qd_count = u16::try_from(questions.len())
+            .map_err(|_| String::from("Too many entries in questionRRs."))?;
+    }
+    let mut counts = vec![qd_count];
+    for section in ["answerRRs", "authorityRRs", "additionalRRs"] {
+        let rrs = match msg.get(section) {
+            Some(Value::Array(rrs)) => rrs.to_vec(),
+            Some(_) => return Err(format!("{} has to be an array.", section)),
+            None => vec![],
+        };
+        for rr in rrs.iter() {
+            body.extend(rr_from_json(rr)?);
+        }
+        counts.push(
+            u16::try_from(rrs.len()).map_err(|_| format!("Too many entries in {}.", section))?,
+        );
+    }
+    for count in counts {
+        bytes.extend(count.to_be_bytes());
+    }
+    bytes.extend(body);
+    Ok(bytes)
+}
+
+fn rr_from_json(rr: &Value) -> Result<Vec<u8>, String> {
+    let rr = rr
+        .as_object()
+        .ok_or_else(|| String::from("An RR has to be a JSON object."))?;
+    let name = rr
+        .get("NAME")
+        .and_then(Value::as_str)
+        .ok_or_else(|| String::from("An RR needs a NAME string."))?;
+    let r_type = r_type(rr, "TYPE")?;
+    let data = match rr.get("RDATAHEX") {
+        Some(hex) => from_hex(
+            hex.as_str()
+                .ok_or_else(|| String::from("RDATAHEX has to be a string."))?,
+        )?,
+        None => {
+            let key = format!("rdata{}", r_type.to_string());
+            let text = rr.get(&key).and_then(Value::as_str).ok_or_else(|| {
+                format!(
+                    "The {} RR of {} has no RDATAHEX or {}.",
+                    r_type.to_string(),
+                    name,
+                    key
+                )
+            })?;
+            data_from_string(&r_type, text)?
+        }
+    };
+    let mut bytes = name_bytes(name)?;
+    bytes.extend(r_type.to_bytes());
+    bytes.extend((number(rr, "CLASS", 1)? as u16).to_be_bytes());
+    bytes.extend((number(rr, "TTL", 0)? as u32).to_be_bytes());
+    let rd_length = u16::try_from(data.len())
+        .map_err(|_| format!("The RDATA of the RR of {} is too long.", name))?;
+    bytes.extend(rd_length.to_be_bytes());
+    bytes.extend(data);
+    Ok(bytes)
+}
+
+/// The type of `key`, or of its `name` member like TYPEname when missing.
+/// Questions without a type ask for A records.
+fn r_type(obj: &Map<String, Value>, key: &str) -> Result<RecordType, String> {
+    if let Some(code) = obj.get(key) {
+        return code
+            .as_u64()
+            .and_then(|code| u16::try_from(code).ok())
+            .map(RecordType::from_code)
+            .ok_or_else(|| format!("{} has to be a number.", key));
+    }
+    match obj.get(&format!("{}name", key)).and_then(Value::as_str) {
+        Some(name) => RecordType::try_from_string(name),
+        None if key == "QTYPE" => Ok(RecordType::A),
+        None => Err(format!("An RR needs a {} or {}name.", key, key)),
+    }
+}
+
+/// Numbers and booleans are accepted for the flags.
+fn number(obj: &Map<String, Value>, key: &str, default: u64) -> Result<u64, String> {
+    match obj.get(key) {
+        None => Ok(default),
+        Some(Value::Bool(set)) => Ok(*set as u64),
+        Some(value) => value
+            .as_u64()
+            .ok_or_else(|| format!("{} has to be a number.", key)),
+    }
+}
+
+fn name_bytes(name: &str) -> Result<Vec<u8>, String> {
+    Name::from_string(name.to_string())
+        .map(|n| n.get_bytes())
+        .map_err(|err| format!("Invalid name {}: {}", name, err))
+}
+
+fn fqdn(name: &Name) -> Result<String, String> {
+    name.get_string()
+        .map(|n| format!("{}.", n))
+        .map_err(String::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_message_from_fields() {
+        let msg = json!({
+            "ID": 32784, "QR": 1, "AA": true, "RD": 1, "RCODE": 0,
+            "QNAME": "example.com.", "QTYPEname": "MX",
+            "answerRRs": [
+                { "NAME": "example.com.", "TYPE": 15, "TTL": 3600,
+                  "rdataMX": "10 mail.example.com." },
+                { "NAME": "example.com.", "TYPE": 99, "CLASS": 1, "TTL": 60,
+                  "RDATAHEX": "01AB" }
+            ]
+        });
+        let bytes = message_from_json(&msg).unwrap();
+        assert_eq!(&bytes[..12], &[128, 16, 133, 0, 0, 1, 0, 2, 0, 0, 0, 0]);
+        // QTYPE MX and QCLASS IN follow the QNAME.
+        assert_eq!(&bytes[25..29], &[0, 15, 0, 1]);
+        assert_eq!(&bytes[bytes.len() - 2..], &[1, 171]);
+
+        let missing = json!({ "QNAME": "example.com", "answerRRs": [{ "NAME": "a", "TYPE": 1 }] });
+        assert!(message_from_json(&missing).is_err());
+    }
+
+    #[test]
+    fn test_too_long() {
+        let rdata = "00".repeat(65536);
+        let msg = json!({
+            "QNAME": "example.com.",
+            "answerRRs": [{ "NAME": "example.com.", "TYPE": 99, "RDATAHEX": rdata }]
+        });
+        let err = message_from_json(&msg).err().unwrap();
+        assert!(err.contains("too long"));
+
+        let rr = json!({ "NAME": "example.com.", "TYPE": 99, "RDATAHEX": "" });
+        let msg = json!({ "QNAME": "example.com.", "additionalRRs": vec![rr; 65536] });
+        let err = message_from_json(&msg).err().unwrap();
+        assert!(err.contains("additionalRRs"));
+    }
+
+    #[test]
+    fn test_message_octets() {
+        let msg = json!({ "ID": 1, "messageOctetsHEX": "00010100" });
+        assert_eq!(message_from_json(&msg).unwrap(), vec![0, 1, 1, 0]);
+    }
+}
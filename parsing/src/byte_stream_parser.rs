@@ -45,7 +45,7 @@ impl<'slice> ByteStream<'slice> {
 pub struct ByteStreamParser<'slice> {
     data: ByteStream<'slice>,
     stream: std::slice::Iter<'slice, u8>,
-    curr_offset: u8,
+    curr_offset: usize,
 }
 
 impl<'slice> ByteStreamParser<'slice> {
@@ -71,34 +71,35 @@ impl<'slice> ByteStreamParser<'slice> {
             self.reset_stream();
         }
         let mut res: Vec<u8> = vec![];
-        let mut taken: u8 = 0;
+        let mut taken: usize = 0;
         let mut cursor: u8;
-        while (taken as usize) < n {
+        while taken < n {
             cursor = *self.stream.next().unwrap();
             res.push(cursor);
             taken += 1;
+            self.curr_offset += 1;
         }
         Ok(res)
     }
 
-    pub fn set_stream_to_offset(&mut self, offset: u8) {
-        if offset > self.curr_offset && offset as usize <= self.stream.len() {
-            let n: u8 = offset - self.curr_offset;
-            let _ = self.pop_n_from_stream(n as usize).unwrap();
+    pub fn set_stream_to_offset(&mut self, offset: usize) {
+        if offset > self.curr_offset && offset <= self.stream.len() {
+            let n: usize = offset - self.curr_offset;
+            let _ = self.pop_n_from_stream(n).unwrap();
         } else {
             self.reset_stream();
-            let n: u8 = offset - self.curr_offset;
-            let _ = self.pop_n_from_stream(n as usize);
+            let n: usize = offset - self.curr_offset;
+            let _ = self.pop_n_from_stream(n);
         }
     }
 
-    pub fn take_stream_slice(&mut self, size: u8) -> Result<Vec<u8>, String> {
-        if size as usize > self.remaining_stream_len() {
+    pub fn take_stream_slice(&mut self, size: usize) -> Result<Vec<u8>, String> {
+        if size > self.remaining_stream_len() {
             return Err(
                 "Slice size is bigger than the remaining length of the stream.".to_string(),
             );
         }
-        let mut curr_size: u8 = 0;
+        let mut curr_size: usize = 0;
         let mut res: Vec<u8> = vec![];
         let mut cursor: u8;
         while curr_size < size {
@@ -114,6 +115,16 @@ impl<'slice> ByteStreamParser<'slice> {
         self.stream.len()
     }
 
+    fn next_byte(&mut self) -> Result<u8, String> {
+        match self.stream.next() {
+            Some(b) => {
+                self.curr_offset += 1;
+                Ok(*b)
+            }
+            None => Err("Stream ended unexpectedly.".to_string()),
+        }
+    }
+
     pub fn parse_dns_header(&mut self) -> Result<Header, String> {
         if self.curr_offset != 0 {
             self.reset_stream();
@@ -124,25 +135,25 @@ impl<'slice> ByteStreamParser<'slice> {
         }
     }
 
+    /// Parses the labels of a name up to the terminating zero length or a
+    /// compression pointer. The root name is a name without labels.
     pub fn parse_name(&mut self) -> Result<Name, String> {
+        if self.remaining_stream_len() == 0 {
+            return Err("Something went wrong parsing the labels.".to_string());
+        }
         let mut labels: Vec<Label> = vec![];
         let mut complet = false;
         let mut compressed: bool = false;
         let mut len: u8;
         while !complet {
-            if self.remaining_stream_len() == 0 {
-                complet = true;
-                continue;
-            }
-            len = *self.stream.next().unwrap();
-            self.curr_offset += 1;
+            len = self.next_byte()?;
             if len == 0 {
                 complet = true;
                 continue;
-            } else if len == 192 {
-                let offset = self.stream.next().unwrap();
-                self.curr_offset += 1;
-                labels.push(Label::new(len, true, *offset, vec![192, *offset]));
+            } else if Label::is_pointer(len) {
+                let low = self.next_byte()?;
+                let offset = Label::pointer_offset(len, low);
+                labels.push(Label::new(len, true, offset, vec![len, low]));
                 compressed = true;
                 complet = true;
                 continue;
@@ -150,63 +161,43 @@ impl<'slice> ByteStreamParser<'slice> {
                 let mut b: Vec<u8> = vec![];
                 let mut counter: u8 = 0;
                 while counter < len {
-                    b.push(*self.stream.next().unwrap());
+                    b.push(self.next_byte()?);
                     counter += 1;
-                    self.curr_offset += 1;
                 }
                 labels.push(Label::new(len, false, 0, b));
                 continue;
             }
         }
-        if labels.is_empty() {
-            return Err("Something went wrong parsing the labels.".to_string());
-        }
         let name = Name::new(labels, compressed);
         Ok(name)
     }
 
     pub fn take_row(&mut self) -> Result<[u8; 2], String> {
-        if self.remaining_stream_len() == 0 {
-            self.reset_stream();
+        if self.remaining_stream_len() < 2 {
+            return Err("Stream to short to take a row".to_string());
         }
-        let mut buf: [u8; 2] = [0, 0];
-        for n in 0..2 {
-            buf[n] = *self.stream.next().unwrap();
-            self.curr_offset += 1;
-        }
-        Ok(buf)
+        Ok([self.next_byte()?, self.next_byte()?])
     }
 
     pub fn parse_question(&mut self) -> Result<Question, String> {
-        let name = self.parse_name().unwrap();
-        let qtype = match self.take_row() {
-            Ok(b) => RecordType::from_bytes(b),
-            Err(err) => panic!("Failed to parse Question from byte stream: {}", err),
-        };
-        let qclass = match self.take_row() {
-            Ok(qc) => QClass::from_row(qc),
-            Err(err) => panic!("Failed to parse Question from byte stream: {}", err),
-        };
+        let name = self.parse_name()?;
+        let qtype = RecordType::from_code(self.take_row()?.as_u16());
+        let qclass = QClass::from_row(self.take_row()?);
         Ok(Question::init(name, qtype, qclass))
     }
 
     pub fn parse_answer(&mut self) -> Result<Answer, String> {
-        let mut name = match self.parse_name() {
-            Ok(n) => n,
-            Err(err) => panic!("Failed to parse Name in answer: {}", err),
-        };
+        let mut name = self.parse_name()?;
         if name.is_compressed() {
-            name = name.decompress(self.data.copy_bytes()).unwrap();
+            name = name.decompress(self.data.copy_bytes())?;
         }
-        let r_type = RecordType::from_bytes(self.take_row().unwrap());
-        let class = self.take_row().unwrap();
-        let mut ttl: [u8; 4] = [0, 0, 0, 0];
-        for n in 0..4 {
-            ttl[n] = *self.stream.next().unwrap();
-            self.curr_offset += 1;
-        }
-        let length: [u8; 2] = self.take_row().unwrap();
-        let a_data: Vec<u8> = self.pop_n_from_stream(length.as_u16() as usize).unwrap();
+        let r_type = RecordType::from_code(self.take_row()?.as_u16());
+        let class = self.take_row()?;
+        let ttl_high = self.take_row()?;
+        let ttl_low = self.take_row()?;
+        let ttl: [u8; 4] = [ttl_high[0], ttl_high[1], ttl_low[0], ttl_low[1]];
+        let length: [u8; 2] = self.take_row()?;
+        let a_data: Vec<u8> = self.pop_n_from_stream(length.as_u16() as usize)?;
         Ok(Answer::new(name, r_type, class, ttl, length, a_data))
     }
 
@@ -230,26 +221,46 @@ impl<'slice> ByteStreamParser<'slice> {
         if self.curr_offset != 0 {
             self.reset_stream();
         }
-        let header = self.parse_dns_header().unwrap();
-        let question = self.parse_question().unwrap();
-        let mut records: Vec<Answer> = vec![];
+        let header = self.parse_dns_header()?;
+        let mut questions: Vec<Question> = vec![];
+        for _ in 0..header.q_count() {
+            questions.push(self.parse_question()?);
+        }
+        let mut answers: Vec<Answer> = vec![];
         for _ in 0..header.an_count() {
-            records.push(self.parse_answer().unwrap());
+            answers.push(self.parse_answer()?);
         }
-        let mut ns_records: Vec<Answer> = vec![];
-        if header.ns_count() > 0 {
-            for _ in 0..header.ns_count() {
-                ns_records.push(self.parse_answer().unwrap());
-            }
+        let mut authority: Vec<Answer> = vec![];
+        for _ in 0..header.ns_count() {
+            authority.push(self.parse_answer()?);
+        }
+        let mut additional: Vec<Answer> = vec![];
+        for _ in 0..header.ar_count() {
+            additional.push(self.parse_answer()?);
+        }
+        // Record data is only read on demand, so bad data is found here.
+        let bytes = self.data.copy_bytes();
+        for rr in answers
+            .iter()
+            .chain(authority.iter())
+            .chain(additional.iter())
+        {
+            rr.validate(bytes.to_vec()).map_err(|err| {
+                format!(
+                    "Invalid {} data of {}: {}",
+                    rr.get_type().to_string(),
+                    rr.get_name().get_string().unwrap_or_default(),
+                    err
+                )
+            })?;
         }
-        let mut answers: Vec<Answer> = vec![];
-        answers.extend(records);
-        answers.extend(ns_records);
         Ok(Response::new(
             self.data.copy_bytes(),
             header,
-            question,
+            questions,
             answers,
+            authority,
+            additional,
         ))
     }
 }
@@ -257,8 +268,9 @@ impl<'slice> ByteStreamParser<'slice> {
 #[cfg(test)]
 mod tests {
     use dns::header::Header;
+    use dns::record::{NSRecord, RecordType};
 
-    use crate::Response;
+    use crate::{Edns, Query, Response};
 
     use super::ByteStreamParser;
 
@@ -368,4 +380,193 @@ mod tests {
         let response: Response = parser.parse_response().unwrap();
         response.print(true);
     }
+
+    #[test]
+    fn test_serialize_response() {
+        let data: Vec<u8> = vec![
+            0xDE, 0xAD, 0x81, 0x80, 0x00, 0x01, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x06, 0x67,
+            0x6F, 0x6F, 0x67, 0x6C, 0x65, 0x03, 0x63, 0x6F, 0x6D, 0x00, 0x00, 0x01, 0x00, 0x01,
+            192, 12, 0, 1, 0, 1, 0, 0, 0, 1, 0, 4, 1, 1, 1, 1, 192, 12, 0, 2, 0, 1, 0, 0, 0, 1, 0,
+            6, 3, 110, 115, 49, 192, 12,
+        ];
+        let response = ByteStreamParser::new(&data).parse_response().unwrap();
+        let json = serde_json::to_value(&response).unwrap();
+        assert_eq!(json["header"]["id"], 0xDEAD);
+        assert_eq!(json["header"]["flags"]["rd"], true);
+        assert_eq!(json["header"]["flags"]["rcode"], "NOERROR");
+        for flag in ["z", "ad", "cd"] {
+            assert_eq!(json["header"]["flags"][flag], false);
+        }
+        assert_eq!(json["question"]["name"], "google.com.");
+        assert_eq!(json["answer"][0]["type"], "A");
+        assert_eq!(json["answer"][0]["class"], "IN");
+        assert_eq!(json["answer"][0]["rdata"]["address"], "1.1.1.1");
+        // The compressed name of the NS record is written out in full.
+        assert_eq!(json["authority"][0]["rdata"]["nsdname"], "ns1.google.com.");
+        assert_eq!(json["additional"].as_array().unwrap().len(), 0);
+
+        // RA, Z, AD and CD share the second flags byte with the RCODE.
+        let mut data = data;
+        data[3] = 0xF0;
+        let response = ByteStreamParser::new(&data).parse_response().unwrap();
+        let json = serde_json::to_value(&response).unwrap();
+        for flag in ["ra", "z", "ad", "cd"] {
+            assert_eq!(json["header"]["flags"][flag], true);
+        }
+        assert_eq!(json["header"]["flags"]["rcode"], "NOERROR");
+    }
+
+    #[test]
+    fn test_rfc8427_round_trip() {
+        let data: Vec<u8> = vec![
+            0xDE, 0xAD, 0x81, 0x80, 0x00, 0x01, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x06, 0x67,
+            0x6F, 0x6F, 0x67, 0x6C, 0x65, 0x03, 0x63, 0x6F, 0x6D, 0x00, 0x00, 0x01, 0x00, 0x01,
+            192, 12, 0, 1, 0, 1, 0, 0, 0, 1, 0, 4, 1, 1, 1, 1, 192, 12, 0, 2, 0, 1, 0, 0, 0, 1, 0,
+            6, 3, 110, 115, 49, 192, 12,
+        ];
+        let response = ByteStreamParser::new(&data).parse_response().unwrap();
+        let mut json = response.to_rfc8427().unwrap();
+        assert_eq!(json["ID"], 0xDEAD);
+        assert_eq!(json["QR"], 1);
+        assert_eq!(json["QNAME"], "google.com.");
+        assert_eq!(json["answerRRs"][0]["rdataA"], "1.1.1.1");
+        assert_eq!(json["authorityRRs"][0]["rdataNS"], "ns1.google.com.");
+        assert_eq!(
+            json["authorityRRs"][0]["RDATAHEX"],
+            "036E733106676F6F676C6503636F6D00"
+        );
+        let copy = Response::from_rfc8427(&json).unwrap();
+        assert_eq!(copy.get_bytes(), data);
+
+        // Without the message octets it is rebuilt from the fields.
+        json.as_object_mut().unwrap().remove("messageOctetsHEX");
+        let rebuilt = Response::from_rfc8427(&json).unwrap();
+        assert_eq!(rebuilt.header().to_bytes(), response.header().to_bytes());
+        assert_eq!(rebuilt.answers()[0].get_data(), &vec![1, 1, 1, 1]);
+        let ns =
+            NSRecord::from_bytes(rebuilt.authority()[0].get_data().to_vec(), vec![], 0).unwrap();
+        assert_eq!(ns.get_nsdname().get_string().unwrap(), "ns1.google.com");
+    }
+
+    #[test]
+    fn test_parse_response_sections() {
+        let mut data: Vec<u8> = vec![
+            0xDE, 0xAD, 0x81, 0x80, 0x00, 0x01, 0x00, 0x01, 0x00, 0x01, 0x00, 0x01, 0x06, 0x67,
+            0x6F, 0x6F, 0x67, 0x6C, 0x65, 0x03, 0x63, 0x6F, 0x6D, 0x00, 0x00, 0x01, 0x00, 0x01,
+        ];
+        // answer of an unknown type, long enough to move the following records past offset 255
+        data.extend(vec![192, 12, 0, 99, 0, 1, 0, 0, 0, 1, 1, 44]);
+        data.extend(vec![0; 300]);
+        // authority NS record with target ns1.google.com starting at offset 352
+        data.extend(vec![
+            192, 12, 0, 2, 0, 1, 0, 0, 0, 1, 0, 6, 3, 110, 115, 49, 192, 12,
+        ]);
+        // additional glue for ns1.google.com, pointing to offset 352
+        data.extend(vec![0xC1, 0x60, 0, 1, 0, 1, 0, 0, 0, 1, 0, 4, 1, 2, 3, 4]);
+
+        let mut parser = ByteStreamParser::new(&data);
+        let response: Response = parser.parse_response().unwrap();
+        assert!(matches!(
+            response.answers()[0].get_type(),
+            RecordType::UNKNOWN(99)
+        ));
+        let ns = NSRecord::from_bytes(
+            response.authority()[0].get_data().to_vec(),
+            response.get_bytes(),
+            0,
+        )
+        .unwrap();
+        assert_eq!(ns.get_nsdname().get_string().unwrap(), "ns1.google.com");
+        assert_eq!(
+            response.additional()[0].get_name().get_string().unwrap(),
+            "ns1.google.com"
+        );
+        assert_eq!(parser.remaining_stream_len(), 0);
+    }
+
+    #[test]
+    fn test_parse_truncated_response() {
+        let data: Vec<u8> = vec![
+            0xDE, 0xAD, 0x81, 0x80, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x06, 0x67,
+            0x6F, 0x6F, 0x67, 0x6C, 0x65, 0x03, 0x63, 0x6F, 0x6D, 0x00, 0x00, 0x01, 0x00, 0x01,
+            192, 12, 0, 1, 0, 1, 0, 0, 0, 1, 0, 4, 1, 1,
+        ];
+        let mut parser = ByteStreamParser::new(&data);
+        assert!(parser.parse_response().is_err());
+    }
+
+    #[test]
+    fn test_parse_invalid_record_data() {
+        let header: Vec<u8> = vec![
+            0xDE, 0xAD, 0x81, 0x80, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x06, 0x67,
+            0x6F, 0x6F, 0x67, 0x6C, 0x65, 0x03, 0x63, 0x6F, 0x6D, 0x00, 0x00, 0x01, 0x00, 0x01,
+        ];
+        // NS target pointing past the end of the message
+        let mut data = header.to_vec();
+        data.extend(vec![192, 12, 0, 2, 0, 1, 0, 0, 0, 1, 0, 2, 0xC0, 0xFF]);
+        assert_eq!(
+            ByteStreamParser::new(&data).parse_response().err(),
+            Some(String::from(
+                "Invalid NS data of google.com: Cant decompress from out ouf bound offset."
+            ))
+        );
+        // A record with a 2 byte address
+        let mut data = header.to_vec();
+        data.extend(vec![192, 12, 0, 1, 0, 1, 0, 0, 0, 1, 0, 2, 1, 1]);
+        assert!(ByteStreamParser::new(&data).parse_response().is_err());
+    }
+
+    #[test]
+    fn test_parse_response_questions() {
+        let mut qry = Query::new("google.com".to_string(), RecordType::A, true);
+        qry.add_question("google.com".to_string(), RecordType::MX);
+        let bytes = qry.to_bytes();
+        assert_eq!(&bytes[4..6], &[0, 2]);
+        let response = ByteStreamParser::new(&bytes).parse_response().unwrap();
+        let types: Vec<u16> = response
+            .questions()
+            .iter()
+            .map(|q| q.get_type().code())
+            .collect();
+        assert_eq!(types, vec![1, 15]);
+        let json = response.to_rfc8427().unwrap();
+        assert!(json.get("QNAME").is_none());
+        assert_eq!(json["questionRRs"][1]["TYPEname"], "MX");
+        let mut json = json;
+        json.as_object_mut().unwrap().remove("messageOctetsHEX");
+        assert_eq!(Response::from_rfc8427(&json).unwrap().get_bytes(), bytes);
+
+        // A FORMERR response without a question.
+        let data: Vec<u8> = vec![0xDE, 0xAD, 0x81, 0x81, 0, 0, 0, 0, 0, 0, 0, 0];
+        let response = ByteStreamParser::new(&data).parse_response().unwrap();
+        assert!(response.question().is_none());
+        assert!(serde_json::to_value(&response).unwrap()["question"].is_null());
+    }
+
+    #[test]
+    fn test_query_with_edns() {
+        let edns = Edns {
+            udp_size: 4096,
+            dnssec_ok: true,
+            nsid: true,
+        };
+        let qry = Query::new("google.com".to_string(), RecordType::A, true).with_edns(Some(edns));
+        let bytes = qry.to_bytes();
+        assert_eq!(&bytes[10..12], &[0, 1]);
+        // Root name, type 41, UDP size, extended RCODE, version, DO bit, NSID.
+        assert_eq!(
+            &bytes[bytes.len() - 15..],
+            &[0, 0, 41, 16, 0, 0, 0, 128, 0, 0, 4, 0, 3, 0, 0]
+        );
+        let parsed = ByteStreamParser::new(&bytes).parse_response().unwrap();
+        let opt = &parsed.additional()[0];
+        assert_eq!(opt.get_type().code(), 41);
+        assert_eq!(opt.get_class(), 4096);
+
+        // Answers made up from the cache do not carry the OPT record.
+        let resp = Response::synthesize(&qry, vec![], false).unwrap();
+        assert!(resp.additional().is_empty());
+        let plain = Query::new("google.com".to_string(), RecordType::A, true).with_edns(None);
+        assert_eq!(plain.to_bytes().len(), bytes.len() - 15);
+    }
 }
@@ -0,0 +1,489 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use dns::{
+    answer::class_string,
+    header_flags::{OPCODE, RCODE},
+    record::RecordType,
+};
+use utility::to_hex;
+
+/// A range of a message and what it holds. Ranges without bytes start a
+/// part of the message, e.g. the first answer record. Notes of more than
+/// one line explain the range bit by bit, like the flags.
+pub struct Annotation {
+    pub offset: usize,
+    pub len: usize,
+    pub note: String,
+}
+
+/// Walks the message field by field, the same way a parser would. When
+/// the message is malformed the walk stops with a note on the error,
+/// covering the bytes which are left.
+pub fn annotate(bytes: &[u8]) -> Vec<Annotation> {
+    let mut walker = Walker {
+        bytes,
+        pos: 0,
+        notes: vec![],
+    };
+    if let Err(err) = walker.message() {
+        walker.notes.push(Annotation {
+            offset: walker.pos,
+            len: bytes.len() - walker.pos,
+            note: format!("Error: {}", err),
+        });
+    }
+    walker.notes
+}
+
+/// A hex dump of the message with the meaning of every range next to its
+/// bytes, eight bytes per line.
+pub fn explain(bytes: &[u8]) -> String {
+    let mut lines: Vec<String> = vec![];
+    for a in annotate(bytes) {
+        if a.len == 0 {
+            if !lines.is_empty() {
+                lines.push(String::new());
+            }
+            lines.push(format!("; {}", a.note));
+            continue;
+        }
+        let chunks: Vec<&[u8]> = bytes[a.offset..a.offset + a.len].chunks(8).collect();
+        let notes: Vec<&str> = a.note.lines().collect();
+        for row in 0..chunks.len().max(notes.len()) {
+            let (offset, hex) = match chunks.get(row) {
+                Some(chunk) => {
+                    let hex: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+                    (format!("{:04x}", a.offset + row * 8), hex.join(" "))
+                }
+                None => (String::new(), String::new()),
+            };
+            let note = notes.get(row).unwrap_or(&"");
+            lines.push(
+                format!("{:<4}  {:<23}  {}", offset, hex, note)
+                    .trim_end()
+                    .to_string(),
+            );
+        }
+    }
+    lines.join("\n")
+}
+
+/// The type of the EDNS pseudo record of RFC 6891.
+const OPT: u16 = 41;
+
+struct Walker<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    notes: Vec<Annotation>,
+}
+
+impl<'a> Walker<'a> {
+    fn peek(&self, len: usize) -> Result<&'a [u8], String> {
+        self.bytes.get(self.pos..self.pos + len).ok_or_else(|| {
+            format!(
+                "The message ends after {} bytes, in the middle of a field of {} bytes.",
+                self.bytes.len(),
+                len
+            )
+        })
+    }
+
+    fn field(&mut self, len: usize, note: String) -> Result<&'a [u8], String> {
+        let bytes = self.peek(len)?;
+        self.notes.push(Annotation {
+            offset: self.pos,
+            len,
+            note,
+        });
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn heading(&mut self, note: String) {
+        self.notes.push(Annotation {
+            offset: self.pos,
+            len: 0,
+            note,
+        });
+    }
+
+    fn u16(&self) -> Result<u16, String> {
+        let bytes = self.peek(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&self) -> Result<u32, String> {
+        let bytes = self.peek(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn message(&mut self) -> Result<(), String> {
+        self.heading("Header".to_string());
+        let id = self.u16()?;
+        self.field(2, format!("ID: {}", id))?;
+        let flags = self.u16()?;
+        self.field(2, flags_note(flags))?;
+        let mut counts = vec![];
+        for (name, what) in [
+            ("QDCOUNT", "questions"),
+            ("ANCOUNT", "answer records"),
+            ("NSCOUNT", "authority records"),
+            ("ARCOUNT", "additional records"),
+        ] {
+            let count = self.u16()?;
+            self.field(2, format!("{}: {} {}", name, count, what))?;
+            counts.push(count);
+        }
+        for n in 1..=counts[0] {
+            self.heading(format!("Question {}", n));
+            self.name("QNAME")?;
+            let r_type = self.u16()?;
+            self.field(2, format!("QTYPE: {}", type_string(r_type)))?;
+            let class = self.u16()?;
+            self.field(2, format!("QCLASS: {} ({})", class, class_string(class)))?;
+        }
+        for (section, count) in ["Answer", "Authority", "Additional"]
+            .iter()
+            .zip(counts[1..].iter())
+        {
+            for n in 1..=*count {
+                self.heading(format!("{} record {}", section, n));
+                self.record()?;
+            }
+        }
+        if self.pos < self.bytes.len() {
+            let rest = self.bytes.len() - self.pos;
+            self.field(rest, format!("{} bytes after the last record", rest))?;
+        }
+        Ok(())
+    }
+
+    /// The labels of a name up to the root label or a compression pointer.
+    fn name(&mut self, what: &str) -> Result<(), String> {
+        loop {
+            let len = self.peek(1)?[0];
+            match len >> 6 {
+                0b11 => {
+                    let target = self.u16()? & 0x3fff;
+                    let name = name_at(self.bytes, target as usize)?;
+                    self.field(
+                        2,
+                        format!("{} pointer to offset {}: {}", what, target, name),
+                    )?;
+                    return Ok(());
+                }
+                0b00 if len == 0 => {
+                    self.field(1, format!("{} root label, end of the name", what))?;
+                    return Ok(());
+                }
+                0b00 => {
+                    let label = self.peek(1 + len as usize)?;
+                    let text = String::from_utf8_lossy(&label[1..]).to_string();
+                    self.field(
+                        1 + len as usize,
+                        format!("{} label of {} bytes: {}", what, len, text),
+                    )?;
+                }
+                _ => {
+                    return Err(format!(
+                        "Unknown label type {:#04x} at offset {}.",
+                        len, self.pos
+                    ))
+                }
+            }
+        }
+    }
+
+    fn record(&mut self) -> Result<(), String> {
+        self.name("NAME")?;
+        let r_type = self.u16()?;
+        self.field(2, format!("TYPE: {}", type_string(r_type)))?;
+        let class = self.u16()?;
+        if r_type == OPT {
+            self.field(2, format!("UDP payload size: {}", class))?;
+        } else {
+            self.field(2, format!("CLASS: {} ({})", class, class_string(class)))?;
+        }
+        let ttl = self.u32()?;
+        if r_type == OPT {
+            self.field(
+                4,
+                format!(
+                    "Extended RCODE: {}, EDNS version: {}, DO: {}",
+                    ttl >> 24,
+                    (ttl >> 16) & 0xff,
+                    (ttl >> 15) & 1
+                ),
+            )?;
+        } else {
+            self.field(4, format!("TTL: {} seconds", ttl))?;
+        }
+        let len = self.u16()? as usize;
+        self.field(2, format!("RDLENGTH: {}", len))?;
+        let end = self.pos + len;
+        self.peek(len)?;
+        self.rdata(r_type, len)?;
+        if self.pos > end {
+            return Err(format!(
+                "The record data is longer than its RDLENGTH of {}.",
+                len
+            ));
+        }
+        if self.pos < end {
+            self.field(end - self.pos, "Rest of the record data".to_string())?;
+        }
+        Ok(())
+    }
+
+    fn rdata(&mut self, r_type: u16, len: usize) -> Result<(), String> {
+        match (RecordType::from_code(r_type), len) {
+            (RecordType::A, 4) => {
+                let data = self.peek(4)?;
+                let addr = Ipv4Addr::new(data[0], data[1], data[2], data[3]);
+                self.field(4, format!("Address: {}", addr))?;
+            }
+            (RecordType::AAAA, 16) => {
+                let mut data = [0u8; 16];
+                data.copy_from_slice(self.peek(16)?);
+                self.field(16, format!("Address: {}", Ipv6Addr::from(data)))?;
+            }
+            (RecordType::CNAME, _) => self.name("CNAME")?,
+            (RecordType::NS, _) => self.name("NSDNAME")?,
+            (RecordType::PTR, _) => self.name("PTRDNAME")?,
+            (RecordType::DNAME, _) => self.name("DNAME")?,
+            (RecordType::MX, _) => {
+                let preference = self.u16()?;
+                self.field(2, format!("PREFERENCE: {}", preference))?;
+                self.name("EXCHANGE")?;
+            }
+            (RecordType::SOA, _) => {
+                self.name("MNAME")?;
+                self.name("RNAME")?;
+                for field in ["SERIAL", "REFRESH", "RETRY", "EXPIRE", "MINIMUM"] {
+                    let value = self.u32()?;
+                    self.field(4, format!("{}: {}", field, value))?;
+                }
+            }
+            (RecordType::UNKNOWN(OPT), _) => {
+                let end = self.pos + len;
+                while self.pos + 4 <= end {
+                    let code = self.u16()?;
+                    self.field(2, format!("Option code: {}", option_string(code)))?;
+                    let option_len = self.u16()? as usize;
+                    self.field(2, format!("Option length: {}", option_len))?;
+                    if option_len > 0 {
+                        self.field(option_len, "Option data".to_string())?;
+                    }
+                }
+            }
+            (_, 0) => {}
+            (_, _) => {
+                let data = self.peek(len)?;
+                self.field(len, format!("RDATA: {}", to_hex(data)))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The value of the flags followed by one line for every field, with the
+/// bits of the field marked in the 16 bits of the flags.
+fn flags_note(flags: u16) -> String {
+    let [high, low] = flags.to_be_bytes();
+    let fields: [(u32, u32, &str, String); 10] = [
+        (15, 1, "QR", bit_meaning(flags, 15, "response", "query")),
+        (
+            11,
+            4,
+            "Opcode",
+            OPCODE::from_byte(high).mnemonic().to_string(),
+        ),
+        (10, 1, "AA", "authoritative answer".to_string()),
+        (9, 1, "TC", "truncated".to_string()),
+        (8, 1, "RD", "recursion desired".to_string()),
+        (7, 1, "RA", "recursion available".to_string()),
+        (6, 1, "Z", "reserved".to_string()),
+        (5, 1, "AD", "authentic data".to_string()),
+        (4, 1, "CD", "checking disabled".to_string()),
+        (0, 4, "RCODE", RCODE::from_byte(low).mnemonic().to_string()),
+    ];
+    let mut lines = vec![format!("Flags: {:#06x}", flags)];
+    for (shift, width, name, meaning) in fields {
+        let value = (flags >> shift) & ((1 << width) - 1);
+        lines.push(format!(
+            "{} {}: {} ({})",
+            bit_pattern(flags, shift, width),
+            name,
+            value,
+            meaning
+        ));
+    }
+    lines.join("\n")
+}
+
+fn bit_meaning(flags: u16, bit: u32, set: &str, unset: &str) -> String {
+    if flags >> bit & 1 == 1 {
+        set.to_string()
+    } else {
+        unset.to_string()
+    }
+}
+
+/// The 16 bits in groups of four, with the bits outside of the field as
+/// dots, e.g. `.000 0... .... ....` for the opcode of a query.
+fn bit_pattern(flags: u16, shift: u32, width: u32) -> String {
+    let mut pattern = String::new();
+    for bit in (0..16).rev() {
+        if bit >= shift && bit < shift + width {
+            pattern.push(if flags >> bit & 1 == 1 { '1' } else { '0' });
+        } else {
+            pattern.push('.');
+        }
+        if bit % 4 == 0 && bit > 0 {
+            pattern.push(' ');
+        }
+    }
+    pattern
+}
+
+fn type_string(code: u16) -> String {
+    match code {
+        OPT => format!("{} (OPT)", code),
+        _ => format!("{} ({})", code, RecordType::from_code(code).to_string()),
+    }
+}
+
+fn option_string(code: u16) -> String {
+    let name = match code {
+        3 => "NSID",
+        8 => "Client Subnet",
+        10 => "COOKIE",
+        12 => "Padding",
+        _ => "unknown",
+    };
+    format!("{} ({})", code, name)
+}
+
+/// The name at `offset` as text. Every compression pointer has to point
+/// before the target of the previous one, so a loop of pointers can not
+/// hang the walk, and the name may not be longer than 255 bytes.
+fn name_at(bytes: &[u8], offset: usize) -> Result<String, String> {
+    let mut labels: Vec<String> = vec![];
+    let mut pos = offset;
+    let mut limit = offset;
+    // The root label ends every name.
+    let mut length = 1;
+    loop {
+        let len = *bytes
+            .get(pos)
+            .ok_or_else(|| format!("Pointer to offset {} outside of the message.", pos))?;
+        match len >> 6 {
+            0b11 => {
+                let target = (u16::from_be_bytes([len, *bytes.get(pos + 1).unwrap_or(&0)]) & 0x3fff)
+                    as usize;
+                if target >= limit {
+                    return Err(format!("Pointer at offset {} does not point back.", pos));
+                }
+                pos = target;
+                limit = target;
+            }
+            0b00 if len == 0 => break,
+            0b00 => {
+                let label = bytes
+                    .get(pos + 1..pos + 1 + len as usize)
+                    .ok_or_else(|| format!("Label at offset {} ends after the message.", pos))?;
+                length += 1 + len as usize;
+                if length > 255 {
+                    return Err(format!(
+                        "Name at offset {} is longer than 255 bytes.",
+                        offset
+                    ));
+                }
+                labels.push(String::from_utf8_lossy(label).to_string());
+                pos += 1 + len as usize;
+            }
+            _ => {
+                return Err(format!(
+                    "Unknown label type {:#04x} at offset {}.",
+                    len, pos
+                ))
+            }
+        }
+    }
+    Ok(format!("{}.", labels.join(".")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_annotate_response() {
+        let data: Vec<u8> = vec![
+            0xDE, 0xAD, 0x81, 0x80, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x06, 0x67,
+            0x6F, 0x6F, 0x67, 0x6C, 0x65, 0x03, 0x63, 0x6F, 0x6D, 0x00, 0x00, 0x01, 0x00, 0x01,
+            192, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 1, 1, 1, 1,
+        ];
+        let notes: Vec<String> = annotate(&data).into_iter().map(|a| a.note).collect();
+        assert_eq!(notes[1], "ID: 57005");
+        assert!(notes[2].starts_with("Flags: 0x8180\n1... .... .... .... QR: 1 (response)"));
+        assert!(notes[2].contains(".... .... .... 0000 RCODE: 0 (NOERROR)"));
+        assert!(notes.contains(&"QNAME label of 6 bytes: google".to_string()));
+        assert!(notes.contains(&"QNAME root label, end of the name".to_string()));
+        assert!(notes.contains(&"NAME pointer to offset 12: google.com.".to_string()));
+        assert!(notes.contains(&"TTL: 60 seconds".to_string()));
+        assert_eq!(notes.last().unwrap(), "Address: 1.1.1.1");
+
+        // The TYPE of the answer is cut in half.
+        let truncated = annotate(&data[..31]);
+        let last = truncated.last().unwrap();
+        assert!(last
+            .note
+            .starts_with("Error: The message ends after 31 bytes"));
+        assert_eq!(last.offset, 30);
+        assert_eq!(last.len, 1);
+    }
+
+    #[test]
+    fn test_explain_layout() {
+        let dump = explain(&[0xc0, 0xaf, 0x01, 0x00]);
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(lines[0], "; Header");
+        assert_eq!(lines[1], "0000  c0 af                    ID: 49327");
+        assert_eq!(lines[2], "0002  01 00                    Flags: 0x0100");
+        assert_eq!(
+            lines[3],
+            format!("{:31}0... .... .... .... QR: 0 (query)", "")
+        );
+        assert!(lines
+            .last()
+            .unwrap()
+            .starts_with("; Error: The message ends"));
+    }
+
+    #[test]
+    fn test_name_at_bounds() {
+        // The QNAME runs over the records up to a pointer back to itself.
+        let data: Vec<u8> = vec![
+            0xDE, 0xAD, 0x81, 0x80, 0x00, 0x01, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x06, 0x67,
+            0x6F, 0x6F, 0x67, 0x6C, 0x65, 0x24, 0x63, 0x6F, 0x6D, 0x00, 0x00, 0x01, 0x20, 0x01,
+            0xC0, 0x0C, 0x00, 0x01, 0xC9, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x04, 0x01, 0x01,
+            0x17, 0x01, 0xC0, 0x0C, 0x00, 0x02, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x06,
+            0x03, 0x6E, 0x73, 0x31, 0xC0, 0x0C,
+        ];
+        assert!(name_at(&data, 12).is_err());
+        assert!(explain(&data).contains("Error: Pointer at offset 60 does not point back."));
+
+        let mut long = vec![0; 12];
+        for _ in 0..5 {
+            long.push(63);
+            long.extend([b'a'; 63]);
+        }
+        long.push(0);
+        assert_eq!(
+            name_at(&long, 12).unwrap_err(),
+            "Name at offset 12 is longer than 255 bytes."
+        );
+        assert_eq!(name_at(&long, 12 + 64 * 2).unwrap().len(), 3 * 64);
+    }
+}
@@ -1,26 +1,107 @@
 use dns::answer::Answer;
 use dns::header::{self, Header};
+use dns::name::Name;
 use dns::question::Question;
 use dns::record::RecordType;
+use dns::rfc8427::{message_from_json, message_to_json};
+use serde::{
+    ser::{Error, SerializeStruct},
+    Serialize, Serializer,
+};
 
 pub mod byte_stream_parser;
+pub mod explain;
+
+use byte_stream_parser::ByteStreamParser;
+
+/// The EDNS(0) options of a query, sent in an OPT record (RFC 6891).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Edns {
+    /// The largest UDP response the client accepts.
+    pub udp_size: u16,
+    /// Asks for DNSSEC records with the DO bit.
+    pub dnssec_ok: bool,
+    /// Asks the server for its name server identifier (RFC 5001).
+    pub nsid: bool,
+}
+
+impl Default for Edns {
+    fn default() -> Self {
+        Self {
+            udp_size: 1232,
+            dnssec_ok: false,
+            nsid: false,
+        }
+    }
+}
+
+impl Edns {
+    /// The OPT pseudo record: the class holds the UDP size, the TTL the
+    /// extended RCODE, the version and the flags.
+    pub fn to_answer(&self) -> Answer {
+        let flags: u16 = if self.dnssec_ok { 0x8000 } else { 0 };
+        let [high, low] = flags.to_be_bytes();
+        let data: Vec<u8> = if self.nsid { vec![0, 3, 0, 0] } else { vec![] };
+        Answer::new(
+            Name::new(vec![], false),
+            RecordType::UNKNOWN(41),
+            self.udp_size.to_be_bytes(),
+            [0, 0, high, low],
+            (data.len() as u16).to_be_bytes(),
+            data,
+        )
+    }
+}
 
 pub struct Query {
     header: header::Header,
-    question: Question,
+    questions: Vec<Question>,
+    edns: Option<Edns>,
 }
 
 impl Query {
     pub fn new(domain: String, r_type: RecordType, rd: bool) -> Self {
         let header = Header::new_query(Some(rd));
         let question = Question::new(domain, Some(r_type), None);
-        Self { header, question }
+        Self {
+            header,
+            questions: vec![question],
+            edns: None,
+        }
+    }
+
+    /// Adds an OPT record with `edns` to the query, `None` removes it.
+    pub fn with_edns(mut self, edns: Option<Edns>) -> Self {
+        self.header.set_ar_count(edns.is_some() as u16);
+        self.edns = edns;
+        self
+    }
+
+    /// Adds another question to the query. Most servers only answer
+    /// queries with a single question, others are refused with FORMERR.
+    pub fn add_question(&mut self, domain: String, r_type: RecordType) {
+        self.questions
+            .push(Question::new(domain, Some(r_type), None));
+        self.header.set_q_count(self.questions.len() as u16);
+    }
+
+    pub fn questions(&self) -> &Vec<Question> {
+        &self.questions
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
     }
 
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut res: Vec<u8> = vec![];
         res.extend(self.header.to_bytes());
-        res.extend(self.question.to_bytes());
+        for q in self.questions.iter() {
+            res.extend(q.to_bytes());
+        }
+        if let Some(edns) = &self.edns {
+            res.extend(edns.to_answer().to_bytes());
+        }
 
         res
     }
@@ -28,7 +109,9 @@ impl Query {
     pub fn print(&self, verbose: bool) {
         if verbose {
             println!("{}", self.header);
-            self.question.print();
+            for q in self.questions.iter() {
+                q.print();
+            }
         }
     }
 }
@@ -36,34 +119,181 @@ impl Query {
 pub struct Response {
     bytes: Vec<u8>,
     header: dns::header::Header,
-    question: dns::question::Question,
+    questions: Vec<dns::question::Question>,
     answers: Vec<dns::answer::Answer>,
+    authority: Vec<dns::answer::Answer>,
+    additional: Vec<dns::answer::Answer>,
 }
 
 impl Response {
-    pub fn new(bytes: Vec<u8>, header: Header, question: Question, answers: Vec<Answer>) -> Self {
+    pub fn new(
+        bytes: Vec<u8>,
+        header: Header,
+        questions: Vec<Question>,
+        answers: Vec<Answer>,
+        authority: Vec<Answer>,
+        additional: Vec<Answer>,
+    ) -> Self {
         Self {
             bytes,
             header,
-            question,
+            questions,
             answers,
+            authority,
+            additional,
         }
     }
 
+    /// Builds the response to `query` for answers which did not come from
+    /// a name server, e.g. from the hosts file or the cache. Answers from
+    /// local data are marked as `authoritative`.
+    pub fn synthesize(
+        query: &Query,
+        answers: Vec<Answer>,
+        authoritative: bool,
+    ) -> Result<Response, String> {
+        Self::synthesize_with(query, 0, answers, vec![], authoritative)
+    }
+
+    /// Like [`Response::synthesize`], with an RCODE and authority records,
+    /// e.g. the SOA of a negative answer.
+    pub fn synthesize_with(
+        query: &Query,
+        rcode: u8,
+        answers: Vec<Answer>,
+        authority: Vec<Answer>,
+        authoritative: bool,
+    ) -> Result<Response, String> {
+        let mut header = query.header().clone();
+        header.flags_mut().set_response();
+        if authoritative {
+            header.flags_mut().set_aa();
+        }
+        header.flags_mut().set_recursion_available();
+        header.flags_mut().set_rcode(rcode);
+        header.set_an_count(answers.len() as u16);
+        header.set_ns_count(authority.len() as u16);
+        header.set_ar_count(0);
+        let mut bytes = header.to_bytes();
+        for q in query.questions.iter() {
+            bytes.extend(q.to_bytes());
+        }
+        for an in answers.iter().chain(authority.iter()) {
+            bytes.extend(an.to_bytes());
+        }
+        ByteStreamParser::new(&bytes).parse_response()
+    }
+
+    /// The response with only the answer records `keep` accepts. The
+    /// records are written out without compression pointers, as their
+    /// offsets change.
+    pub fn retain_answers<F>(&self, keep: F) -> Result<Response, String>
+    where
+        F: Fn(&Answer) -> bool,
+    {
+        let src = self.get_bytes();
+        let answers: Vec<&Answer> = self.answers.iter().filter(|an| keep(an)).collect();
+        let mut header = self.header.clone();
+        header.set_an_count(answers.len() as u16);
+        let mut bytes = header.to_bytes();
+        for q in self.questions.iter() {
+            bytes.extend(q.to_bytes());
+        }
+        for rr in answers
+            .into_iter()
+            .chain(self.authority.iter())
+            .chain(self.additional.iter())
+        {
+            bytes.extend(rr.expand(src.to_vec())?.to_bytes());
+        }
+        ByteStreamParser::new(&bytes).parse_response()
+    }
+
     pub fn get_bytes(&self) -> Vec<u8> {
         self.bytes.to_vec()
     }
 
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// The first question, responses without one (e.g. some FORMERR
+    /// responses) have none.
+    pub fn question(&self) -> Option<&Question> {
+        self.questions.first()
+    }
+
+    pub fn questions(&self) -> &Vec<Question> {
+        &self.questions
+    }
+
+    pub fn answers(&self) -> &Vec<Answer> {
+        &self.answers
+    }
+
+    pub fn authority(&self) -> &Vec<Answer> {
+        &self.authority
+    }
+
+    pub fn additional(&self) -> &Vec<Answer> {
+        &self.additional
+    }
+
+    /// The response in the JSON format of RFC 8427.
+    pub fn to_rfc8427(&self) -> Result<serde_json::Value, String> {
+        message_to_json(
+            &self.bytes,
+            &self.questions,
+            [&self.answers, &self.authority, &self.additional],
+        )
+    }
+
+    /// Reads a response from its RFC 8427 form, e.g. one written by
+    /// [`Response::to_rfc8427`].
+    pub fn from_rfc8427(json: &serde_json::Value) -> Result<Response, String> {
+        let bytes = message_from_json(json)?;
+        ByteStreamParser::new(&bytes).parse_response()
+    }
+
     pub fn print(&self, verbose: bool) {
         if verbose {
             println!("{}", self.header)
         }
-        self.question.print();
+        for q in self.questions.iter() {
+            q.print();
+        }
         println!("Answer Records: {}", self.header.an_count());
         println!("NS Records: {}", self.header.ns_count());
         println!("Additional Records: {}", self.header.ar_count());
-        for an in self.answers.to_vec().iter() {
+        for an in self.answers.iter() {
             an.print(self.get_bytes());
         }
+        for ns in self.authority.iter() {
+            ns.print(self.get_bytes());
+        }
+        for ar in self.additional.iter() {
+            ar.print(self.get_bytes());
+        }
+    }
+}
+
+/// The header, the question and every section with the names in the
+/// record data written out in full. `question` is the first question, or
+/// null without any, so the schema is the same for every response.
+impl Serialize for Response {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let expand = |records: &Vec<Answer>| -> Result<Vec<Answer>, S::Error> {
+            records
+                .iter()
+                .map(|rr| rr.expand(self.get_bytes()).map_err(S::Error::custom))
+                .collect()
+        };
+        let mut s = serializer.serialize_struct("Response", 5)?;
+        s.serialize_field("header", &self.header)?;
+        s.serialize_field("question", &self.question())?;
+        s.serialize_field("answer", &expand(&self.answers)?)?;
+        s.serialize_field("authority", &expand(&self.authority)?)?;
+        s.serialize_field("additional", &expand(&self.additional)?)?;
+        s.end()
     }
 }
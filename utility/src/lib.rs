@@ -128,11 +128,46 @@ impl Blob for Vec<u8> {
     }
 }
 
+/// Lowercase hex digits of `bytes`, two per byte.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Bytes of a string of hex digits in either case. Whitespace between the
+/// digits is ignored.
+pub fn from_hex(hex: &str) -> Result<Vec<u8>, &'static str> {
+    let digits: Vec<u8> = hex.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    if digits.len() % 2 == 1 {
+        return Err("Hex string has an odd number of digits.");
+    }
+    let value = |digit: u8| -> Result<u8, &'static str> {
+        match digit {
+            b'0'..=b'9' => Ok(digit - b'0'),
+            b'a'..=b'f' => Ok(digit - b'a' + 10),
+            b'A'..=b'F' => Ok(digit - b'A' + 10),
+            _ => Err("Hex string contains a character which is not a hex digit."),
+        }
+    };
+    digits
+        .chunks(2)
+        .map(|pair| Ok(value(pair[0])? << 4 | value(pair[1])?))
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use crate::Blob;
     use crate::Byte;
     use crate::Row;
+    use crate::{from_hex, to_hex};
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(to_hex(&[0, 171, 255]), "00abff");
+        assert_eq!(from_hex("00AB ff").unwrap(), vec![0, 171, 255]);
+        assert!(from_hex("abc").is_err());
+        assert!(from_hex("zz").is_err());
+    }
 
     #[test]
     fn test_slicing() {